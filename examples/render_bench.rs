@@ -0,0 +1,144 @@
+//! Times each rasterizer path on the real game assets and checks that they all
+//! produce the same pixels as the scalar path.
+//!
+//! cargo run --release --example render_bench
+
+// The game modules are pulled in whole, but only the renderer is exercised
+#![allow(dead_code)]
+
+#[path = "../src/asset.rs"]
+mod asset;
+#[path = "../src/common.rs"]
+mod common;
+#[path = "../src/font.rs"]
+mod font;
+#[path = "../src/game_error.rs"]
+mod game_error;
+#[path = "../src/rasterizer.rs"]
+mod rasterizer;
+#[path = "../src/render_group.rs"]
+mod render_group;
+
+use asset::*;
+use common::*;
+use font::*;
+use rasterizer::*;
+use render_group::*;
+use std::time::Instant;
+
+const WIDTH: i32 = 960;
+const HEIGHT: i32 = 540;
+const FRAMES: u32 = 100;
+
+fn push_backdrop<'a>(render_group: &mut RenderGroup<'a>, backdrop: &'a LoadedBitmap) {
+    push_bitmap(render_group, 0.0, backdrop, 0.0, 0.0, 1.0);
+}
+
+/// A grid of walking skeletons at a few zoom levels, including some hanging
+/// off the edges of the buffer to exercise clipping
+fn push_skeletons<'a>(render_group: &mut RenderGroup<'a>, skeleton: &'a LoadedBitmap) {
+    let frame_width = 22;
+    let frames = skeleton.width / frame_width;
+    let mut index = 0;
+    for row in -1..12 {
+        for column in -1..24 {
+            let scale = [0.5, 1.0, 1.5, 2.0, 3.0][index % 5];
+            push_sprite(
+                render_group,
+                1.0,
+                skeleton,
+                column as f32 * 41.3,
+                row as f32 * 47.7,
+                10,
+                33,
+                frame_width,
+                index as u32 % frames,
+                scale,
+            );
+            index += 1;
+        }
+    }
+}
+
+/// Lines of text at a few scales, to exercise sampling glyphs out of the
+/// middle of an atlas
+fn push_lines_of_text<'a>(render_group: &mut RenderGroup<'a>, font: &'a LoadedFont) {
+    let line = "The quick brown fox jumps over the lazy dog 0123456789 {}[]<>?!";
+    let mut y = -4.0;
+    for (index, &scale) in [1.0, 2.0, 3.0].iter().cycle().take(24).enumerate() {
+        push_text(render_group, 1.0, font, -3.0 + index as f32, y, scale, line);
+        y += scale * font.line_height as f32;
+    }
+}
+
+fn bench<'a, F>(name: &str, push: F)
+where
+    F: Fn(&mut RenderGroup<'a>),
+{
+    let mut reference: Option<(f64, Vec<u32>)> = None;
+
+    for &rasterizer in &[Rasterizer::Scalar, Rasterizer::Sse2, Rasterizer::Avx2] {
+        if !is_rasterizer_supported(rasterizer) {
+            println!("{:>10} {:?}: not supported on this CPU", name, rasterizer);
+            continue;
+        }
+
+        let mut pixels = vec![0x0040_4040u32; (WIDTH * HEIGHT) as usize];
+        let buffer = GameOffscreenBuffer {
+            memory: pixels.as_mut_ptr() as *mut _,
+            width: WIDTH,
+            height: HEIGHT,
+            pitch: WIDTH * 4,
+            bytes_per_pixel: 4,
+        };
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            let mut render_group = RenderGroup::default();
+            push_clear(&mut render_group, 0.25, 0.25, 0.25);
+            push(&mut render_group);
+            render_group_to_output_using(&mut render_group, &buffer, rasterizer);
+        }
+        let ms_per_frame = 1000.0 * start.elapsed().as_secs_f64() / FRAMES as f64;
+
+        match &reference {
+            None => {
+                println!("{:>10} {:?}: {:.3}ms/f", name, rasterizer, ms_per_frame);
+                reference = Some((ms_per_frame, pixels));
+            }
+            Some((scalar_ms_per_frame, scalar_pixels)) => {
+                let mismatches = pixels
+                    .iter()
+                    .zip(scalar_pixels.iter())
+                    .filter(|(a, b)| a != b)
+                    .count();
+                println!(
+                    "{:>10} {:?}: {:.3}ms/f, {:.2}x scalar, {} pixels differ",
+                    name,
+                    rasterizer,
+                    ms_per_frame,
+                    scalar_ms_per_frame / ms_per_frame,
+                    mismatches
+                );
+                assert_eq!(mismatches, 0, "{:?} does not match scalar", rasterizer);
+            }
+        }
+    }
+}
+
+fn main() {
+    let backdrop = load_bitmap("data/assets/Bricks.png").expect("could not load background");
+    let skeleton =
+        load_bitmap("data/assets/Skeleton Walk.png").expect("could not load skeleton walk png");
+    let font = load_font("data/assets/Font.fnt").expect("could not load font");
+
+    bench("backdrop", |render_group| {
+        push_backdrop(render_group, &backdrop)
+    });
+    bench("skeletons", |render_group| {
+        push_skeletons(render_group, &skeleton)
+    });
+    bench("text", |render_group| {
+        push_lines_of_text(render_group, &font)
+    });
+}
@@ -0,0 +1,567 @@
+//! equivalent to handmade_asset.h & handmade_asset.cpp
+
+use crate::{common::*, render_group::*};
+use image::ImageResult;
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+};
+
+/// A bitmap ready for the renderer. Pixels are stored top-down as 0xAARRGGBB
+/// (BGRA in memory), with the color channels premultiplied by alpha in linear
+/// space and then converted back to sRGB.
+pub struct LoadedBitmap {
+    pub width: u32,
+    pub height: u32,
+    pub memory: Vec<u32>,
+}
+
+pub fn load_bitmap(file_name: &str) -> ImageResult<LoadedBitmap> {
+    let image = image::open(file_name)?.to_rgba();
+    let (width, height) = image.dimensions();
+
+    let memory = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            let alpha = a as f32 / 255.0;
+
+            let r = linear1_to_srgb255(alpha * srgb255_to_linear1(r as f32));
+            let g = linear1_to_srgb255(alpha * srgb255_to_linear1(g as f32));
+            let b = linear1_to_srgb255(alpha * srgb255_to_linear1(b as f32));
+
+            (a as u32) << 24 | ((r + 0.5) as u32) << 16 | ((g + 0.5) as u32) << 8 | (b + 0.5) as u32
+        })
+        .collect();
+
+    Ok(LoadedBitmap {
+        width,
+        height,
+        memory,
+    })
+}
+
+/// Decoded 16-bit PCM at the rate it was authored at. Mono sounds only fill
+/// the first channel.
+#[derive(Default)]
+pub struct LoadedSound {
+    pub samples_per_second: u32,
+    pub sample_count: u32,
+    pub channel_count: u32,
+    pub samples: [Vec<i16>; 2],
+}
+
+/// Room for the data directory and a file name in the assets directory
+pub const MAX_ASSET_PATH_LENGTH: usize = MAX_DATA_DIRECTORY_LENGTH + 64;
+
+/// A WAV file that is read a chunk at a time through the platform layer, for
+/// music that is too long to keep in memory all at once. It is only where the
+/// samples are in the file, the platform layer keeps the file open.
+#[derive(Clone, Copy)]
+pub struct WavStream {
+    /// NUL padded, read it with `get_name`
+    file_name: [u8; MAX_ASSET_PATH_LENGTH],
+    pub samples_per_second: u32,
+    pub channel_count: u32,
+    data_offset: u64,
+    sample_count: u32,
+    next_sample: u32,
+}
+
+/// What the RIFF chunks before the sample data say about it
+struct WavFormat {
+    samples_per_second: u32,
+    channel_count: u32,
+    data_offset: u64,
+    sample_count: u32,
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// KSDATAFORMAT_SUBTYPE_PCM, which a `WAVE_FORMAT_EXTENSIBLE` file ends its fmt
+/// chunk with if the samples are PCM
+const PCM_SUBFORMAT: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+fn invalid_wav(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+pub fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_tag(reader: &mut impl Read) -> io::Result<[u8; 4]> {
+    let mut tag = [0; 4];
+    reader.read_exact(&mut tag)?;
+    Ok(tag)
+}
+
+/// Walks the RIFF chunks up to the start of the sample data without decoding
+/// any of it. Only 16-bit PCM with one or two channels is supported.
+fn read_wav_format(reader: &mut (impl Read + Seek)) -> io::Result<WavFormat> {
+    if read_tag(reader)? != *b"RIFF" {
+        return Err(invalid_wav("not a RIFF file"));
+    }
+    read_u32(reader)?;
+    if read_tag(reader)? != *b"WAVE" {
+        return Err(invalid_wav("not a WAVE file"));
+    }
+
+    let mut format = None;
+    loop {
+        let tag = read_tag(reader)?;
+        let size = read_u32(reader)?;
+        // NOTE: RIFF chunks are padded to an even number of bytes
+        let padded_size = (size as i64 + 1) & !1;
+
+        match &tag {
+            b"fmt " => {
+                if size < 16 {
+                    return Err(invalid_wav("WAV fmt chunk is too short"));
+                }
+
+                let format_tag = read_u16(reader)?;
+                let channel_count = read_u16(reader)? as u32;
+                let samples_per_second = read_u32(reader)?;
+                read_u32(reader)?; // average bytes per second
+                read_u16(reader)?; // block align
+                let bits_per_sample = read_u16(reader)?;
+
+                let mut fmt_size = 16;
+                let is_pcm = match format_tag {
+                    WAVE_FORMAT_PCM => true,
+                    WAVE_FORMAT_EXTENSIBLE if size >= 40 => {
+                        // NOTE: The extension size, valid bits and channel
+                        // mask come before the subformat
+                        let mut extension = [0; 24];
+                        reader.read_exact(&mut extension)?;
+                        fmt_size = 40;
+                        extension[8..] == PCM_SUBFORMAT
+                    }
+                    _ => false,
+                };
+                if !is_pcm {
+                    return Err(invalid_wav("only PCM WAV files are supported"));
+                }
+                if bits_per_sample != 16 {
+                    return Err(invalid_wav("only 16-bit WAV files are supported"));
+                }
+                if channel_count != 1 && channel_count != 2 {
+                    return Err(invalid_wav("only mono and stereo WAV files are supported"));
+                }
+                if samples_per_second == 0 {
+                    return Err(invalid_wav("WAV file has a sample rate of zero"));
+                }
+
+                format = Some((samples_per_second, channel_count));
+                reader.seek(SeekFrom::Current(padded_size - fmt_size))?;
+            }
+            b"data" => {
+                let (samples_per_second, channel_count) =
+                    format.ok_or_else(|| invalid_wav("WAV data chunk comes before fmt chunk"))?;
+
+                // NOTE: Streamed or cut short files can say there is more
+                // data than the file holds, up to 0xFFFFFFFF bytes
+                let data_offset = reader.stream_position()?;
+                let file_size = reader.seek(SeekFrom::End(0))?;
+                reader.seek(SeekFrom::Start(data_offset))?;
+                let size = (size as u64).min(file_size.saturating_sub(data_offset)) as u32;
+
+                return Ok(WavFormat {
+                    samples_per_second,
+                    channel_count,
+                    data_offset,
+                    sample_count: size / (2 * channel_count),
+                });
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(padded_size))?;
+            }
+        }
+    }
+}
+
+/// Splits interleaved 16-bit samples into a slice per channel. `channels`
+/// only needs as many slices as there are channels, each with room for every
+/// frame in `bytes`.
+pub fn deinterleave_wav_samples(bytes: &[u8], channel_count: u32, channels: &mut [&mut [i16]]) {
+    for (frame_index, frame) in bytes.chunks_exact(2 * channel_count as usize).enumerate() {
+        for (channel, sample) in channels.iter_mut().zip(frame.chunks_exact(2)) {
+            channel[frame_index] = i16::from_le_bytes([sample[0], sample[1]]);
+        }
+    }
+}
+
+/// Decodes a whole WAV file up front
+fn read_wav(reader: &mut (impl Read + Seek)) -> io::Result<LoadedSound> {
+    let format = read_wav_format(reader)?;
+    let sample_count = format.sample_count as usize;
+
+    let mut bytes = vec![0; 2 * format.channel_count as usize * sample_count];
+    reader.read_exact(&mut bytes)?;
+
+    let mut samples: [Vec<i16>; 2] = Default::default();
+    for channel in samples.iter_mut().take(format.channel_count as usize) {
+        *channel = vec![0; sample_count];
+    }
+    let [left, right] = &mut samples;
+    deinterleave_wav_samples(
+        &bytes,
+        format.channel_count,
+        &mut [left.as_mut_slice(), right.as_mut_slice()],
+    );
+
+    Ok(LoadedSound {
+        samples_per_second: format.samples_per_second,
+        sample_count: format.sample_count,
+        channel_count: format.channel_count,
+        samples,
+    })
+}
+
+/// Decodes the whole file up front. Use `open_wav_stream` for long tracks.
+pub fn load_wav(file_name: &str) -> io::Result<LoadedSound> {
+    read_wav(&mut BufReader::new(File::open(file_name)?))
+}
+
+/// Reads where the samples are, the rest is read by `read_wav_chunk`
+pub fn open_wav_stream(file_name: &str) -> io::Result<WavStream> {
+    if file_name.len() >= MAX_ASSET_PATH_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the path to the WAV file is too long to stream it",
+        ));
+    }
+    let format = read_wav_format(&mut BufReader::new(File::open(file_name)?))?;
+
+    let mut result = WavStream {
+        file_name: [0; MAX_ASSET_PATH_LENGTH],
+        samples_per_second: format.samples_per_second,
+        channel_count: format.channel_count,
+        data_offset: format.data_offset,
+        sample_count: format.sample_count,
+        next_sample: 0,
+    };
+    copy_name(&mut result.file_name, file_name);
+    Ok(result)
+}
+
+/// Reads as many of the next samples as fit in `bytes`, or are left, still
+/// interleaved. Returns how many, which is zero once the whole file has been
+/// read.
+///
+/// # Safety
+///
+/// The platform functions in `memory` must be valid, or None.
+pub unsafe fn read_wav_chunk(
+    memory: &GameMemory,
+    stream: &mut WavStream,
+    bytes: &mut [u8],
+) -> io::Result<u32> {
+    let read_data_from_file = memory
+        .platform_read_data_from_file
+        .ok_or_else(|| io::Error::other("the platform can't stream files"))?;
+
+    let frame_size = 2 * stream.channel_count;
+    let sample_count =
+        (bytes.len() as u32 / frame_size).min(stream.sample_count - stream.next_sample);
+    let size = (sample_count * frame_size) as usize;
+    let offset = stream.data_offset + stream.next_sample as u64 * frame_size as u64;
+
+    let file_name = get_name(&stream.file_name);
+    if !read_data_from_file(
+        file_name.as_ptr(),
+        file_name.len(),
+        offset,
+        size,
+        bytes.as_mut_ptr(),
+    ) {
+        return Err(io::Error::other(format!("could not read {}", file_name)));
+    }
+    stream.next_sample += sample_count;
+
+    Ok(sample_count)
+}
+
+/// Starts the stream over from its first sample, for looping music.
+pub fn rewind_wav_stream(stream: &mut WavStream) {
+    stream.next_sample = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{mem::zeroed, slice};
+    use std::{env, fs, io::Cursor, str};
+
+    fn push_chunk(bytes: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+        bytes.extend_from_slice(tag);
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+    }
+
+    fn make_fmt(
+        format_tag: u16,
+        channel_count: u16,
+        samples_per_second: u32,
+        bits: u16,
+    ) -> Vec<u8> {
+        let block_align = channel_count * bits / 8;
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&format_tag.to_le_bytes());
+        fmt.extend_from_slice(&channel_count.to_le_bytes());
+        fmt.extend_from_slice(&samples_per_second.to_le_bytes());
+        fmt.extend_from_slice(&(samples_per_second * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt
+    }
+
+    fn make_data(samples: &[i16]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn make_wav(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (tag, data) in chunks {
+            push_chunk(&mut body, tag, data);
+        }
+        let mut bytes = Vec::new();
+        push_chunk(&mut bytes, b"RIFF", &body);
+        bytes
+    }
+
+    fn get_wav_error(bytes: Vec<u8>) -> String {
+        match read_wav(&mut Cursor::new(bytes)) {
+            Ok(_) => panic!("the WAV decoded"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn decodes_stereo_into_channels() {
+        let bytes = make_wav(&[
+            (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 2, 22050, 16)),
+            (b"data", make_data(&[1, -1, 2, -2, i16::MAX, i16::MIN])),
+        ]);
+        let sound = read_wav(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.samples_per_second, 22050);
+        assert_eq!(sound.channel_count, 2);
+        assert_eq!(sound.sample_count, 3);
+        assert_eq!(sound.samples[0], vec![1, 2, i16::MAX]);
+        assert_eq!(sound.samples[1], vec![-1, -2, i16::MIN]);
+    }
+
+    #[test]
+    fn mono_only_fills_the_first_channel() {
+        let bytes = make_wav(&[
+            (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 1, 48000, 16)),
+            (b"data", make_data(&[5, 6, 7])),
+        ]);
+        let sound = read_wav(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.channel_count, 1);
+        assert_eq!(sound.samples[0], vec![5, 6, 7]);
+        assert!(sound.samples[1].is_empty());
+    }
+
+    #[test]
+    fn skips_other_chunks_and_padding() {
+        // NOTE: An odd sized chunk is padded, and a longer fmt chunk has
+        // extra fields after the ones read
+        let mut fmt = make_fmt(WAVE_FORMAT_PCM, 1, 44100, 16);
+        fmt.extend_from_slice(&[0; 8]);
+        let bytes = make_wav(&[
+            (b"LIST", b"odd".to_vec()),
+            (b"fmt ", fmt),
+            (b"fact", vec![0; 4]),
+            (b"data", make_data(&[9, 8])),
+        ]);
+        let sound = read_wav(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.samples[0], vec![9, 8]);
+    }
+
+    fn make_extensible_fmt(subformat: &[u8; 16]) -> Vec<u8> {
+        let mut fmt = make_fmt(WAVE_FORMAT_EXTENSIBLE, 2, 48000, 16);
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        fmt.extend_from_slice(&3u32.to_le_bytes());
+        fmt.extend_from_slice(subformat);
+        fmt
+    }
+
+    #[test]
+    fn decodes_extensible_pcm() {
+        let bytes = make_wav(&[
+            (b"fmt ", make_extensible_fmt(&PCM_SUBFORMAT)),
+            (b"data", make_data(&[3, -3])),
+        ]);
+        let sound = read_wav(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.samples[0], vec![3]);
+        assert_eq!(sound.samples[1], vec![-3]);
+    }
+
+    #[test]
+    fn data_sizes_past_the_end_are_cut_to_the_file() {
+        let mut bytes = make_wav(&[
+            (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 1, 48000, 16)),
+            (b"data", make_data(&[4, 5, 6])),
+        ]);
+        let size_offset = bytes.len() - 6 - 4;
+        bytes[size_offset..size_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let sound = read_wav(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.sample_count, 3);
+        assert_eq!(sound.samples[0], vec![4, 5, 6]);
+
+        // NOTE: Cut off in the middle of the samples, the whole ones are kept
+        let mut bytes = make_wav(&[
+            (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 2, 48000, 16)),
+            (b"data", make_data(&[1, 2, 3, 4])),
+        ]);
+        bytes.truncate(bytes.len() - 3);
+        let sound = read_wav(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.samples[0], vec![1]);
+        assert_eq!(sound.samples[1], vec![2]);
+    }
+
+    #[test]
+    fn rejects_unsupported_and_malformed_files() {
+        let data = make_data(&[0, 0]);
+        let bad_wavs = [
+            (b"RIFX\0\0\0\0WAVE".to_vec(), "not a RIFF file"),
+            (b"RIFF\0\0\0\0AVI ".to_vec(), "not a WAVE file"),
+            (
+                make_wav(&[(b"fmt ", vec![1, 0, 1, 0]), (b"data", data.clone())]),
+                "fmt chunk is too short",
+            ),
+            (
+                make_wav(&[
+                    (b"fmt ", make_fmt(3, 1, 48000, 16)),
+                    (b"data", data.clone()),
+                ]),
+                "only PCM",
+            ),
+            (
+                // NOTE: KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+                make_wav(&[
+                    (b"fmt ", {
+                        let mut float_subformat = PCM_SUBFORMAT;
+                        float_subformat[0] = 3;
+                        make_extensible_fmt(&float_subformat)
+                    }),
+                    (b"data", data.clone()),
+                ]),
+                "only PCM",
+            ),
+            (
+                make_wav(&[
+                    (b"fmt ", make_fmt(WAVE_FORMAT_EXTENSIBLE, 1, 48000, 16)),
+                    (b"data", data.clone()),
+                ]),
+                "only PCM",
+            ),
+            (
+                make_wav(&[
+                    (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 1, 48000, 8)),
+                    (b"data", data.clone()),
+                ]),
+                "only 16-bit",
+            ),
+            (
+                make_wav(&[
+                    (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 3, 48000, 16)),
+                    (b"data", data.clone()),
+                ]),
+                "only mono and stereo",
+            ),
+            (
+                make_wav(&[
+                    (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 1, 0, 16)),
+                    (b"data", data.clone()),
+                ]),
+                "sample rate of zero",
+            ),
+            (
+                make_wav(&[
+                    (b"data", data.clone()),
+                    (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 1, 48000, 16)),
+                ]),
+                "data chunk comes before fmt chunk",
+            ),
+        ];
+        for (bytes, message) in bad_wavs.iter() {
+            let error = get_wav_error(bytes.clone());
+            assert!(error.contains(message), "{} should say {}", error, message);
+        }
+
+        // NOTE: With no data at all
+        let bytes = make_wav(&[(b"fmt ", make_fmt(WAVE_FORMAT_PCM, 2, 48000, 16))]);
+        assert!(read_wav(&mut Cursor::new(bytes)).is_err());
+    }
+
+    unsafe extern "C" fn test_read_data_from_file(
+        name: *const u8,
+        name_length: usize,
+        offset: u64,
+        size: usize,
+        destination: *mut u8,
+    ) -> bool {
+        let name = str::from_utf8(slice::from_raw_parts(name, name_length)).unwrap();
+        let bytes = fs::read(name).unwrap();
+        let offset = offset as usize;
+        slice::from_raw_parts_mut(destination, size).copy_from_slice(&bytes[offset..offset + size]);
+        true
+    }
+
+    #[test]
+    fn streams_in_chunks() {
+        let samples: Vec<i16> = (0..20).collect();
+        let bytes = make_wav(&[
+            (b"LIST", vec![0; 6]),
+            (b"fmt ", make_fmt(WAVE_FORMAT_PCM, 2, 48000, 16)),
+            (b"data", make_data(&samples)),
+        ]);
+        let path = env::temp_dir().join(format!("wav_stream_test_{}.wav", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+
+        let mut memory: GameMemory = unsafe { zeroed() };
+        memory.platform_read_data_from_file = Some(test_read_data_from_file);
+        let mut stream = open_wav_stream(path.to_str().unwrap()).unwrap();
+        assert_eq!((stream.channel_count, stream.sample_count), (2, 10));
+
+        // NOTE: Room for 4 frames, with a byte over that doesn't make a frame
+        let mut chunk = [0u8; 17];
+        let mut streamed = Vec::new();
+        loop {
+            let sample_count = unsafe { read_wav_chunk(&memory, &mut stream, &mut chunk) }.unwrap();
+            if sample_count == 0 {
+                break;
+            }
+            streamed.extend_from_slice(&chunk[..4 * sample_count as usize]);
+        }
+        assert_eq!(streamed, make_data(&samples));
+
+        rewind_wav_stream(&mut stream);
+        assert_eq!(
+            unsafe { read_wav_chunk(&memory, &mut stream, &mut chunk) }.unwrap(),
+            4
+        );
+        assert_eq!(&chunk[..16], &make_data(&samples[..8])[..]);
+        fs::remove_file(&path).unwrap();
+    }
+}
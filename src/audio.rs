@@ -0,0 +1,394 @@
+//! equivalent to handmade_audio.h & handmade_audio.cpp
+
+use crate::asset::*;
+use crate::common::*;
+use core::{ptr, slice};
+use std::io;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoundId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayingSoundId(u32);
+
+/// Sounds past this many are not played
+const MAX_PLAYING_SOUND_COUNT: usize = 32;
+/// How many samples of a streamed sound are read at a time
+const STREAM_CHUNK_SAMPLE_COUNT: u32 = 1 << 16;
+/// Streams playing at once, each has a chunk buffer of its own
+const MAX_STREAM_COUNT: usize = 2;
+/// Streams are read through this much at a time, to be split into channels
+const STREAM_READ_SIZE: usize = 4096;
+
+#[derive(Clone, Copy)]
+enum SoundSource {
+    Loaded(SoundId),
+    /// Index into `AudioState::streams`
+    Streamed(usize),
+}
+
+#[derive(Clone, Copy)]
+struct PlayingSound {
+    id: PlayingSoundId,
+    source: SoundSource,
+    volume: f32,
+    /// -1.0 is all the way left, 1.0 is all the way right
+    pan: f32,
+    looping: bool,
+    /// In samples of the source sound (not of the output), so sounds authored
+    /// at any rate play back at the right speed
+    position: f64,
+}
+
+/// A streamed sound and the chunk of it being mixed. The chunk buffers are
+/// pushed once when the audio is initialized and refilled in place, so
+/// everything a stream needs stays in game memory.
+struct SoundStream {
+    is_in_use: bool,
+    wav: WavStream,
+    /// `STREAM_CHUNK_SAMPLE_COUNT` samples each, only the first for mono
+    chunk_samples: [RelativePointer<i16>; 2],
+    chunk_sample_count: u32,
+}
+
+pub struct AudioState {
+    pub master_volume: f32,
+    sounds: Vec<LoadedSound>,
+    /// The first `playing_sound_count` are playing, in the order they started
+    playing_sounds: [PlayingSound; MAX_PLAYING_SOUND_COUNT],
+    playing_sound_count: usize,
+    next_playing_sound_id: u32,
+    streams: [SoundStream; MAX_STREAM_COUNT],
+}
+
+/// The samples a playing sound is mixed from at the moment, all of a loaded
+/// sound or the current chunk of a stream
+struct SoundChunk<'a> {
+    samples_per_second: u32,
+    sample_count: u32,
+    channel_count: u32,
+    samples: [&'a [i16]; 2],
+}
+
+/// Pushes the stream chunk buffers into `arena`. The sounds are left alone,
+/// they start out with `replace_sounds`.
+///
+/// # Safety
+///
+/// `arena` must point to an initialized arena in the same storage block as
+/// `audio_state`.
+pub unsafe fn initialize_audio(audio_state: &mut AudioState, arena: *mut MemoryArena) {
+    audio_state.master_volume = 1.0;
+    audio_state.playing_sound_count = 0;
+    audio_state.next_playing_sound_id = 0;
+    for stream in &mut audio_state.streams {
+        stream.is_in_use = false;
+        stream.chunk_sample_count = 0;
+        for channel in &mut stream.chunk_samples {
+            let samples = push_array(arena, STREAM_CHUNK_SAMPLE_COUNT as usize, "sound stream");
+            set_relative_pointer(channel, samples);
+        }
+    }
+}
+
+/// Plays `sounds` from now on. The ones there were are forgotten rather than
+/// freed, game memory from an earlier run only points at them.
+pub fn replace_sounds(audio_state: &mut AudioState, sounds: Vec<LoadedSound>) {
+    unsafe { ptr::write(&mut audio_state.sounds, sounds) };
+}
+
+/// A copy of the sounds added so far, without copying their samples
+///
+/// # Safety
+///
+/// The copy and `audio_state` share the sounds, neither may add to them or
+/// free them.
+pub unsafe fn share_sounds(audio_state: &AudioState) -> Vec<LoadedSound> {
+    ptr::read(&audio_state.sounds)
+}
+
+pub fn add_sound(audio_state: &mut AudioState, sound: LoadedSound) -> SoundId {
+    audio_state.sounds.push(sound);
+    SoundId(audio_state.sounds.len() - 1)
+}
+
+/// None if `MAX_PLAYING_SOUND_COUNT` sounds are already playing
+fn push_playing_sound(
+    audio_state: &mut AudioState,
+    source: SoundSource,
+    looping: bool,
+) -> Option<PlayingSoundId> {
+    if audio_state.playing_sound_count == MAX_PLAYING_SOUND_COUNT {
+        return None;
+    }
+
+    let id = PlayingSoundId(audio_state.next_playing_sound_id);
+    audio_state.next_playing_sound_id = audio_state.next_playing_sound_id.wrapping_add(1);
+    audio_state.playing_sounds[audio_state.playing_sound_count] = PlayingSound {
+        id,
+        source,
+        volume: 1.0,
+        pan: 0.0,
+        looping,
+        position: 0.0,
+    };
+    audio_state.playing_sound_count += 1;
+
+    Some(id)
+}
+
+/// Returns None if there are too many sounds playing to play another.
+pub fn play_sound(
+    audio_state: &mut AudioState,
+    sound: SoundId,
+    looping: bool,
+) -> Option<PlayingSoundId> {
+    debug_assert!(sound.0 < audio_state.sounds.len());
+    push_playing_sound(audio_state, SoundSource::Loaded(sound), looping)
+}
+
+/// Plays a sound that is read from `wav` as it is mixed, for music. Returns
+/// None if `MAX_STREAM_COUNT` streams or too many sounds are already playing.
+pub fn play_stream(
+    audio_state: &mut AudioState,
+    wav: WavStream,
+    looping: bool,
+) -> Option<PlayingSoundId> {
+    let stream_index = audio_state
+        .streams
+        .iter()
+        .position(|stream| !stream.is_in_use)?;
+    let id = push_playing_sound(audio_state, SoundSource::Streamed(stream_index), looping)?;
+
+    let stream = &mut audio_state.streams[stream_index];
+    stream.is_in_use = true;
+    stream.wav = wav;
+    stream.chunk_sample_count = 0;
+
+    Some(id)
+}
+
+/// Does nothing if the sound has already finished.
+pub fn change_volume(audio_state: &mut AudioState, id: PlayingSoundId, volume: f32, pan: f32) {
+    if let Some(playing_sound) = audio_state.playing_sounds[..audio_state.playing_sound_count]
+        .iter_mut()
+        .find(|playing_sound| playing_sound.id == id)
+    {
+        playing_sound.volume = volume;
+        playing_sound.pan = pan.clamp(-1.0, 1.0);
+    }
+}
+
+fn get_sound_chunk<'a>(
+    sounds: &'a [LoadedSound],
+    streams: &'a [SoundStream],
+    source: SoundSource,
+) -> SoundChunk<'a> {
+    match source {
+        SoundSource::Loaded(sound) => {
+            let sound = &sounds[sound.0];
+            SoundChunk {
+                samples_per_second: sound.samples_per_second,
+                sample_count: sound.sample_count,
+                channel_count: sound.channel_count,
+                samples: [&sound.samples[0], &sound.samples[1]],
+            }
+        }
+        SoundSource::Streamed(stream_index) => {
+            let stream = &streams[stream_index];
+            let channel = |channel_index: usize| unsafe {
+                slice::from_raw_parts(
+                    get_relative_pointer(&stream.chunk_samples[channel_index]),
+                    stream.chunk_sample_count as usize,
+                )
+            };
+            SoundChunk {
+                samples_per_second: stream.wav.samples_per_second,
+                sample_count: stream.chunk_sample_count,
+                channel_count: stream.wav.channel_count,
+                samples: [channel(0), channel(1)],
+            }
+        }
+    }
+}
+
+/// Refills the stream's chunk buffers with the next chunk of its file.
+unsafe fn read_stream_chunk(memory: &GameMemory, stream: &mut SoundStream) -> io::Result<()> {
+    let channel_count = stream.wav.channel_count as usize;
+    let mut channels = [
+        slice::from_raw_parts_mut(
+            get_relative_pointer(&stream.chunk_samples[0]),
+            STREAM_CHUNK_SAMPLE_COUNT as usize,
+        ),
+        slice::from_raw_parts_mut(
+            get_relative_pointer(&stream.chunk_samples[1]),
+            STREAM_CHUNK_SAMPLE_COUNT as usize,
+        ),
+    ];
+
+    let mut bytes = [0u8; STREAM_READ_SIZE];
+    let mut chunk_sample_count = 0;
+    while chunk_sample_count < STREAM_CHUNK_SAMPLE_COUNT {
+        let max_size =
+            2 * channel_count * (STREAM_CHUNK_SAMPLE_COUNT - chunk_sample_count) as usize;
+        let read_bytes = &mut bytes[..max_size.min(STREAM_READ_SIZE)];
+        let sample_count = read_wav_chunk(memory, &mut stream.wav, read_bytes)?;
+        if sample_count == 0 {
+            break;
+        }
+
+        let start = chunk_sample_count as usize;
+        let [left, right] = &mut channels;
+        deinterleave_wav_samples(
+            &read_bytes[..2 * channel_count * sample_count as usize],
+            stream.wav.channel_count,
+            &mut [&mut left[start..], &mut right[start..]],
+        );
+        chunk_sample_count += sample_count;
+    }
+    stream.chunk_sample_count = chunk_sample_count;
+
+    Ok(())
+}
+
+/// Moves `playing_sound` on to the next chunk or loop iteration until its
+/// position is inside its source sound. Returns false once it has finished.
+unsafe fn advance_playing_sound(
+    memory: &GameMemory,
+    sounds: &[LoadedSound],
+    streams: &mut [SoundStream],
+    playing_sound: &mut PlayingSound,
+) -> bool {
+    match playing_sound.source {
+        SoundSource::Loaded(sound) => {
+            let sample_count = sounds[sound.0].sample_count as f64;
+            if sample_count == 0.0 {
+                return false;
+            }
+            while playing_sound.position >= sample_count {
+                if !playing_sound.looping {
+                    return false;
+                }
+                playing_sound.position -= sample_count;
+            }
+        }
+        SoundSource::Streamed(stream_index) => {
+            let stream = &mut streams[stream_index];
+            let mut rewound = false;
+            while playing_sound.position >= stream.chunk_sample_count as f64 {
+                playing_sound.position -= stream.chunk_sample_count as f64;
+                if let Err(e) = read_stream_chunk(memory, stream) {
+                    error!("could not stream sound: {}", e);
+                    stream.is_in_use = false;
+                    return false;
+                }
+
+                if stream.chunk_sample_count == 0 {
+                    // NOTE: Rewinding an empty stream would loop forever
+                    if !playing_sound.looping || rewound {
+                        stream.is_in_use = false;
+                        return false;
+                    }
+                    rewind_wav_stream(&mut stream.wav);
+                    rewound = true;
+                }
+            }
+        }
+    }
+
+    true
+}
+
+/// Mixes `playing_sound` into `mix`. Returns false once it has finished.
+unsafe fn mix_playing_sound(
+    memory: &GameMemory,
+    sounds: &[LoadedSound],
+    streams: &mut [SoundStream],
+    playing_sound: &mut PlayingSound,
+    master_volume: f32,
+    output_samples_per_second: f64,
+    mix: &mut [[f32; 2]],
+) -> bool {
+    let volume = master_volume * playing_sound.volume;
+    let left_volume = volume * (1.0 - playing_sound.pan).min(1.0);
+    let right_volume = volume * (1.0 + playing_sound.pan).min(1.0);
+
+    for frame in mix.iter_mut() {
+        if !advance_playing_sound(memory, sounds, streams, playing_sound) {
+            return false;
+        }
+
+        let sound = get_sound_chunk(sounds, streams, playing_sound.source);
+        let right_channel = if sound.channel_count > 1 { 1 } else { 0 };
+        let position = playing_sound.position;
+        let sample_index = position as usize;
+        // TODO: Interpolate across chunk boundaries of streamed sounds
+        let next_sample_index = (sample_index + 1).min(sound.sample_count as usize - 1);
+        let t = (position - sample_index as f64) as f32;
+
+        let sample = |channel: usize| {
+            let samples = &sound.samples[channel];
+            (1.0 - t) * samples[sample_index] as f32 + t * samples[next_sample_index] as f32
+        };
+        frame[0] += left_volume * sample(0);
+        frame[1] += right_volume * sample(right_channel);
+
+        let step = sound.samples_per_second as f64 / output_samples_per_second;
+        playing_sound.position += step;
+    }
+
+    advance_playing_sound(memory, sounds, streams, playing_sound)
+}
+
+/// Mixes every playing sound into the buffer, which is interleaved stereo
+/// (left, right, left, right, ...). Sounds authored at a different rate than
+/// the buffer are resampled with linear interpolation. One-shot sounds that
+/// finish are dropped. The mix is done in `temp_arena`, which is left as it
+/// was.
+///
+/// # Safety
+///
+/// The platform functions in `memory` must be valid, or None, and
+/// `temp_arena` must point to an initialized arena.
+pub unsafe fn output_playing_sounds(
+    audio_state: &mut AudioState,
+    memory: &GameMemory,
+    temp_arena: *mut MemoryArena,
+    sound_buffer: &mut GameSoundOutputBuffer,
+) {
+    let sample_count = sound_buffer.sample_count as usize;
+    let output_samples_per_second = sound_buffer.samples_per_second as f64;
+
+    let temporary_memory = begin_temporary_memory(temp_arena);
+    let mix = push_array::<[f32; 2]>(temp_arena, sample_count, "sound mix");
+    mix.write_bytes(0, sample_count);
+    let mix = slice::from_raw_parts_mut(mix, sample_count);
+
+    let mut kept_count = 0;
+    for playing_sound_index in 0..audio_state.playing_sound_count {
+        let mut playing_sound = audio_state.playing_sounds[playing_sound_index];
+        if mix_playing_sound(
+            memory,
+            &audio_state.sounds,
+            &mut audio_state.streams,
+            &mut playing_sound,
+            audio_state.master_volume,
+            output_samples_per_second,
+            mix,
+        ) {
+            audio_state.playing_sounds[kept_count] = playing_sound;
+            kept_count += 1;
+        }
+    }
+    audio_state.playing_sound_count = kept_count;
+
+    let mut dest = sound_buffer.samples;
+    for frame in mix.iter() {
+        for channel in frame {
+            *dest = channel.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            dest = dest.add(1);
+        }
+    }
+
+    end_temporary_memory(temporary_memory);
+}
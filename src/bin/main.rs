@@ -5,10 +5,29 @@ pub mod common;
 #[path = "../os/win32.rs"]
 mod os;
 
+#[cfg(target_os = "linux")]
+#[path = "../os/linux.rs"]
+mod os;
+
 #[macro_use]
 extern crate log;
 
+use log::LevelFilter;
+use std::env;
+
 fn main() {
+    // NOTE: Without RUST_LOG the platform layer's config picks the log level,
+    // so let everything through here and have log's max level do the
+    // filtering. Only warnings until the config has been read.
+    if env::var_os("RUST_LOG").is_some() {
+        env_logger::init();
+    } else {
+        env_logger::Builder::new()
+            .filter_level(LevelFilter::Trace)
+            .init();
+        log::set_max_level(LevelFilter::Warn);
+    }
+
     // log levels: error, warn, info, debug, trace
     info!("starting up... log level: {}", log::max_level());
 
@@ -1,8 +1,17 @@
 //! equivalent to handmade_platform.cpp
 
-use core::mem::*;
+use core::{marker::PhantomData, mem::*};
+#[cfg(not(windows))]
+use std::ffi::c_void;
+#[cfg(windows)]
 use winapi::ctypes::c_void;
 
+use std::{
+    alloc::{alloc_zeroed, Layout},
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering},
+};
+
 pub fn kilobytes(bytes: usize) -> usize {
     bytes * 1024
 }
@@ -19,9 +28,139 @@ pub fn terabytes(bytes: usize) -> usize {
     1024 * gigabytes(bytes)
 }
 
+/// A pointer kept as the distance from itself to what it points at, so it
+/// still points at the same thing after the memory holding both is copied to
+/// another address, and game memory comes out byte for byte the same wherever
+/// it is mapped. Zeroed is null.
+///
+/// Only use it where it was set, a copy on its own points somewhere else.
+pub struct RelativePointer<T> {
+    offset: isize,
+    target: PhantomData<*mut T>,
+}
+
+pub fn set_relative_pointer<T>(pointer: &mut RelativePointer<T>, target: *mut T) {
+    pointer.offset = if target.is_null() {
+        0
+    } else {
+        (target as isize).wrapping_sub(pointer as *mut RelativePointer<T> as isize)
+    };
+}
+
+pub fn get_relative_pointer<T>(pointer: &RelativePointer<T>) -> *mut T {
+    if pointer.offset == 0 {
+        null_mut()
+    } else {
+        (pointer as *const RelativePointer<T> as isize).wrapping_add(pointer.offset) as *mut T
+    }
+}
+
+pub const MAX_ARENA_NAME_LENGTH: usize = 32;
+#[cfg(debug_assertions)]
+pub const MAX_ARENA_TAG_COUNT: usize = 32;
+
+/// Everything pushed into an arena under one tag. Debug builds only.
+#[cfg(debug_assertions)]
+#[derive(Clone, Copy)]
+pub struct ArenaTag {
+    pub name: [u8; MAX_ARENA_NAME_LENGTH],
+    /// Not counting alignment padding
+    pub size: usize,
+    pub count: u32,
+}
+
+pub struct MemoryArena {
+    /// NUL padded, copied so it outlives a game code reload
+    pub name: [u8; MAX_ARENA_NAME_LENGTH],
+    pub size: usize,
+    /// Relative so the arena can point into another storage block, as long
+    /// as the two are moved together
+    pub base: RelativePointer<u8>,
+    pub used: usize,
+    /// The most `used` has been since the arena was initialized
+    pub high_water_mark: usize,
+
+    /// Tags past the last one are counted under the last one
+    #[cfg(debug_assertions)]
+    pub tags: [ArenaTag; MAX_ARENA_TAG_COUNT],
+    #[cfg(debug_assertions)]
+    pub tag_count: usize,
+}
+
+/// Copies `name` into a NUL padded buffer, cut short to leave room for at
+/// least one NUL.
+pub fn copy_name(destination: &mut [u8], name: &str) {
+    let bytes = name.as_bytes();
+    let length = bytes.len().min(destination.len().saturating_sub(1));
+    destination[..length].copy_from_slice(&bytes[..length]);
+    for byte in &mut destination[length..] {
+        *byte = 0;
+    }
+}
+
+/// The name in a NUL padded buffer filled in by `copy_name`
+pub fn get_name(bytes: &[u8]) -> &str {
+    let length = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    // NOTE: Cutting a name short can split a character, drop what is left of it
+    match std::str::from_utf8(&bytes[..length]) {
+        Ok(name) => name,
+        Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+    }
+}
+
+pub fn initialize_arena(arena: &mut MemoryArena, name: &str, size: usize, base: *mut u8) {
+    copy_name(&mut arena.name, name);
+    arena.size = size;
+    set_relative_pointer(&mut arena.base, base);
+    arena.high_water_mark = 0;
+    clear_arena(arena);
+}
+
+/// Frees everything in the arena at once. The high water mark is kept.
+pub fn clear_arena(arena: &mut MemoryArena) {
+    arena.used = 0;
+    #[cfg(debug_assertions)]
+    {
+        arena.tag_count = 0;
+    }
+}
+
+#[cfg(debug_assertions)]
+fn record_arena_tag(arena: &mut MemoryArena, tag: &str, size: usize) {
+    let found = arena.tags[..arena.tag_count]
+        .iter()
+        .position(|arena_tag| get_name(&arena_tag.name) == tag);
+    let tag_index = match found {
+        Some(tag_index) => tag_index,
+        None if arena.tag_count < MAX_ARENA_TAG_COUNT => {
+            let tag_index = arena.tag_count;
+            copy_name(&mut arena.tags[tag_index].name, tag);
+            arena.tags[tag_index].size = 0;
+            arena.tags[tag_index].count = 0;
+            arena.tag_count += 1;
+            tag_index
+        }
+        None => {
+            copy_name(&mut arena.tags[MAX_ARENA_TAG_COUNT - 1].name, "other");
+            MAX_ARENA_TAG_COUNT - 1
+        }
+    };
+    arena.tags[tag_index].size += size;
+    arena.tags[tag_index].count += 1;
+}
+
+/// The tags used so far, in the order they were first pushed
+#[cfg(debug_assertions)]
+pub fn get_arena_tags(arena: &MemoryArena) -> &[ArenaTag] {
+    &arena.tags[..arena.tag_count]
+}
+
 unsafe fn get_alignment_offset(arena: *mut MemoryArena, alignment: usize) -> usize {
     let mut alignment_offset = 0;
-    let result_pointer = (*arena).base as usize + (*arena).used;
+    let result_pointer = get_relative_pointer(&(*arena).base) as usize + (*arena).used;
     let alignment_mask = alignment.saturating_sub(1);
     if (result_pointer & alignment_mask) > 0 {
         alignment_offset = alignment - (result_pointer & alignment_mask);
@@ -31,15 +170,26 @@ unsafe fn get_alignment_offset(arena: *mut MemoryArena, alignment: usize) -> usi
 
 /// Uses PushSize for the correct amount and returns the pointer already cast to
 /// the correct type for you.
-pub unsafe fn push_struct<T>(arena: *mut MemoryArena) -> *mut T {
-    push_size(arena, size_of::<T>(), Some(align_of::<T>())) as *mut T
+///
+/// # Safety
+///
+/// `arena` must point to an initialized arena whose memory is valid.
+pub unsafe fn push_struct<T>(arena: *mut MemoryArena, tag: &str) -> *mut T {
+    push_size(arena, size_of::<T>(), Some(align_of::<T>()), tag) as *mut T
 }
 
-/// Pushes the given number of bytes into the arena. Panics on OOM.
+/// Pushes the given number of bytes into the arena. Panics on OOM. `tag` says
+/// what the memory is for in the memory report, it is only kept in debug
+/// builds.
+///
+/// # Safety
+///
+/// `arena` must point to an initialized arena whose memory is valid.
 pub unsafe fn push_size(
     arena: *mut MemoryArena,
     size_init: usize,
     alignment: Option<usize>,
+    tag: &str,
 ) -> *mut u8 {
     let alignment = alignment.unwrap_or(4);
     let mut size = size_init;
@@ -47,12 +197,21 @@ pub unsafe fn push_size(
     let alignment_offset = get_alignment_offset(arena, alignment);
     size += alignment_offset;
 
-    debug_assert!((*arena).used + size <= (*arena).size);
+    assert!(
+        (*arena).used + size <= (*arena).size,
+        "{} arena is out of memory: {} of {} bytes used, {} more wanted for {}",
+        get_name(&(*arena).name),
+        (*arena).used,
+        (*arena).size,
+        size,
+        tag
+    );
 
-    let result = (*arena)
-        .base
-        .offset((*arena).used as isize + alignment_offset as isize);
+    let result = get_relative_pointer(&(*arena).base).add((*arena).used + alignment_offset);
     (*arena).used += size;
+    (*arena).high_water_mark = (*arena).high_water_mark.max((*arena).used);
+    #[cfg(debug_assertions)]
+    record_arena_tag(&mut *arena, tag, size_init);
 
     debug_assert!(size >= size_init);
 
@@ -65,14 +224,55 @@ pub unsafe fn push_size(
 /// (eg: `[u16; 20]`), which will have a similar effect, but this allows you to
 /// select a size to push at runtime, which cannot currently be done with
 /// PushStruct because Rust arrays must have a known size at compile time.
-pub unsafe fn push_array<T>(arena: *mut MemoryArena, count: usize) -> *mut T {
-    push_size(arena, size_of::<T>() * count, Some(align_of::<T>())) as *mut T
+///
+/// # Safety
+///
+/// `arena` must point to an initialized arena whose memory is valid.
+pub unsafe fn push_array<T>(arena: *mut MemoryArena, count: usize, tag: &str) -> *mut T {
+    push_size(arena, size_of::<T>() * count, Some(align_of::<T>()), tag) as *mut T
 }
 
-pub struct MemoryArena {
-    pub size: usize,
-    pub base: *mut u8,
-    pub used: usize,
+/// How much of an arena was in use before something temporary was pushed
+/// into it, see `end_temporary_memory`
+pub struct TemporaryMemory {
+    arena: *mut MemoryArena,
+    used: usize,
+    #[cfg(debug_assertions)]
+    tags: [ArenaTag; MAX_ARENA_TAG_COUNT],
+    #[cfg(debug_assertions)]
+    tag_count: usize,
+}
+
+/// # Safety
+///
+/// `arena` must point to an initialized arena that outlives the temporary
+/// memory.
+pub unsafe fn begin_temporary_memory(arena: *mut MemoryArena) -> TemporaryMemory {
+    TemporaryMemory {
+        arena,
+        used: (*arena).used,
+        #[cfg(debug_assertions)]
+        tags: (*arena).tags,
+        #[cfg(debug_assertions)]
+        tag_count: (*arena).tag_count,
+    }
+}
+
+/// Frees everything pushed into the arena since `begin_temporary_memory`,
+/// tags included
+///
+/// # Safety
+///
+/// The arena must still be valid, and nothing pushed since may be used after.
+pub unsafe fn end_temporary_memory(temporary_memory: TemporaryMemory) {
+    let arena = temporary_memory.arena;
+    debug_assert!((*arena).used >= temporary_memory.used);
+    (*arena).used = temporary_memory.used;
+    #[cfg(debug_assertions)]
+    {
+        (*arena).tags = temporary_memory.tags;
+        (*arena).tag_count = temporary_memory.tag_count;
+    }
 }
 
 pub struct GameOffscreenBuffer {
@@ -83,6 +283,14 @@ pub struct GameOffscreenBuffer {
     pub bytes_per_pixel: i32,
 }
 
+pub struct GameSoundOutputBuffer {
+    pub samples_per_second: i32,
+    pub sample_count: i32,
+    /// Interleaved stereo, so there are two samples (left, right) for each
+    /// of `sample_count`
+    pub samples: *mut i16,
+}
+
 #[derive(Debug, Default)]
 pub struct GameButtonState {
     pub half_transition_count: i32,
@@ -115,13 +323,310 @@ pub struct GameControllerInput {
     pub terminator: GameButtonState,
 }
 
+/// A key by what is printed on it, independent of the platform's key codes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    /// 'A' to 'Z', '0' to '9' and the punctuation keys of a US layout:
+    /// `` ; = , - . / ` [ \ ] ' ``
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Escape,
+    Space,
+    Enter,
+    Tab,
+    Backspace,
+    Shift,
+    Control,
+    Alt,
+    CapsLock,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// F1 to F24
+    Function(u8),
+    /// The digits of the numeric keypad, 0 to 9
+    Numpad(u8),
+    NumpadAdd,
+    NumpadSubtract,
+    NumpadMultiply,
+    NumpadDivide,
+    NumpadDecimal,
+}
+
+/// One for every Windows virtual key code
+pub const KEY_COUNT: usize = 256;
+
+/// Characters the platform can hand the game in one frame, the rest are
+/// dropped
+pub const MAX_TEXT_INPUT_COUNT: usize = 32;
+
+/// `Key::Char` punctuation and the Windows virtual key codes for them
+const PUNCTUATION_KEYS: [(char, usize); 11] = [
+    (';', 0xBA),
+    ('=', 0xBB),
+    (',', 0xBC),
+    ('-', 0xBD),
+    ('.', 0xBE),
+    ('/', 0xBF),
+    ('`', 0xC0),
+    ('[', 0xDB),
+    ('\\', 0xDC),
+    (']', 0xDD),
+    ('\'', 0xDE),
+];
+
+/// Where `key` lives in `GameInput::keys`, None for a `Key::Char`,
+/// `Key::Function` or `Key::Numpad` that isn't a key. Letters are the same
+/// key either case. The numbering follows the Windows virtual key codes.
+pub fn key_index(key: Key) -> Option<usize> {
+    let index = match key {
+        Key::Char(c) if c.is_ascii_alphanumeric() => c.to_ascii_uppercase() as usize,
+        Key::Char(c) => {
+            return PUNCTUATION_KEYS
+                .iter()
+                .find(|&&(punctuation, _)| punctuation == c)
+                .map(|&(_, index)| index)
+        }
+        Key::Backspace => 0x08,
+        Key::Tab => 0x09,
+        Key::Enter => 0x0D,
+        Key::Shift => 0x10,
+        Key::Control => 0x11,
+        Key::Alt => 0x12,
+        Key::CapsLock => 0x14,
+        Key::Escape => 0x1B,
+        Key::Space => 0x20,
+        Key::PageUp => 0x21,
+        Key::PageDown => 0x22,
+        Key::End => 0x23,
+        Key::Home => 0x24,
+        Key::Left => 0x25,
+        Key::Up => 0x26,
+        Key::Right => 0x27,
+        Key::Down => 0x28,
+        Key::Insert => 0x2D,
+        Key::Delete => 0x2E,
+        Key::Numpad(digit) if digit <= 9 => 0x60 + digit as usize,
+        Key::NumpadMultiply => 0x6A,
+        Key::NumpadAdd => 0x6B,
+        Key::NumpadSubtract => 0x6D,
+        Key::NumpadDecimal => 0x6E,
+        Key::NumpadDivide => 0x6F,
+        Key::Function(number) if (1..=24).contains(&number) => 0x6F + number as usize,
+        Key::Numpad(_) | Key::Function(_) => return None,
+    };
+    Some(index)
+}
+
+/// The key at `index` in `GameInput::keys`, if it has a name. Letters come
+/// back upper case.
+pub fn key_from_index(index: usize) -> Option<Key> {
+    let key = match index {
+        0x30..=0x39 | 0x41..=0x5A => Key::Char(index as u8 as char),
+        0x60..=0x69 => Key::Numpad((index - 0x60) as u8),
+        0x70..=0x87 => Key::Function((index - 0x6F) as u8),
+        0x08 => Key::Backspace,
+        0x09 => Key::Tab,
+        0x0D => Key::Enter,
+        0x10 => Key::Shift,
+        0x11 => Key::Control,
+        0x12 => Key::Alt,
+        0x14 => Key::CapsLock,
+        0x1B => Key::Escape,
+        0x20 => Key::Space,
+        0x21 => Key::PageUp,
+        0x22 => Key::PageDown,
+        0x23 => Key::End,
+        0x24 => Key::Home,
+        0x25 => Key::Left,
+        0x26 => Key::Up,
+        0x27 => Key::Right,
+        0x28 => Key::Down,
+        0x2D => Key::Insert,
+        0x2E => Key::Delete,
+        0x6A => Key::NumpadMultiply,
+        0x6B => Key::NumpadAdd,
+        0x6D => Key::NumpadSubtract,
+        0x6E => Key::NumpadDecimal,
+        0x6F => Key::NumpadDivide,
+        _ => {
+            return PUNCTUATION_KEYS
+                .iter()
+                .find(|&&(_, punctuation_index)| punctuation_index == index)
+                .map(|&(c, _)| Key::Char(c))
+        }
+    };
+    Some(key)
+}
+
 pub struct GameInput {
     pub mouse_buttons: [GameButtonState; 5],
+    /// In offscreen buffer pixels, wherever the buffer is shown in the window.
+    /// Can be outside of the buffer when the mouse is over the black bars.
     pub mouse_x: i32,
     pub mouse_y: i32,
     pub mouse_z: i32,
     pub dt_for_frame: f32,
+    /// How long the last frame really took, as measured by the platform. 0
+    /// for the extra catch-up updates of a frame, so each frame shows up once.
+    pub frame_seconds: f32,
+    pub target_seconds_per_frame: f32,
     pub controllers: [GameControllerInput; 5],
+
+    /// Every key the platform knows about, indexed by `key_index`, named or
+    /// not. Unlike the keyboard controller, these are not affected by the
+    /// bindings.
+    pub keys: [GameButtonState; KEY_COUNT],
+
+    /// What was typed since the last update, after keyboard layout and key
+    /// repeat. Control characters such as backspace ('\u{8}') and escape
+    /// come through too, enter arrives as '\n'.
+    pub text_input: [char; MAX_TEXT_INPUT_COUNT],
+    pub text_input_count: usize,
+}
+
+/// False for a key that isn't one, see `key_index`
+pub fn is_key_down(input: &GameInput, key: Key) -> bool {
+    key_index(key).is_some_and(|index| input.keys[index].ended_down)
+}
+
+/// Whether `key` went down at least once since the last update
+pub fn was_key_pressed(input: &GameInput, key: Key) -> bool {
+    key_index(key).is_some_and(|index| {
+        let button = &input.keys[index];
+        button.half_transition_count > 1 || (button.half_transition_count == 1 && button.ended_down)
+    })
+}
+
+pub fn text_input(input: &GameInput) -> &[char] {
+    &input.text_input[..input.text_input_count]
+}
+
+/// For the platform layer. Drops the character if the buffer is full.
+pub fn push_text_input(input: &mut GameInput, c: char) {
+    if input.text_input_count < MAX_TEXT_INPUT_COUNT {
+        input.text_input[input.text_input_count] = c;
+        input.text_input_count += 1;
+    }
+}
+
+/// For the platform layer, text typed one UTF-16 code unit at a time like
+/// Windows' WM_CHAR. The first half of a surrogate pair waits in
+/// `high_surrogate` for its partner. Carriage returns come through as '\n'.
+pub fn push_utf16_text_input(input: &mut GameInput, high_surrogate: &mut u16, unit: u16) {
+    if (0xD800..0xDC00).contains(&unit) {
+        *high_surrogate = unit;
+        return;
+    }
+
+    // NOTE: Half a surrogate pair without the other half is dropped
+    let pair = [take(high_surrogate), unit];
+    let units = if !(0xDC00..0xE000).contains(&unit) {
+        &pair[1..]
+    } else if pair[0] != 0 {
+        &pair[..]
+    } else {
+        return;
+    };
+    for c in char::decode_utf16(units.iter().cloned()).filter_map(Result::ok) {
+        push_text_input(input, if c == '\r' { '\n' } else { c });
+    }
+}
+
+/// Opaque to the game, the platform layer decides what a queue looks like and
+/// hands out pointers to it through `GameMemory`.
+pub struct PlatformWorkQueue {
+    _private: [u8; 0],
+}
+
+pub type PlatformWorkQueueCallback =
+    unsafe extern "C" fn(queue: *mut PlatformWorkQueue, data: *mut c_void);
+
+/// Queues `callback` to be run with `data` on some worker thread. Only call
+/// this from the main thread.
+pub type PlatformAddEntry = unsafe extern "C" fn(
+    queue: *mut PlatformWorkQueue,
+    callback: PlatformWorkQueueCallback,
+    data: *mut c_void,
+);
+
+/// Returns once every entry added to the queue so far has finished running.
+/// The calling thread helps with the work while it waits.
+pub type PlatformCompleteAllWork = unsafe extern "C" fn(queue: *mut PlatformWorkQueue);
+
+/// A whole file read by the platform layer. `contents` is null when the file
+/// doesn't exist or couldn't be read, otherwise hand it back to
+/// `PlatformFreeFileMemory` when done with it.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PlatformFileContents {
+    pub contents: *mut u8,
+    pub size: usize,
+}
+
+/// Reads the file named by the UTF-8 in `name`, relative to where the game
+/// was started like the assets are.
+pub type PlatformReadEntireFile =
+    unsafe extern "C" fn(name: *const u8, name_length: usize) -> PlatformFileContents;
+
+pub type PlatformFreeFileMemory = unsafe extern "C" fn(file: PlatformFileContents);
+
+/// Replaces the file named by `name` with `size` bytes of `memory`, making
+/// any directories it needs. Should the write fail the old file is kept
+/// whole. Returns whether it worked.
+pub type PlatformWriteEntireFile = unsafe extern "C" fn(
+    name: *const u8,
+    name_length: usize,
+    memory: *const u8,
+    size: usize,
+) -> bool;
+
+/// Reads `size` bytes from `offset` in the file named by `name` into
+/// `destination`, for streaming a file a piece at a time. The platform layer
+/// keeps the file open between reads, so nothing the game holds on to does.
+/// Returns whether all of it was read.
+pub type PlatformReadDataFromFile = unsafe extern "C" fn(
+    name: *const u8,
+    name_length: usize,
+    offset: u64,
+    size: usize,
+    destination: *mut u8,
+) -> bool;
+
+pub const MAX_DATA_DIRECTORY_LENGTH: usize = 256;
+
+/// The settings from the platform layer's config file and command line that
+/// the game cares about
+#[derive(Debug)]
+pub struct GameConfig {
+    /// Where the assets are, NUL padded UTF-8. Read it with `get_name`.
+    pub data_directory: [u8; MAX_DATA_DIRECTORY_LENGTH],
+    /// For generating the world, None for a different world every run
+    pub seed: Option<u64>,
+    /// For the game's own logger, which RUST_LOG overrides like the platform
+    /// layer's
+    pub log_level: log::LevelFilter,
+}
+
+pub const MAX_GAME_ERROR_MESSAGE_LENGTH: usize = 256;
+pub const MAX_GAME_ERROR_FILE_LENGTH: usize = 128;
+
+/// A panic in the game code, caught before it could unwind into the platform
+/// layer. The strings are NUL padded, read them with `get_name`.
+#[derive(Debug)]
+pub struct GameError {
+    pub message: [u8; MAX_GAME_ERROR_MESSAGE_LENGTH],
+    pub file: [u8; MAX_GAME_ERROR_FILE_LENGTH],
+    pub line: u32,
+    pub column: u32,
+    /// `GameMemory::update_count` when it happened
+    pub frame_index: u64,
 }
 
 #[derive(Debug)]
@@ -133,11 +638,81 @@ pub struct GameMemory {
     pub transient_storage_size: usize,
     // required to be cleared to zero at startup
     pub transient_storage: *mut u8,
+
+    pub high_priority_queue: *mut PlatformWorkQueue,
+    pub platform_add_entry: Option<PlatformAddEntry>,
+    pub platform_complete_all_work: Option<PlatformCompleteAllWork>,
+
+    pub platform_read_entire_file: Option<PlatformReadEntireFile>,
+    pub platform_free_file_memory: Option<PlatformFreeFileMemory>,
+    pub platform_write_entire_file: Option<PlatformWriteEntireFile>,
+    pub platform_read_data_from_file: Option<PlatformReadDataFromFile>,
+
+    pub debug_table: *mut DebugTable,
+
+    /// Read once at startup, doesn't change after
+    pub config: GameConfig,
+
+    /// Filled in by the platform layer before every update
+    pub replay_status: ReplayStatus,
+    /// What the player is pressing right now, set by the platform layer
+    /// before every update. The same as the update's input unless a replay is
+    /// playing, so debug tools can be used while a replay loops.
+    pub live_input: *const GameInput,
+
+    /// Updates run since startup, counted by the game. Unlike game state it
+    /// keeps going when a replay loops.
+    pub update_count: u64,
+    /// Bumped by the game whenever game state takes or lets go of memory on
+    /// the heap, outside game memory. A snapshot from another generation
+    /// points at memory freed since or leaks what is held now, so the
+    /// platform layer doesn't restore it. Snapshots from an earlier run are
+    /// the exception: the game gives them what this run holds instead.
+    pub heap_generation: u64,
+    /// What went wrong, when an entry point returns false
+    pub error: GameError,
+}
+
+pub const REPLAY_SLOT_COUNT: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySlotState {
+    Empty,
+    /// Holds a recording, maybe from an earlier run
+    Recorded,
+    Recording,
+    Looping,
+}
+
+/// The platform's input recording slots, for the game to show. Slots are
+/// numbered from 1 for people.
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayStatus {
+    pub selected_slot: usize,
+    pub slots: [ReplaySlotState; REPLAY_SLOT_COUNT],
+
+    // NOTE: For the looping slot
+    /// Recorded updates run since the start of the loop
+    pub frame_index: usize,
+    pub frame_count: usize,
+    pub is_paused: bool,
+    /// Recorded updates per real update
+    pub speed: f32,
 }
 
+/// Returns false if the game panicked, see `GameMemory::error`
 pub type GameUpdateAndRender =
-    unsafe extern "C" fn(*mut GameMemory, *mut GameInput, *mut GameOffscreenBuffer);
+    unsafe extern "C" fn(*mut GameMemory, *mut GameInput, *mut GameOffscreenBuffer) -> bool;
+
+// NOTE: At the moment, this has to be a very fast function, it cannot be
+// more than a millisecond or so.
+/// Returns false if the game panicked, see `GameMemory::error`
+pub type GameGetSoundSamples =
+    unsafe extern "C" fn(*mut GameMemory, *mut GameSoundOutputBuffer) -> bool;
 
+/// # Safety
+///
+/// `input` must be a valid pointer.
 pub unsafe fn get_controller(
     input: *mut GameInput,
     controller_index: usize,
@@ -145,3 +720,362 @@ pub unsafe fn get_controller(
     debug_assert!(controller_index < (*input).controllers.len());
     &mut (*input).controllers[controller_index]
 }
+
+//
+// NOTE: Profiling
+//
+
+pub const MAX_DEBUG_EVENT_COUNT: usize = 1 << 16;
+pub const MAX_DEBUG_NAME_COUNT: usize = 1024;
+pub const MAX_DEBUG_NAME_LENGTH: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugEventType {
+    BeginBlock,
+    EndBlock,
+    /// Recorded by the platform at the start of every frame, with how long
+    /// the frame before took
+    FrameMarker {
+        seconds_elapsed: f32,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub struct DebugEvent {
+    pub clock: u64,
+    /// Into `DebugTable::names`, unused for frame markers
+    pub name_index: u32,
+    pub thread_id: u32,
+    pub event_type: DebugEventType,
+}
+
+/// What `timed_block!` records into. The platform allocates one and shares it
+/// with the game through `GameMemory`, so both end up in the same frames.
+pub struct DebugTable {
+    /// Block names, NUL padded, each registered the first time a timed block
+    /// with that name runs. The names are copied so they outlive a game code
+    /// reload.
+    pub names: [[u8; MAX_DEBUG_NAME_LENGTH]; MAX_DEBUG_NAME_COUNT],
+    pub name_count: AtomicU32,
+
+    /// Which of `events` is being recorded into in the high 32 bits, and how
+    /// many events were recorded into it so far in the low 32 bits. Events
+    /// past the end of the array are dropped.
+    pub event_array_index_event_index: AtomicU64,
+    pub events: [[DebugEvent; MAX_DEBUG_EVENT_COUNT]; 2],
+}
+
+/// Each of the game and the platform layer has its own copy of this, pointing
+/// at the same table. Timed blocks are skipped while it is null.
+pub static GLOBAL_DEBUG_TABLE: AtomicPtr<DebugTable> = AtomicPtr::new(null_mut());
+
+/// For the platform layer. The table is never freed.
+pub fn allocate_debug_table() -> *mut DebugTable {
+    // NOTE: Zeroed is a valid empty table, and it is too big for the stack
+    unsafe { alloc_zeroed(Layout::new::<DebugTable>()) as *mut DebugTable }
+}
+
+pub fn read_cycle_counter() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+    #[cfg(target_arch = "x86")]
+    unsafe {
+        core::arch::x86::_rdtsc()
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+    {
+        // TODO: A real cycle counter on other architectures
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// The OS thread id, which the game and the platform layer agree on
+#[cfg(windows)]
+pub fn get_thread_id() -> u32 {
+    unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() }
+}
+
+/// The OS thread id, which the game and the platform layer agree on
+#[cfg(target_os = "linux")]
+pub fn get_thread_id() -> u32 {
+    unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+}
+
+/// The OS thread id, which the game and the platform layer agree on
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn get_thread_id() -> u32 {
+    0
+}
+
+fn record_debug_event(table: *mut DebugTable, name_index: u32, event_type: DebugEventType) {
+    if table.is_null() {
+        return;
+    }
+
+    unsafe {
+        let array_index_event_index = (*table)
+            .event_array_index_event_index
+            .fetch_add(1, Ordering::AcqRel);
+        let array_index = (array_index_event_index >> 32) as usize;
+        let event_index = (array_index_event_index & 0xFFFF_FFFF) as usize;
+        if event_index < MAX_DEBUG_EVENT_COUNT {
+            (*table).events[array_index][event_index] = DebugEvent {
+                clock: read_cycle_counter(),
+                name_index,
+                thread_id: get_thread_id(),
+                event_type,
+            };
+        }
+    }
+}
+
+/// Returns the index of `name` in the table, registering it the first time
+/// around. `slot` caches the index (plus one) for the calling timed block.
+/// A reloaded game starts with empty slots, and finds its names already
+/// there rather than registering them again.
+fn get_debug_name_index(table: *mut DebugTable, slot: &AtomicU32, name: &str) -> Option<u32> {
+    let cached = slot.load(Ordering::Acquire);
+    if cached != 0 {
+        return Some(cached - 1);
+    }
+
+    unsafe {
+        let name_count = (*table).name_count.load(Ordering::Acquire) as usize;
+        // NOTE: Compared as stored, so a name too long for the table matches
+        // its cut down copy
+        let mut stored_name = [0u8; MAX_DEBUG_NAME_LENGTH];
+        copy_name(&mut stored_name, name);
+        if let Some(name_index) = (&(*table).names)[..name_count.min(MAX_DEBUG_NAME_COUNT)]
+            .iter()
+            .position(|existing_name| *existing_name == stored_name)
+        {
+            slot.store(name_index as u32 + 1, Ordering::Release);
+            return Some(name_index as u32);
+        }
+
+        if name_count >= MAX_DEBUG_NAME_COUNT {
+            return None;
+        }
+        let name_index = (*table).name_count.fetch_add(1, Ordering::AcqRel);
+        if name_index as usize >= MAX_DEBUG_NAME_COUNT {
+            return None;
+        }
+
+        copy_name(&mut (&mut (*table).names)[name_index as usize], name);
+        slot.store(name_index + 1, Ordering::Release);
+        Some(name_index)
+    }
+}
+
+/// Records the end of its block when dropped
+pub struct TimedBlock {
+    table: *mut DebugTable,
+    name_index: Option<u32>,
+}
+
+impl Drop for TimedBlock {
+    fn drop(&mut self) {
+        if let Some(name_index) = self.name_index {
+            record_debug_event(self.table, name_index, DebugEventType::EndBlock);
+        }
+    }
+}
+
+/// Use `timed_block!` instead.
+pub fn begin_timed_block(slot: &AtomicU32, name: &str) -> TimedBlock {
+    let table = GLOBAL_DEBUG_TABLE.load(Ordering::Acquire);
+    let name_index = if table.is_null() {
+        None
+    } else {
+        get_debug_name_index(table, slot, name)
+    };
+    if let Some(name_index) = name_index {
+        record_debug_event(table, name_index, DebugEventType::BeginBlock);
+    }
+    TimedBlock { table, name_index }
+}
+
+/// For the platform layer, once at the start of every frame
+pub fn record_frame_marker(seconds_elapsed: f32) {
+    record_debug_event(
+        GLOBAL_DEBUG_TABLE.load(Ordering::Acquire),
+        0,
+        DebugEventType::FrameMarker { seconds_elapsed },
+    );
+}
+
+/// Switches recording over to the other event array and returns the events
+/// recorded into this one. They stay valid until the next swap.
+///
+/// # Safety
+///
+/// `table` must be a valid table. Only one thread may swap at a time.
+pub unsafe fn swap_debug_event_arrays(table: *mut DebugTable) -> (&'static [DebugEvent], usize) {
+    let current = (*table)
+        .event_array_index_event_index
+        .load(Ordering::Acquire);
+    let array_index = (current >> 32) as usize;
+    let next_array_index = (array_index + 1) % 2;
+    let recorded = (*table)
+        .event_array_index_event_index
+        .swap((next_array_index as u64) << 32, Ordering::AcqRel);
+    let event_count = (recorded & 0xFFFF_FFFF) as usize;
+
+    let events = &(&(*table).events)[array_index][..event_count.min(MAX_DEBUG_EVENT_COUNT)];
+    (events, event_count - events.len())
+}
+
+/// Times the rest of the enclosing scope under `name`, a string literal.
+/// Works the same in the game and the platform layer.
+macro_rules! timed_block {
+    ($name:expr) => {
+        let _timed_block = {
+            static NAME_INDEX: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+            $crate::common::begin_timed_block(&NAME_INDEX, $name)
+        };
+    };
+}
+pub(crate) use timed_block;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_keys() -> Vec<Key> {
+        let mut keys = vec![
+            Key::Up,
+            Key::Down,
+            Key::Left,
+            Key::Right,
+            Key::Escape,
+            Key::Space,
+            Key::Enter,
+            Key::Tab,
+            Key::Backspace,
+            Key::Shift,
+            Key::Control,
+            Key::Alt,
+            Key::CapsLock,
+            Key::Insert,
+            Key::Delete,
+            Key::Home,
+            Key::End,
+            Key::PageUp,
+            Key::PageDown,
+            Key::NumpadAdd,
+            Key::NumpadSubtract,
+            Key::NumpadMultiply,
+            Key::NumpadDivide,
+            Key::NumpadDecimal,
+        ];
+        keys.extend(('A'..='Z').chain('0'..='9').map(Key::Char));
+        keys.extend(";=,-./`[\\]'".chars().map(Key::Char));
+        keys.extend((1..=24).map(Key::Function));
+        keys.extend((0..=9).map(Key::Numpad));
+        keys
+    }
+
+    #[test]
+    fn every_key_has_its_own_index() {
+        let mut indices: Vec<usize> = all_keys()
+            .into_iter()
+            .map(|key| key_index(key).expect("every key has an index"))
+            .collect();
+        assert!(indices.iter().all(|&index| index < KEY_COUNT));
+        let key_count = indices.len();
+        indices.sort_unstable();
+        indices.dedup();
+        assert_eq!(indices.len(), key_count);
+    }
+
+    #[test]
+    fn indices_name_their_keys() {
+        for key in all_keys() {
+            assert_eq!(key_from_index(key_index(key).unwrap()), Some(key));
+        }
+        for index in 0..KEY_COUNT {
+            if let Some(key) = key_from_index(index) {
+                assert_eq!(key_index(key), Some(index), "{:?}", key);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_keys_that_are_not_keys() {
+        assert_eq!(key_index(Key::Char('w')), key_index(Key::Char('W')));
+        for key in &[
+            Key::Char('\u{DC}'),
+            Key::Char('!'),
+            Key::Char(' '),
+            Key::Function(0),
+            Key::Function(25),
+            Key::Numpad(10),
+        ] {
+            assert_eq!(key_index(*key), None, "{:?}", key);
+        }
+
+        let input: GameInput = unsafe { zeroed() };
+        assert!(!is_key_down(&input, Key::Char('\u{1F600}')));
+        assert!(!was_key_pressed(&input, Key::Function(u8::MAX)));
+    }
+
+    #[test]
+    fn key_presses_count_once_down() {
+        let mut input: GameInput = unsafe { zeroed() };
+        let key = Key::Function(1);
+        assert!(!was_key_pressed(&input, key));
+
+        input.keys[key_index(key).unwrap()] = GameButtonState {
+            half_transition_count: 1,
+            ended_down: true,
+        };
+        assert!(was_key_pressed(&input, key));
+        assert!(is_key_down(&input, key));
+
+        // NOTE: Released since the last update, it was pressed before that
+        input.keys[key_index(key).unwrap()].ended_down = false;
+        assert!(!was_key_pressed(&input, key));
+
+        // NOTE: Tapped within one update
+        input.keys[key_index(key).unwrap()].half_transition_count = 2;
+        assert!(was_key_pressed(&input, key));
+        assert!(!is_key_down(&input, key));
+    }
+
+    #[test]
+    fn surrogate_pairs_come_through_as_one_char() {
+        let mut input: GameInput = unsafe { zeroed() };
+        let mut high_surrogate = 0;
+        for unit in "h\u{1F600}\r".encode_utf16() {
+            push_utf16_text_input(&mut input, &mut high_surrogate, unit);
+        }
+        assert_eq!(text_input(&input), &['h', '\u{1F600}', '\n']);
+        assert_eq!(high_surrogate, 0);
+
+        // NOTE: Either half on its own is dropped
+        push_utf16_text_input(&mut input, &mut high_surrogate, 0xDE00);
+        push_utf16_text_input(&mut input, &mut high_surrogate, 0xD83D);
+        push_utf16_text_input(&mut input, &mut high_surrogate, 'x' as u16);
+        assert_eq!(text_input(&input), &['h', '\u{1F600}', '\n', 'x']);
+    }
+
+    #[test]
+    fn text_input_past_the_limit_is_dropped() {
+        let mut input: GameInput = unsafe { zeroed() };
+        let mut high_surrogate = 0;
+        for _ in 0..MAX_TEXT_INPUT_COUNT - 1 {
+            push_utf16_text_input(&mut input, &mut high_surrogate, 'a' as u16);
+        }
+        for unit in "\u{1F600}b".encode_utf16() {
+            push_utf16_text_input(&mut input, &mut high_surrogate, unit);
+        }
+        let typed = text_input(&input);
+        assert_eq!(typed.len(), MAX_TEXT_INPUT_COUNT);
+        assert_eq!(typed[MAX_TEXT_INPUT_COUNT - 1], '\u{1F600}');
+    }
+}
@@ -0,0 +1,565 @@
+//! The debug overlay: a panel in the top right corner with a graph of recent
+//! frame times and whatever game state the caller wants to inspect, how full
+//! the memory arenas are on the left, and the last profiled frame along the
+//! bottom. F1 toggles it.
+
+use crate::common::*;
+use crate::font::*;
+use crate::profiler::*;
+use crate::render_group::*;
+
+const DEBUG_OVERLAY_KEY: Key = Key::Function(1);
+
+const FRAME_TIME_COUNT: usize = 120;
+
+const TEXT_SCALE: f32 = 2.0;
+const MARGIN: f32 = 4.0;
+const BAR_WIDTH: f32 = 2.0;
+const GRAPH_HEIGHT: f32 = 60.0;
+/// The top of the graph, in target frames
+const GRAPH_MAX_FRAMES: f32 = 2.0;
+
+/// The profiler and memory views are dense, so they use the font at its
+/// native size
+const DENSE_TEXT_SCALE: f32 = 1.0;
+const FLAME_ROW_HEIGHT: f32 = 10.0;
+const FLAME_LANE_GAP: f32 = 2.0;
+const MAX_PROFILE_TREE_LINES: usize = 24;
+const MEMORY_BAR_WIDTH: f32 = 240.0;
+const MEMORY_BAR_HEIGHT: f32 = 6.0;
+const FLAME_COLORS: [(f32, f32, f32); 6] = [
+    (0.8, 0.3, 0.2),
+    (0.2, 0.5, 0.8),
+    (0.3, 0.7, 0.3),
+    (0.7, 0.5, 0.1),
+    (0.6, 0.3, 0.7),
+    (0.2, 0.6, 0.6),
+];
+
+/// Lives on the heap rather than in game memory, so replaying a loop doesn't
+/// rewind it.
+pub struct DebugOverlay {
+    pub is_visible: bool,
+    /// Measured frame times in seconds, oldest first from
+    /// `next_frame_time_index`. 0 where no frame was recorded yet.
+    frame_times: [f32; FRAME_TIME_COUNT],
+    next_frame_time_index: usize,
+}
+
+pub fn make_debug_overlay() -> DebugOverlay {
+    DebugOverlay {
+        is_visible: false,
+        frame_times: [0.0; FRAME_TIME_COUNT],
+        next_frame_time_index: 0,
+    }
+}
+
+/// Call every update with the live input, whether the overlay is visible or
+/// not, so the graph is already filled in when it is opened.
+pub fn update_debug_overlay(overlay: &mut DebugOverlay, input: &GameInput) {
+    if was_key_pressed(input, DEBUG_OVERLAY_KEY) {
+        overlay.is_visible = !overlay.is_visible;
+    }
+
+    if input.frame_seconds > 0.0 {
+        overlay.frame_times[overlay.next_frame_time_index] = input.frame_seconds;
+        overlay.next_frame_time_index = (overlay.next_frame_time_index + 1) % FRAME_TIME_COUNT;
+    }
+}
+
+fn frame_time_summary(overlay: &DebugOverlay) -> String {
+    let recorded = overlay.frame_times.iter().filter(|&&seconds| seconds > 0.0);
+    let count = recorded.clone().count();
+    if count == 0 {
+        return "No frames yet".to_string();
+    }
+
+    let average = recorded.clone().sum::<f32>() / count as f32;
+    let max = recorded.fold(0.0, |max: f32, &seconds| max.max(seconds));
+    format!(
+        "FPS {:.1}  {:.1}ms avg  {:.1}ms max",
+        1.0 / average,
+        1000.0 * average,
+        1000.0 * max
+    )
+}
+
+/// Pushes the overlay, if it is visible, with `text` under the frame time
+/// graph. `text` can have several lines.
+#[allow(clippy::too_many_arguments)]
+pub fn push_debug_overlay<'a>(
+    render_group: &mut RenderGroup<'a>,
+    sort_key: f32,
+    overlay: &DebugOverlay,
+    font: &'a LoadedFont,
+    buffer_width: i32,
+    target_seconds_per_frame: f32,
+    text: &str,
+) {
+    if !overlay.is_visible {
+        return;
+    }
+
+    let text = format!("{}\n{}", frame_time_summary(overlay), text);
+    let line_count = text.lines().count();
+    let graph_width = BAR_WIDTH * FRAME_TIME_COUNT as f32;
+    let width = graph_width.max(text_width(font, &text, TEXT_SCALE)) + 2.0 * MARGIN;
+    let height =
+        GRAPH_HEIGHT + TEXT_SCALE * (line_count as i32 * font.line_height) as f32 + 3.0 * MARGIN;
+
+    let min_x = buffer_width as f32 - width;
+    let min_y = 0.0;
+    push_rectangle(
+        render_group,
+        sort_key,
+        min_x,
+        min_y,
+        min_x + width,
+        min_y + height,
+        0.0,
+        0.0,
+        0.0,
+    );
+
+    // NOTE: One bar per frame, oldest on the left, red when the frame missed
+    // the target
+    let graph_min_x = min_x + MARGIN;
+    let graph_max_y = min_y + MARGIN + GRAPH_HEIGHT;
+    let pixels_per_second = GRAPH_HEIGHT / (GRAPH_MAX_FRAMES * target_seconds_per_frame);
+    for bar_index in 0..FRAME_TIME_COUNT {
+        let seconds =
+            overlay.frame_times[(overlay.next_frame_time_index + bar_index) % FRAME_TIME_COUNT];
+        if seconds <= 0.0 {
+            continue;
+        }
+
+        let bar_height = (pixels_per_second * seconds).min(GRAPH_HEIGHT);
+        let (r, g) = if seconds > 1.05 * target_seconds_per_frame {
+            (1.0, 0.2)
+        } else {
+            (0.2, 1.0)
+        };
+        let bar_min_x = graph_min_x + BAR_WIDTH * bar_index as f32;
+        push_rectangle(
+            render_group,
+            sort_key,
+            bar_min_x,
+            graph_max_y - bar_height,
+            bar_min_x + BAR_WIDTH,
+            graph_max_y,
+            r,
+            g,
+            0.2,
+        );
+    }
+
+    let target_y = graph_max_y - pixels_per_second * target_seconds_per_frame;
+    push_rectangle(
+        render_group,
+        sort_key,
+        graph_min_x,
+        target_y,
+        graph_min_x + graph_width,
+        target_y + 1.0,
+        1.0,
+        1.0,
+        0.0,
+    );
+
+    push_text(
+        render_group,
+        sort_key,
+        font,
+        min_x + MARGIN,
+        graph_max_y + MARGIN,
+        TEXT_SCALE,
+        &text,
+    );
+}
+
+/// Pushes the last profiled frame along the bottom of the buffer, if the
+/// overlay is visible: a flame graph with a lane for each thread, and the
+/// block timings as a tree below it.
+#[allow(clippy::too_many_arguments)]
+pub fn push_profiler_view<'a>(
+    render_group: &mut RenderGroup<'a>,
+    sort_key: f32,
+    overlay: &DebugOverlay,
+    profiler: &Profiler,
+    font: &'a LoadedFont,
+    buffer_width: i32,
+    buffer_height: i32,
+) {
+    if !overlay.is_visible {
+        return;
+    }
+    let frame = match get_last_profiled_frame(profiler) {
+        Some(frame) => frame,
+        None => return,
+    };
+
+    // NOTE: Threads get a lane each, in the order they first show up
+    let mut lanes: Vec<(u32, u32)> = Vec::new();
+    for block in &frame.blocks {
+        match lanes
+            .iter_mut()
+            .find(|(thread_id, _)| *thread_id == block.thread_id)
+        {
+            Some((_, row_count)) => *row_count = (*row_count).max(block.depth + 1),
+            None => lanes.push((block.thread_id, block.depth + 1)),
+        }
+    }
+    let row_count: u32 = lanes.iter().map(|&(_, row_count)| row_count).sum();
+    let flame_height =
+        FLAME_ROW_HEIGHT * row_count as f32 + FLAME_LANE_GAP * lanes.len().saturating_sub(1) as f32;
+
+    let mut lines = vec![format!(
+        "Frame {:.2}ms, {} blocks, {} events dropped, F2 writes {}",
+        1000.0 * frame.seconds_elapsed,
+        frame.blocks.len(),
+        frame.dropped_event_count,
+        CHROME_TRACE_FILE_NAME
+    )];
+    lines.extend(get_profile_tree_lines(profiler));
+    lines.truncate(MAX_PROFILE_TREE_LINES);
+    let text = lines.join("\n");
+    let line_height = DENSE_TEXT_SCALE * font.line_height as f32;
+
+    let width = buffer_width as f32;
+    let height = flame_height + line_height * lines.len() as f32 + 3.0 * MARGIN;
+    let min_x = 0.0;
+    let min_y = buffer_height as f32 - height;
+    push_rectangle(
+        render_group,
+        sort_key,
+        min_x,
+        min_y,
+        min_x + width,
+        min_y + height,
+        0.0,
+        0.0,
+        0.0,
+    );
+
+    let flame_min_x = min_x + MARGIN;
+    let flame_width = width - 2.0 * MARGIN;
+    let frame_cycles = (frame.end_clock - frame.begin_clock).max(1) as f32;
+    let glyph_width = get_glyph(font, 'M').map_or(1, |glyph| glyph.advance.max(1)) as f32;
+    let mut lane_min_y = min_y + MARGIN;
+    for &(thread_id, lane_row_count) in &lanes {
+        for block in frame
+            .blocks
+            .iter()
+            .filter(|block| block.thread_id == thread_id)
+        {
+            let end_clock = if block.end_clock == 0 {
+                frame.end_clock
+            } else {
+                block.end_clock
+            };
+            let bar_min_x = flame_min_x
+                + flame_width * block.begin_clock.saturating_sub(frame.begin_clock) as f32
+                    / frame_cycles;
+            let bar_max_x = flame_min_x
+                + flame_width * end_clock.saturating_sub(frame.begin_clock) as f32 / frame_cycles;
+            let bar_min_y = lane_min_y + FLAME_ROW_HEIGHT * block.depth as f32;
+            let (r, g, b) = FLAME_COLORS[block.name % FLAME_COLORS.len()];
+            push_rectangle(
+                render_group,
+                sort_key,
+                bar_min_x,
+                bar_min_y,
+                bar_max_x.max(bar_min_x + 1.0),
+                bar_min_y + FLAME_ROW_HEIGHT - 1.0,
+                r,
+                g,
+                b,
+            );
+
+            // NOTE: Labels are cut to fit their bar, and left off if even
+            // that would be unreadable
+            let fit_count =
+                ((bar_max_x - bar_min_x - 2.0) / (DENSE_TEXT_SCALE * glyph_width)) as usize;
+            if fit_count >= 3 {
+                let label: String = get_profiled_block_name(profiler, block)
+                    .chars()
+                    .take(fit_count)
+                    .collect();
+                push_text(
+                    render_group,
+                    sort_key,
+                    font,
+                    bar_min_x + 1.0,
+                    bar_min_y,
+                    DENSE_TEXT_SCALE,
+                    &label,
+                );
+            }
+        }
+        lane_min_y += FLAME_ROW_HEIGHT * lane_row_count as f32 + FLAME_LANE_GAP;
+    }
+
+    push_text(
+        render_group,
+        sort_key,
+        font,
+        min_x + MARGIN,
+        min_y + 2.0 * MARGIN + flame_height,
+        DENSE_TEXT_SCALE,
+        &text,
+    );
+}
+
+/// `bytes` in the biggest unit that keeps it above 1
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit_index = 0;
+    while value >= 1024.0 && unit_index + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit_index += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit_index])
+}
+
+fn get_arena_summary(arena: &MemoryArena) -> String {
+    format!(
+        "{}: {} of {} used ({:.1}%), peak {}",
+        get_name(&arena.name),
+        format_bytes(arena.used),
+        format_bytes(arena.size),
+        100.0 * arena.used as f64 / arena.size.max(1) as f64,
+        format_bytes(arena.high_water_mark)
+    )
+}
+
+/// A line for the arena, then one for each tag in debug builds
+pub fn get_arena_report_lines(arena: &MemoryArena) -> Vec<String> {
+    // NOTE: Release builds have no tags to add
+    #[cfg_attr(not(debug_assertions), allow(unused_mut))]
+    let mut lines = vec![get_arena_summary(arena)];
+    #[cfg(debug_assertions)]
+    {
+        let mut tagged_size = 0;
+        for tag in get_arena_tags(arena) {
+            lines.push(format!(
+                "  {}: {} in {}",
+                get_name(&tag.name),
+                format_bytes(tag.size),
+                tag.count
+            ));
+            tagged_size += tag.size;
+        }
+        if arena.used > tagged_size {
+            lines.push(format!(
+                "  alignment: {}",
+                format_bytes(arena.used - tagged_size)
+            ));
+        }
+    }
+    lines
+}
+
+/// Pushes a fill bar for each arena, if the overlay is visible, starting at
+/// `min_y` on the left. In debug builds the bar is split up by tag. The
+/// yellow mark is the high water mark.
+pub fn push_memory_view<'a>(
+    render_group: &mut RenderGroup<'a>,
+    sort_key: f32,
+    overlay: &DebugOverlay,
+    arenas: &[&MemoryArena],
+    font: &'a LoadedFont,
+    min_y: f32,
+) {
+    if !overlay.is_visible {
+        return;
+    }
+
+    let summaries: Vec<String> = arenas
+        .iter()
+        .map(|arena| get_arena_summary(arena))
+        .collect();
+    let line_height = DENSE_TEXT_SCALE * font.line_height as f32;
+    let text_width = summaries
+        .iter()
+        .map(|summary| text_width(font, summary, DENSE_TEXT_SCALE))
+        .fold(0.0, f32::max);
+    let width = MEMORY_BAR_WIDTH.max(text_width) + 2.0 * MARGIN;
+    let height = (line_height + MEMORY_BAR_HEIGHT + MARGIN) * arenas.len() as f32 + MARGIN;
+    let min_x = 0.0;
+    push_rectangle(
+        render_group,
+        sort_key,
+        min_x,
+        min_y,
+        min_x + width,
+        min_y + height,
+        0.0,
+        0.0,
+        0.0,
+    );
+
+    let bar_min_x = min_x + MARGIN;
+    let mut row_min_y = min_y + MARGIN;
+    for (arena, summary) in arenas.iter().zip(&summaries) {
+        push_text(
+            render_group,
+            sort_key,
+            font,
+            bar_min_x,
+            row_min_y,
+            DENSE_TEXT_SCALE,
+            summary,
+        );
+
+        let bar_min_y = row_min_y + line_height;
+        let bar_max_y = bar_min_y + MEMORY_BAR_HEIGHT;
+        let pixels_per_byte = MEMORY_BAR_WIDTH / arena.size.max(1) as f32;
+        push_rectangle(
+            render_group,
+            sort_key,
+            bar_min_x,
+            bar_min_y,
+            bar_min_x + MEMORY_BAR_WIDTH,
+            bar_max_y,
+            0.2,
+            0.2,
+            0.2,
+        );
+
+        // NOTE: Anything in use gets at least a pixel, or a 64 MB arena with
+        // a few KB in it would look empty
+        let mut segments: Vec<usize> = Vec::new();
+        #[cfg(debug_assertions)]
+        segments.extend(get_arena_tags(arena).iter().map(|tag| tag.size));
+        let tagged_size: usize = segments.iter().sum();
+        segments.push(arena.used.saturating_sub(tagged_size));
+        let mut segment_min_x = bar_min_x;
+        for (segment_index, &segment_size) in segments.iter().enumerate() {
+            if segment_size == 0 {
+                continue;
+            }
+            let segment_width = (pixels_per_byte * segment_size as f32).max(1.0);
+            let (r, g, b) = FLAME_COLORS[segment_index % FLAME_COLORS.len()];
+            push_rectangle(
+                render_group,
+                sort_key,
+                segment_min_x,
+                bar_min_y,
+                segment_min_x + segment_width,
+                bar_max_y,
+                r,
+                g,
+                b,
+            );
+            segment_min_x += segment_width;
+        }
+
+        let high_water_x = (bar_min_x + pixels_per_byte * arena.high_water_mark as f32)
+            .max(segment_min_x)
+            .min(bar_min_x + MEMORY_BAR_WIDTH - 1.0);
+        if arena.high_water_mark > 0 {
+            push_rectangle(
+                render_group,
+                sort_key,
+                high_water_x,
+                bar_min_y - 1.0,
+                high_water_x + 1.0,
+                bar_max_y + 1.0,
+                1.0,
+                1.0,
+                0.0,
+            );
+        }
+
+        row_min_y = bar_max_y + MARGIN;
+    }
+}
+
+/// The replay slots on one line, the selected one in brackets
+pub fn get_replay_status_line(status: &ReplayStatus) -> String {
+    let slots: Vec<String> = status
+        .slots
+        .iter()
+        .enumerate()
+        .map(|(slot_index, slot)| {
+            let slot = match slot {
+                ReplaySlotState::Empty => "-",
+                ReplaySlotState::Recorded => "saved",
+                ReplaySlotState::Recording => "rec",
+                ReplaySlotState::Looping => "loop",
+            };
+            if slot_index == status.selected_slot {
+                format!("[{} {}]", slot_index + 1, slot)
+            } else {
+                format!("{} {}", slot_index + 1, slot)
+            }
+        })
+        .collect();
+    format!("Replay {}", slots.join(" "))
+}
+
+/// Pushes a REC or LOOP tag at the top middle of the buffer while a replay
+/// slot is recording or looping, whether the overlay is visible or not. A
+/// looping slot also shows where it is and how fast it is going.
+pub fn push_replay_indicator<'a>(
+    render_group: &mut RenderGroup<'a>,
+    sort_key: f32,
+    status: &ReplayStatus,
+    font: &'a LoadedFont,
+    buffer_width: i32,
+) {
+    let active = status.slots.iter().enumerate().find(|(_, slot)| {
+        **slot == ReplaySlotState::Recording || **slot == ReplaySlotState::Looping
+    });
+    let (slot_index, slot) = match active {
+        Some(active) => active,
+        None => return,
+    };
+
+    let (text, r, g, b) = if *slot == ReplaySlotState::Recording {
+        (format!("REC {}", slot_index + 1), 0.8, 0.1, 0.1)
+    } else {
+        (
+            format!(
+                "LOOP {} {}/{} {}x{}",
+                slot_index + 1,
+                status.frame_index,
+                status.frame_count,
+                status.speed,
+                if status.is_paused { " paused" } else { "" }
+            ),
+            0.1,
+            0.6,
+            0.1,
+        )
+    };
+    let width = text_width(font, &text, TEXT_SCALE) + 2.0 * MARGIN;
+    let height = TEXT_SCALE * font.line_height as f32 + 2.0 * MARGIN;
+    let min_x = 0.5 * (buffer_width as f32 - width);
+    let min_y = 0.0;
+    push_rectangle(
+        render_group,
+        sort_key,
+        min_x,
+        min_y,
+        min_x + width,
+        min_y + height,
+        r,
+        g,
+        b,
+    );
+    push_text(
+        render_group,
+        sort_key,
+        font,
+        min_x + MARGIN,
+        min_y + MARGIN,
+        TEXT_SCALE,
+        &text,
+    );
+}
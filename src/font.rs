@@ -0,0 +1,356 @@
+//! Bitmap fonts in the BMFont text format, with every glyph packed into a
+//! single atlas page that is loaded like any other bitmap.
+//!
+//! ```text
+//! common lineHeight=9 scaleW=96 scaleH=48 pages=1
+//! page id=0 file="Font.png"
+//! char id=65 x=6 y=16 width=6 height=8 xoffset=0 yoffset=0 xadvance=6
+//! kerning first=65 second=86 amount=-1
+//! ```
+
+use crate::asset::*;
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// Where a glyph is in the atlas and how to place it, in font pixels
+#[derive(Clone, Copy, Debug)]
+pub struct FontGlyph {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// From the pen position to the top left corner of the glyph
+    pub offset_x: i32,
+    pub offset_y: i32,
+    /// How far the pen moves after drawing the glyph
+    pub advance: i32,
+}
+
+pub struct LoadedFont {
+    pub atlas: LoadedBitmap,
+    /// Distance from the top of one line to the top of the next
+    pub line_height: i32,
+    /// Sorted by character. These are Vecs so a zeroed font is a valid
+    /// empty one, like the rest of the game state.
+    glyphs: Vec<(char, FontGlyph)>,
+    kerning: Vec<((char, char), i32)>,
+}
+
+/// Drawn for characters the font has no glyph for
+const FALLBACK_CHARACTER: char = '?';
+
+fn invalid_font(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Splits a line like `char id=65 x=6` into its tag and key value pairs.
+/// Values can be quoted to contain spaces.
+fn parse_font_line(line: &str) -> (&str, HashMap<&str, &str>) {
+    let line = line.trim();
+    let tag_end = line.find(' ').unwrap_or(line.len());
+    let (tag, mut rest) = line.split_at(tag_end);
+
+    let mut pairs = HashMap::new();
+    loop {
+        rest = rest.trim_start();
+        let equals = match rest.find('=') {
+            Some(equals) => equals,
+            None => break,
+        };
+        let key = rest[..equals].trim();
+        rest = &rest[equals + 1..];
+
+        let value;
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let quote = quoted.find('"').unwrap_or(quoted.len());
+            value = &quoted[..quote];
+            rest = quoted.get(quote + 1..).unwrap_or("");
+        } else {
+            let value_end = rest.find(' ').unwrap_or(rest.len());
+            value = &rest[..value_end];
+            rest = &rest[value_end..];
+        }
+        pairs.insert(key, value);
+    }
+
+    (tag, pairs)
+}
+
+fn font_value<T: std::str::FromStr>(
+    pairs: &HashMap<&str, &str>,
+    key: &str,
+    line_number: usize,
+) -> io::Result<T> {
+    pairs
+        .get(key)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| invalid_font(format!("line {}: missing or bad {}", line_number, key)))
+}
+
+fn font_char(pairs: &HashMap<&str, &str>, key: &str, line_number: usize) -> io::Result<char> {
+    std::char::from_u32(font_value(pairs, key, line_number)?)
+        .ok_or_else(|| invalid_font(format!("line {}: bad character {}", line_number, key)))
+}
+
+/// Loads a BMFont text file and its atlas page, which is looked up next to
+/// the font file. Only single page fonts are supported.
+pub fn load_font(file_name: &str) -> io::Result<LoadedFont> {
+    let text = fs::read_to_string(file_name)?;
+
+    let mut line_height = None;
+    let mut page_file_name = None;
+    let mut glyphs = Vec::new();
+    let mut kerning = Vec::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let (tag, pairs) = parse_font_line(line);
+        match tag {
+            "common" => {
+                line_height = Some(font_value(&pairs, "lineHeight", line_number)?);
+                if pairs.get("pages").is_some_and(|&pages| pages != "1") {
+                    return Err(invalid_font("only single page fonts are supported".into()));
+                }
+            }
+            "page" => {
+                let page_file = pairs
+                    .get("file")
+                    .ok_or_else(|| invalid_font(format!("line {}: missing file", line_number)))?;
+                page_file_name = Some(Path::new(file_name).with_file_name(page_file));
+            }
+            "char" => {
+                glyphs.push((
+                    font_char(&pairs, "id", line_number)?,
+                    FontGlyph {
+                        x: font_value(&pairs, "x", line_number)?,
+                        y: font_value(&pairs, "y", line_number)?,
+                        width: font_value(&pairs, "width", line_number)?,
+                        height: font_value(&pairs, "height", line_number)?,
+                        offset_x: font_value(&pairs, "xoffset", line_number)?,
+                        offset_y: font_value(&pairs, "yoffset", line_number)?,
+                        advance: font_value(&pairs, "xadvance", line_number)?,
+                    },
+                ));
+            }
+            "kerning" => {
+                kerning.push((
+                    (
+                        font_char(&pairs, "first", line_number)?,
+                        font_char(&pairs, "second", line_number)?,
+                    ),
+                    font_value(&pairs, "amount", line_number)?,
+                ));
+            }
+            // NOTE: info, chars and kernings are only informational
+            _ => {}
+        }
+    }
+
+    let line_height = line_height.ok_or_else(|| invalid_font("no common line".into()))?;
+    let page_file_name = page_file_name.ok_or_else(|| invalid_font("no page line".into()))?;
+    glyphs.sort_by_key(|&(c, _)| c);
+    kerning.sort_by_key(|&(pair, _)| pair);
+    let atlas = load_bitmap(&page_file_name.to_string_lossy())
+        .map_err(|e| invalid_font(format!("could not load {:?}: {}", page_file_name, e)))?;
+
+    for (c, glyph) in &glyphs {
+        // NOTE: These can overflow, the numbers are straight from the file
+        let right = glyph.x.checked_add(glyph.width);
+        let bottom = glyph.y.checked_add(glyph.height);
+        if right.is_none_or(|right| right > atlas.width)
+            || bottom.is_none_or(|bottom| bottom > atlas.height)
+        {
+            return Err(invalid_font(format!("glyph {:?} is outside the atlas", c)));
+        }
+    }
+
+    Ok(LoadedFont {
+        atlas,
+        line_height,
+        glyphs,
+        kerning,
+    })
+}
+
+/// The glyph for `c`, or the fallback glyph if the font doesn't have one
+pub fn get_glyph(font: &LoadedFont, c: char) -> Option<FontGlyph> {
+    let find = |c| {
+        font.glyphs
+            .binary_search_by_key(&c, |&(glyph_c, _)| glyph_c)
+            .ok()
+            .map(|index| font.glyphs[index].1)
+    };
+    find(c).or_else(|| find(FALLBACK_CHARACTER))
+}
+
+/// Extra pen movement between `previous` and `c`, in font pixels
+pub fn get_kerning(font: &LoadedFont, previous: char, c: char) -> i32 {
+    font.kerning
+        .binary_search_by_key(&(previous, c), |&(pair, _)| pair)
+        .map(|index| font.kerning[index].1)
+        .unwrap_or(0)
+}
+
+/// Width of the widest line of `text` in buffer pixels, at `scale`
+pub fn text_width(font: &LoadedFont, text: &str, scale: f32) -> f32 {
+    let mut widest = 0;
+    for line in text.lines() {
+        let mut width = 0;
+        let mut previous = None;
+        for c in line.chars() {
+            if let Some(previous) = previous {
+                width += get_kerning(font, previous, c);
+            }
+            if let Some(glyph) = get_glyph(font, c) {
+                width += glyph.advance;
+            }
+            previous = Some(c);
+        }
+        widest = widest.max(width);
+    }
+    scale * widest as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf};
+
+    /// Writes `text` as a font next to a copy of the game's 96 by 48 atlas
+    fn write_test_font(name: &str, text: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("font_test_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&directory).unwrap();
+        let atlas_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/assets/Font.png");
+        fs::copy(atlas_path, directory.join("Atlas.png")).unwrap();
+        let font_path = directory.join("Test.fnt");
+        fs::write(&font_path, text).unwrap();
+        font_path
+    }
+
+    fn load_test_font(name: &str, text: &str) -> io::Result<LoadedFont> {
+        let font_path = write_test_font(name, text);
+        let result = load_font(font_path.to_str().unwrap());
+        fs::remove_dir_all(font_path.parent().unwrap()).unwrap();
+        result
+    }
+
+    fn get_load_error(name: &str, text: &str) -> String {
+        match load_test_font(name, text) {
+            Ok(_) => panic!("the font loaded"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    const TEST_FONT: &str = "info face=\"Test Font\" size=8\n\
+        common lineHeight=9 base=7 scaleW=96 scaleH=48 pages=1\n\
+        page id=0 file=\"Atlas.png\"\n\
+        chars count=3\n\
+        char id=86 x=12 y=0 width=6 height=8 xoffset=0 yoffset=1 xadvance=6\n\
+        char id=65 x=6 y=0 width=6 height=8 xoffset=-1 yoffset=0 xadvance=5\n\
+        char id=63 x=0 y=0 width=6 height=8 xoffset=0 yoffset=0 xadvance=4\n\
+        kernings count=1\n\
+        kerning first=65 second=86 amount=-2\n";
+
+    #[test]
+    fn splits_lines_into_pairs() {
+        let (tag, pairs) = parse_font_line("  page id=0 file=\"My Atlas.png\" extra=1 ");
+        assert_eq!(tag, "page");
+        assert_eq!(pairs.get("id"), Some(&"0"));
+        assert_eq!(pairs.get("file"), Some(&"My Atlas.png"));
+        assert_eq!(pairs.get("extra"), Some(&"1"));
+
+        let (tag, pairs) = parse_font_line("chars");
+        assert_eq!(tag, "chars");
+        assert!(pairs.is_empty());
+    }
+
+    #[test]
+    fn loads_glyphs_and_kerning() {
+        let font = load_test_font("loads", TEST_FONT).unwrap();
+        assert_eq!(font.line_height, 9);
+        assert_eq!((font.atlas.width, font.atlas.height), (96, 48));
+
+        let glyph = get_glyph(&font, 'A').unwrap();
+        assert_eq!((glyph.x, glyph.y, glyph.width, glyph.height), (6, 0, 6, 8));
+        assert_eq!((glyph.offset_x, glyph.advance), (-1, 5));
+        // NOTE: Characters the font doesn't have are drawn as '?'
+        assert_eq!(get_glyph(&font, 'Z').unwrap().advance, 4);
+
+        assert_eq!(get_kerning(&font, 'A', 'V'), -2);
+        assert_eq!(get_kerning(&font, 'V', 'A'), 0);
+        assert_eq!(text_width(&font, "AV", 1.0), 9.0);
+        assert_eq!(text_width(&font, "V\nAZZ", 2.0), 26.0);
+    }
+
+    #[test]
+    fn rejects_bad_fonts() {
+        let page = "page id=0 file=\"Atlas.png\"\n";
+        let common = "common lineHeight=9 pages=1\n";
+        let bad_fonts = [
+            ("no_common", page.to_string(), "no common line"),
+            ("no_page", common.to_string(), "no page line"),
+            (
+                "pages",
+                format!("common lineHeight=9 pages=2\n{}", page),
+                "only single page",
+            ),
+            (
+                "line_height",
+                format!("common lineHeight=tall\n{}", page),
+                "line 1: missing or bad lineHeight",
+            ),
+            (
+                "page_file",
+                format!("{}page id=0\n", common),
+                "line 2: missing file",
+            ),
+            (
+                "char_value",
+                format!(
+                    "{}{}char id=65 x=6 y=0 width=6 xoffset=0 yoffset=0 xadvance=6\n",
+                    common, page
+                ),
+                "line 3: missing or bad height",
+            ),
+            (
+                "char_id",
+                format!("{}{}kerning first=55296 second=65 amount=1\n", common, page),
+                "line 3: bad character first",
+            ),
+            (
+                "outside",
+                format!(
+                    "{}{}char id=65 x=90 y=0 width=7 height=8 xoffset=0 yoffset=0 xadvance=6\n",
+                    common, page
+                ),
+                "outside the atlas",
+            ),
+            (
+                "overflow",
+                format!(
+                    "{}{}char id=65 x=4294967295 y=0 width=7 height=8 xoffset=0 yoffset=0 \
+                     xadvance=6\n",
+                    common, page
+                ),
+                "outside the atlas",
+            ),
+            (
+                "atlas",
+                format!("{}page id=0 file=\"Missing.png\"\n", common),
+                "could not load",
+            ),
+        ];
+        for (name, text, message) in bad_fonts.iter() {
+            let error = get_load_error(name, text);
+            assert!(error.contains(message), "{} should say {}", error, message);
+        }
+    }
+
+    #[test]
+    fn loads_the_game_font() {
+        let font_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/assets/Font.fnt");
+        let font = load_font(font_path.to_str().unwrap()).unwrap();
+        for c in ' '..='~' {
+            assert!(get_glyph(&font, c).is_some(), "{:?}", c);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Stops panics in the game code at its entry points, before they unwind into
+//! the platform layer and abort the process. The platform layer gets a
+//! `GameError` in `GameMemory` instead, and decides what to do about it.
+
+use crate::common::*;
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{Mutex, Once},
+};
+
+static INSTALL_PANIC_HOOK: Once = Once::new();
+
+/// File, line and column of the last panic, which the payload doesn't carry.
+/// Not thread local, a panic on a worker thread is picked up on the main one.
+static LAST_PANIC_LOCATION: Mutex<Option<(String, u32, u32)>> = Mutex::new(None);
+
+/// The hook belongs to this copy of the game code's std, so every reload
+/// installs its own
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let (Some(location), Ok(mut last_location)) =
+            (info.location(), LAST_PANIC_LOCATION.lock())
+        {
+            *last_location = Some((
+                location.file().to_string(),
+                location.line(),
+                location.column(),
+            ));
+        }
+        // NOTE: Still print it, with a backtrace when RUST_BACKTRACE asks
+        default_hook(info);
+    }));
+}
+
+fn get_panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "panicked with something other than a message"
+    }
+}
+
+/// Runs `body`, which is the whole of an entry point. Returns false if it
+/// panicked, with what happened in `memory.error`.
+///
+/// # Safety
+///
+/// `memory` must be valid.
+pub unsafe fn catch_game_panic(memory: *mut GameMemory, body: impl FnOnce()) -> bool {
+    INSTALL_PANIC_HOOK.call_once(install_panic_hook);
+
+    let payload = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(()) => return true,
+        Err(payload) => payload,
+    };
+    let (file, line, column) = LAST_PANIC_LOCATION
+        .lock()
+        .ok()
+        .and_then(|mut last_location| last_location.take())
+        .unwrap_or_else(|| ("unknown".to_string(), 0, 0));
+
+    let error = &mut (*memory).error;
+    copy_name(&mut error.message, get_panic_message(&*payload));
+    copy_name(&mut error.file, &file);
+    error.line = line;
+    error.column = column;
+    error.frame_index = (*memory).update_count;
+    false
+}
+
+/// For work queue callbacks, they are entry points too. Hand what comes back
+/// to `resume_worker_panic` on the main thread once the work is done.
+pub fn catch_worker_panic(body: impl FnOnce()) -> Option<Box<dyn Any + Send>> {
+    panic::catch_unwind(AssertUnwindSafe(body)).err()
+}
+
+/// Carries on with a panic caught on a worker thread, on this one, so the
+/// entry point reports it
+pub fn resume_worker_panic(payload: Option<Box<dyn Any + Send>>) {
+    if let Some(payload) = payload {
+        panic::resume_unwind(payload);
+    }
+}
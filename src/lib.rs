@@ -1,18 +1,46 @@
 //! equivalent to handmade.h & handmade.cpp
 
+mod asset;
+mod audio;
 pub mod common;
+mod debug;
+mod font;
+mod game_error;
+mod profiler;
+mod rasterizer;
+mod render_group;
+mod save;
 mod tile;
 
+use asset::*;
+use audio::*;
 use common::*;
 use core::mem::*;
-use image::{DynamicImage, GenericImageView};
+use debug::*;
+use font::*;
+use game_error::*;
+use profiler::*;
 use rand::prelude::*;
+use render_group::*;
+use save::*;
 use tile::*;
 
 #[macro_use]
 extern crate log;
 
-use std::f32;
+use std::{
+    env, f32, ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Once,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static INITIALIZE_LOGGING: Once = Once::new();
+/// Set by `load_heap_state` and never freed. Null again after the game code
+/// is reloaded, until the next time it's needed.
+static HEAP_STATE: AtomicPtr<HeapState> = AtomicPtr::new(ptr::null_mut());
 
 struct World {
     tile_map: TileMap,
@@ -21,63 +49,434 @@ struct World {
 struct CharacterImage {
     align_x: i32,
     align_y: i32,
-    image: DynamicImage,
+    image: LoadedBitmap,
     frame_width: u32,
     frames: u32,
 }
 
 struct State {
     world_arena: MemoryArena,
-    world: *mut World,
+    world: RelativePointer<World>,
+    /// All of transient storage, cleared every frame. The sound mix takes its
+    /// per-frame scratch buffers from it.
+    transient_arena: MemoryArena,
 
     camera_p: TileMapPosition,
+    camera_zoom: f32,
     player_p: TileMapPosition,
 
-    backdrop: DynamicImage,
+    /// Where the player is walking to after a click
+    move_target: TileMapPosition,
+    has_move_target: bool,
+
+    /// The position under the mouse, on the camera's floor. Kept up to date
+    /// every frame for debugging and editing tools.
+    hovered_p: TileMapPosition,
+
+    backdrop: LoadedBitmap,
+    font: LoadedFont,
 
     character_image: CharacterImage,
     character_walk_frame: u32,
+
+    audio_state: AudioState,
+    footstep_sound: SoundId,
+    /// Kept in game state so replays and moved snapshots vary the steps the
+    /// same way
+    footstep_rng: StdRng,
+
+    /// What the world was generated from, the config's seed if it had one
+    world_seed: u64,
+
+    /// Shown after the tile in the HUD until `hud_message_seconds` runs out.
+    /// The first `hud_message_length` bytes are UTF-8.
+    hud_message: [u8; MAX_HUD_MESSAGE_LENGTH],
+    hud_message_length: usize,
+    hud_message_seconds: f32,
+
+    /// Allocated on the first update and never freed
+    debug_overlay: *mut DebugOverlay,
+    profiler: *mut Profiler,
+    /// `GameMemory::heap_generation` the heap memory above was loaded in
+    heap_generation: u64,
+}
+
+/// A copy of what `State` holds on the heap, as this run loaded it. Game
+/// memory restored from an earlier run, say from a replay slot it recorded,
+/// points at what that run loaded, so it is handed these instead.
+struct HeapState {
+    backdrop: LoadedBitmap,
+    font: LoadedFont,
+    character_image: LoadedBitmap,
+    sounds: Vec<LoadedSound>,
+    debug_overlay: *mut DebugOverlay,
+    profiler: *mut Profiler,
+}
+
+/// Where the asset called `file_name` is, in the data directory the platform
+/// layer was configured with
+fn get_asset_path(memory: &GameMemory, file_name: &str) -> String {
+    format!(
+        "{}/assets/{}",
+        get_name(&memory.config.data_directory),
+        file_name
+    )
 }
 
 /// This ensures that GameUpdateAndRender has a signature that will match what
 /// is specified in handmade_platform.rs
 const _UPDATE_CHECK: GameUpdateAndRender = update_and_render;
+const _SOUND_CHECK: GameGetSoundSamples = get_sound_samples;
 
 const PLAYER_HEIGHT: f32 = 1.4;
 const PLAYER_WIDTH: f32 = 0.75 * PLAYER_HEIGHT;
+/// In meters per second
+const CLICK_TO_MOVE_SPEED: f32 = 4.0;
+
+/// Size of a tile on screen at a camera zoom of 1.0
+const TILE_SIDE_IN_PIXELS: f32 = 60.0;
+const MIN_CAMERA_ZOOM: f32 = 0.05;
+const MAX_CAMERA_ZOOM: f32 = 4.0;
+/// How fast holding a zoom button changes the zoom, as a factor per second
+const CAMERA_ZOOM_RATE: f32 = 2.0;
+
+const BACKDROP_SORT_KEY: f32 = 0.0;
+const TILE_SORT_KEY: f32 = 1.0;
+const HOVER_SORT_KEY: f32 = 1.5;
+const CHARACTER_SORT_KEY: f32 = 2.0;
+const HUD_SORT_KEY: f32 = 3.0;
+const DEBUG_OVERLAY_SORT_KEY: f32 = 4.0;
+const HUD_TEXT_SCALE: f32 = 2.0;
+const HUD_MESSAGE_SECONDS: f32 = 3.0;
+const MAX_HUD_MESSAGE_LENGTH: usize = 128;
+
+/// Walk animation frames where a foot hits the ground
+const FOOTSTEP_FRAMES: [u32; 2] = [0, 6];
+
+/// What each storage block the platform gave us is split into, with a line
+/// for each arena tag in debug builds
+fn get_memory_report(memory: &GameMemory, game_state: &State) -> String {
+    let mut lines = vec![
+        format!(
+            "permanent storage: {}",
+            format_bytes(memory.permanent_storage_size)
+        ),
+        format!("  game state: {}", format_bytes(size_of::<State>())),
+    ];
+    lines.extend(
+        get_arena_report_lines(&game_state.world_arena)
+            .into_iter()
+            .map(|line| format!("  {}", line)),
+    );
+    lines.push(format!(
+        "transient storage: {}",
+        format_bytes(memory.transient_storage_size)
+    ));
+    lines.extend(
+        get_arena_report_lines(&game_state.transient_arena)
+            .into_iter()
+            .map(|line| format!("  {}", line)),
+    );
+    lines.join("\n")
+}
+
+/// Control+1 to 4 saves to that slot, Control+Shift+1 to 4 loads from it
+unsafe fn process_save_keys(
+    memory: &GameMemory,
+    game_state: &mut State,
+    tile_map: &mut TileMap,
+    input: &GameInput,
+) {
+    if !is_key_down(input, Key::Control) {
+        return;
+    }
+
+    for slot in 0..SAVE_SLOT_COUNT {
+        if !was_key_pressed(input, Key::Char((b'1' + slot as u8) as char)) {
+            continue;
+        }
+
+        let message = if is_key_down(input, Key::Shift) {
+            match load_game(memory, slot) {
+                Ok(Some(saved_game)) => match apply_saved_game(game_state, tile_map, saved_game) {
+                    Ok(()) => format!("Loaded slot {}", slot + 1),
+                    Err(e) => format!("Could not load slot {}: {}", slot + 1, e),
+                },
+                Ok(None) => format!("Slot {} is empty", slot + 1),
+                Err(e) => format!("Could not load slot {}: {}", slot + 1, e),
+            }
+        } else {
+            match save_game(memory, game_state, tile_map, slot) {
+                Ok(()) => format!("Saved to slot {}", slot + 1),
+                Err(e) => format!("Could not save to slot {}: {}", slot + 1, e),
+            }
+        };
+        info!("{}", message);
+        set_hud_message(game_state, &message);
+    }
+}
+
+/// Cut short, on a character boundary, if it doesn't fit
+fn set_hud_message(game_state: &mut State, message: &str) {
+    let mut length = message.len().min(MAX_HUD_MESSAGE_LENGTH);
+    while !message.is_char_boundary(length) {
+        length -= 1;
+    }
+    game_state.hud_message[..length].copy_from_slice(&message.as_bytes()[..length]);
+    game_state.hud_message_length = length;
+    game_state.hud_message_seconds = HUD_MESSAGE_SECONDS;
+}
+
+fn get_hud_message(game_state: &State) -> &str {
+    std::str::from_utf8(&game_state.hud_message[..game_state.hud_message_length])
+        .unwrap_or_default()
+}
+
+fn meters_to_pixels(tile_map: &TileMap, camera_zoom: f32) -> f32 {
+    camera_zoom * TILE_SIDE_IN_PIXELS / tile_map.tile_side_in_meters
+}
+
+/// The world position drawn at pixel (`x`, `y`) of the buffer, on the
+/// camera's floor. The inverse of the mapping used to draw the world.
+fn buffer_to_tile_map_position(
+    tile_map: &TileMap,
+    camera_p: &TileMapPosition,
+    camera_zoom: f32,
+    buffer: &GameOffscreenBuffer,
+    x: i32,
+    y: i32,
+) -> TileMapPosition {
+    let meters_to_pixels = meters_to_pixels(tile_map, camera_zoom);
+    let dx = (x as f32 + 0.5 - 0.5 * buffer.width as f32) / meters_to_pixels;
+    let dy = -(y as f32 + 0.5 - 0.5 * buffer.height as f32) / meters_to_pixels;
+
+    offset_position(tile_map, camera_p, dx, dy)
+}
+
+/// Moves the player to `new_player_p` if neither of their feet would end up
+/// in a wall, taking stairs on the way. Returns whether the player moved.
+unsafe fn move_player(
+    tile_map: &TileMap,
+    game_state: *mut State,
+    mut new_player_p: TileMapPosition,
+) -> bool {
+    let player_left = offset_position(tile_map, &new_player_p, -0.5 * PLAYER_WIDTH, 0.0);
+    let player_right = offset_position(tile_map, &new_player_p, 0.5 * PLAYER_WIDTH, 0.0);
+
+    if is_tile_map_point_empty(tile_map, &new_player_p)
+        && is_tile_map_point_empty(tile_map, &player_left)
+        && is_tile_map_point_empty(tile_map, &player_right)
+    {
+        if !are_on_same_tile(&(*game_state).player_p, &new_player_p) {
+            let new_tile_value = get_tile_value(tile_map, &new_player_p);
+
+            if new_tile_value == 3 {
+                new_player_p.abs_tile_z += 1;
+            } else if new_tile_value == 4 {
+                new_player_p.abs_tile_z -= 1;
+            }
+        }
+        (*game_state).player_p = new_player_p;
+        true
+    } else {
+        false
+    }
+}
+
+/// Walks the player one frame's worth towards their move target, if they
+/// have one. The target is dropped once reached or if a wall is in the way.
+unsafe fn move_player_towards_target(tile_map: &TileMap, game_state: *mut State, dt: f32) {
+    if !(*game_state).has_move_target {
+        return;
+    }
+
+    let target = (*game_state).move_target.clone();
+    let player_p = (*game_state).player_p.clone();
+    if target.abs_tile_z != player_p.abs_tile_z {
+        (*game_state).has_move_target = false;
+        return;
+    }
 
-// TODO: platform independent code should get priority for removing unsafe
-fn initialize_arena(arena: &mut MemoryArena, size: usize, base: *mut u8) {
-    (*arena).size = size;
-    (*arena).base = base;
-    (*arena).used = 0;
+    let diff = subtract(tile_map, &target, &player_p);
+    let distance = (diff.dx * diff.dx + diff.dy * diff.dy).sqrt();
+    let step = CLICK_TO_MOVE_SPEED * dt;
+
+    let arrived = distance <= step;
+    let new_player_p = if arrived {
+        target
+    } else {
+        offset_position(
+            tile_map,
+            &player_p,
+            step * diff.dx / distance,
+            step * diff.dy / distance,
+        )
+    };
+
+    let moved = move_player(tile_map, game_state, new_player_p);
+    if moved {
+        (*game_state).character_walk_frame += 1;
+        (*game_state).character_walk_frame %= (*game_state).character_image.frames;
+    }
+    if arrived || !moved {
+        (*game_state).has_move_target = false;
+    }
+}
+
+/// Loads what game state holds on the heap, over whatever it held before
+/// without freeing it, and keeps a copy in `HEAP_STATE`
+unsafe fn load_heap_state(memory: *mut GameMemory, game_state: *mut State) {
+    ptr::write(
+        &mut (*game_state).backdrop,
+        load_bitmap(&get_asset_path(&*memory, "Bricks.png")).expect("could not load background"),
+    );
+    ptr::write(
+        &mut (*game_state).font,
+        load_font(&get_asset_path(&*memory, "Font.fnt")).expect("could not load font"),
+    );
+    ptr::write(
+        &mut (*game_state).character_image,
+        CharacterImage {
+            image: load_bitmap(&get_asset_path(&*memory, "Skeleton Walk.png"))
+                .expect("could not load skeleton walk png"),
+            align_x: 10,
+            align_y: 33,
+            frame_width: 22,
+            frames: 13,
+        },
+    );
+
+    let audio_state = &mut (*game_state).audio_state;
+    replace_sounds(audio_state, Vec::new());
+    (*game_state).footstep_sound = add_sound(
+        audio_state,
+        load_wav(&get_asset_path(&*memory, "Footstep.wav")).expect("could not load footstep wav"),
+    );
+
+    // NOTE: The profiler and the debug overlay live outside game memory so
+    // restoring a replay snapshot doesn't rewind them, or leave the profiler
+    // with blocks that were freed
+    (*game_state).profiler = Box::into_raw(Box::default());
+    (*game_state).debug_overlay = Box::into_raw(Box::new(make_debug_overlay()));
+
+    let heap_state = HeapState {
+        backdrop: ptr::read(&(*game_state).backdrop),
+        font: ptr::read(&(*game_state).font),
+        character_image: ptr::read(&(*game_state).character_image.image),
+        sounds: share_sounds(&(*game_state).audio_state),
+        debug_overlay: (*game_state).debug_overlay,
+        profiler: (*game_state).profiler,
+    };
+    HEAP_STATE.store(Box::into_raw(Box::new(heap_state)), Ordering::Release);
+
+    // NOTE: Counting from the clock, so game memory saved by an earlier run
+    // isn't taken for this run's
+    if (*memory).heap_generation == 0 {
+        (*memory).heap_generation = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+    }
+    (*memory).heap_generation += 1;
+    (*game_state).heap_generation = (*memory).heap_generation;
 }
 
+/// Hands game memory from an earlier run what this run holds on the heap, in
+/// place of what the earlier run held and freed when it exited. The assets
+/// are loaded again if this game code hasn't loaded any yet.
+unsafe fn restore_heap_state(memory: *mut GameMemory, game_state: *mut State) {
+    let heap_state = HEAP_STATE.load(Ordering::Acquire);
+    if heap_state.is_null() {
+        load_heap_state(memory, game_state);
+        return;
+    }
+
+    ptr::write(
+        &mut (*game_state).backdrop,
+        ptr::read(&(*heap_state).backdrop),
+    );
+    ptr::write(&mut (*game_state).font, ptr::read(&(*heap_state).font));
+    ptr::write(
+        &mut (*game_state).character_image.image,
+        ptr::read(&(*heap_state).character_image),
+    );
+    replace_sounds(
+        &mut (*game_state).audio_state,
+        ptr::read(&(*heap_state).sounds),
+    );
+    (*game_state).debug_overlay = (*heap_state).debug_overlay;
+    (*game_state).profiler = (*heap_state).profiler;
+    (*game_state).heap_generation = (*memory).heap_generation;
+}
+
+/// The game code has its own copy of the log crate, with no logger until it
+/// installs one. Every reload installs its own, like the panic hook.
+fn initialize_logging(config: &GameConfig) {
+    let level = config.log_level;
+    INITIALIZE_LOGGING.call_once(|| {
+        let mut builder = if env::var_os("RUST_LOG").is_some() {
+            env_logger::Builder::from_default_env()
+        } else {
+            let mut builder = env_logger::Builder::new();
+            builder.filter_level(level);
+            builder
+        };
+        if let Err(e) = builder.try_init() {
+            eprintln!("the game could not start logging: {}", e);
+        }
+    });
+}
+
+/// # Safety
+///
+/// Called by the platform layer with valid pointers, `memory` must stay the
+/// same block of memory between calls.
 #[no_mangle]
 pub unsafe extern "C" fn update_and_render(
     memory: *mut GameMemory,
     input: *mut GameInput,
     buffer: *mut GameOffscreenBuffer,
+) -> bool {
+    initialize_logging(&(*memory).config);
+    let result = catch_game_panic(memory, || run_update_and_render(memory, input, buffer));
+    (*memory).update_count += 1;
+    result
+}
+
+unsafe fn run_update_and_render(
+    memory: *mut GameMemory,
+    input: *mut GameInput,
+    buffer: *mut GameOffscreenBuffer,
 ) {
-    debug_assert!(std::mem::size_of::<State>() <= (*memory).permanent_storage_size);
+    // NOTE: The size is configurable, so this can happen in release builds too
+    assert!(
+        size_of::<State>() <= (*memory).permanent_storage_size,
+        "permanent storage is {} bytes, the game state needs {}",
+        (*memory).permanent_storage_size,
+        size_of::<State>()
+    );
 
     #[allow(clippy::cast_ptr_alignment)]
     let game_state = (*memory).permanent_storage as *mut State;
 
-    if !(*memory).is_initialized {
-        (*game_state).backdrop =
-            image::open("data/assets/Bricks.png").expect("could not load background");
+    GLOBAL_DEBUG_TABLE.store((*memory).debug_table, Ordering::Release);
+    if (*game_state).profiler.is_null() {
+        load_heap_state(memory, game_state);
+    } else if (*game_state).heap_generation != (*memory).heap_generation {
+        restore_heap_state(memory, game_state);
+    }
+    // NOTE: Debug keys go by what the player is pressing, not the replay
+    let live_input = if (*memory).live_input.is_null() {
+        &*input
+    } else {
+        &*(*memory).live_input
+    };
+    collate_debug_events(&mut *(*game_state).profiler, (*memory).debug_table);
+    timed_block!("update_and_render");
 
-        (*game_state).character_image = CharacterImage {
-            image: image::open("data/assets/Skeleton Walk.png")
-                .expect("could not load skeleton walk png"),
-            align_x: 10,
-            align_y: 33,
-            frame_width: 22,
-            frames: 13,
-        };
+    if !(*memory).is_initialized {
         (*game_state).camera_p.abs_tile_x = 17 / 2;
         (*game_state).camera_p.abs_tile_y = 9 / 2;
+        (*game_state).camera_zoom = 1.0;
 
         (*game_state).player_p.abs_tile_x = 1;
         (*game_state).player_p.abs_tile_y = 3;
@@ -86,12 +485,32 @@ pub unsafe extern "C" fn update_and_render(
 
         initialize_arena(
             &mut (*game_state).world_arena,
-            (*memory).permanent_storage_size as usize - size_of::<State>(),
-            ((*memory).permanent_storage as *mut u8).add(size_of::<State>()),
+            "world",
+            (*memory).permanent_storage_size - size_of::<State>(),
+            (*memory).permanent_storage.add(size_of::<State>()),
+        );
+        initialize_arena(
+            &mut (*game_state).transient_arena,
+            "transient",
+            (*memory).transient_storage_size,
+            (*memory).transient_storage,
         );
 
-        (*game_state).world = push_struct::<World>(&mut (*game_state).world_arena);
-        let world = (*game_state).world;
+        initialize_audio(
+            &mut (*game_state).audio_state,
+            &mut (*game_state).world_arena,
+        );
+        let audio_state = &mut (*game_state).audio_state;
+        // NOTE: Music is optional, it is too big to keep in the repo
+        match open_wav_stream(&get_asset_path(&*memory, "Music.wav")) {
+            Ok(music) => {
+                play_stream(audio_state, music, true);
+            }
+            Err(e) => info!("playing without music: {}", e),
+        }
+
+        let world = push_struct::<World>(&mut (*game_state).world_arena, "World");
+        set_relative_pointer(&mut (*game_state).world, world);
 
         let tile_map = &mut (*world).tile_map;
 
@@ -102,11 +521,7 @@ pub unsafe extern "C" fn update_and_render(
         tile_map.tile_chunk_count_x = 128;
         tile_map.tile_chunk_count_y = 128;
         tile_map.tile_chunk_count_z = 2;
-        let tile_chunk_count =
-            tile_map.tile_chunk_count_x * tile_map.tile_chunk_count_y * tile_map.tile_chunk_count_z;
-        for _ in 0..tile_chunk_count {
-            tile_map.tile_chunks.push(TileChunk { tiles: vec![] })
-        }
+        push_tile_chunks(tile_map, &mut (*game_state).world_arena);
 
         tile_map.tile_side_in_meters = 1.4;
 
@@ -116,6 +531,10 @@ pub unsafe extern "C" fn update_and_render(
         let mut screen_y = 0;
         let mut abs_tile_z = 0;
 
+        (*game_state).world_seed = (*memory).config.seed.unwrap_or_else(|| thread_rng().gen());
+        let mut rng = StdRng::seed_from_u64((*game_state).world_seed);
+        (*game_state).footstep_rng = StdRng::seed_from_u64((*game_state).world_seed);
+
         //TODO: Replace all this with real world generation
         let mut door_left = false;
         let mut door_right = false;
@@ -124,7 +543,6 @@ pub unsafe extern "C" fn update_and_render(
         let mut door_up = false;
         let mut door_down = false;
         for _screen_index in 0..100 {
-            let mut rng = thread_rng();
             let random_choice = if door_up || door_down {
                 rng.gen_range(0, 2)
             } else {
@@ -170,7 +588,14 @@ pub unsafe extern "C" fn update_and_render(
                         1
                     };
 
-                    set_tile_value(tile_map, abs_tile_x, abs_tile_y, abs_tile_z, tile_value);
+                    set_tile_value(
+                        &mut (*game_state).world_arena,
+                        tile_map,
+                        abs_tile_x,
+                        abs_tile_y,
+                        abs_tile_z,
+                        tile_value,
+                    );
                 }
             }
 
@@ -204,11 +629,47 @@ pub unsafe extern "C" fn update_and_render(
         (*memory).is_initialized = true;
     }
 
-    let world = (*game_state).world;
+    clear_arena(&mut (*game_state).transient_arena);
+
+    let world = get_relative_pointer(&(*game_state).world);
     let tile_map = &mut (*world).tile_map;
 
-    let tile_side_in_pixels = 60;
-    let meters_to_pixels = tile_side_in_pixels as f32 / tile_map.tile_side_in_meters;
+    let last_walk_frame = (*game_state).character_walk_frame;
+
+    update_debug_overlay(&mut *(*game_state).debug_overlay, live_input);
+    if was_key_pressed(live_input, Key::Function(2)) {
+        match write_chrome_trace(&*(*game_state).profiler, CHROME_TRACE_FILE_NAME) {
+            Ok(()) => info!("wrote {}", CHROME_TRACE_FILE_NAME),
+            Err(e) => error!("could not write {}: {}", CHROME_TRACE_FILE_NAME, e),
+        }
+    }
+    if was_key_pressed(live_input, Key::Function(3)) {
+        info!(
+            "memory report\n{}",
+            get_memory_report(&*memory, &*game_state)
+        );
+    }
+
+    // NOTE: So do the save keys, or every loop of a replay would write the
+    // save files again, or load them over the world it is replaying
+    process_save_keys(&*memory, &mut *game_state, tile_map, live_input);
+    (*game_state).hud_message_seconds =
+        ((*game_state).hud_message_seconds - (*input).dt_for_frame).max(0.0);
+
+    (*game_state).hovered_p = buffer_to_tile_map_position(
+        tile_map,
+        &(*game_state).camera_p,
+        (*game_state).camera_zoom,
+        &(*buffer),
+        (*input).mouse_x,
+        (*input).mouse_y,
+    );
+    // NOTE: Holding the button down keeps steering towards the mouse
+    if (*input).mouse_buttons[0].ended_down {
+        (*game_state).move_target = (*game_state).hovered_p.clone();
+        (*game_state).has_move_target = true;
+    }
+    move_player_towards_target(tile_map, game_state, (*input).dt_for_frame);
 
     for controller_index in 0..(*input).controllers.len() {
         let controller = common::get_controller(input, controller_index);
@@ -216,6 +677,16 @@ pub unsafe extern "C" fn update_and_render(
             trace!("use analog movement tuning");
         } else {
             trace!("use digital movement tuning");
+            if (*controller).left_shoulder.ended_down {
+                (*game_state).camera_zoom /= 1.0 + CAMERA_ZOOM_RATE * (*input).dt_for_frame;
+            }
+            if (*controller).right_shoulder.ended_down {
+                (*game_state).camera_zoom *= 1.0 + CAMERA_ZOOM_RATE * (*input).dt_for_frame;
+            }
+            (*game_state).camera_zoom = (*game_state)
+                .camera_zoom
+                .clamp(MIN_CAMERA_ZOOM, MAX_CAMERA_ZOOM);
+
             let player_speed = if (*controller).action_up.ended_down {
                 10.0
             } else {
@@ -247,70 +718,87 @@ pub unsafe extern "C" fn update_and_render(
                 };
 
             // TODO: Diagonal will be faster! Fix once we have vectors
-            let mut new_player_p = (*game_state).player_p.clone();
-            new_player_p.offset_x += (*input).dt_for_frame * d_player_x;
-            new_player_p.offset_y += (*input).dt_for_frame * d_player_y;
-            new_player_p = recanonicalize_position(tile_map, new_player_p);
-            // TODO: Delta function that auto-recanonicalizes
-
-            let mut player_left = new_player_p.clone();
-            player_left.offset_x -= 0.5 * PLAYER_WIDTH;
-            player_left = recanonicalize_position(tile_map, player_left);
-
-            let mut player_right = new_player_p.clone();
-            player_right.offset_x += 0.5 * PLAYER_WIDTH;
-            player_right = recanonicalize_position(tile_map, player_right);
-
-            if is_tile_map_point_empty(tile_map, &new_player_p)
-                && is_tile_map_point_empty(tile_map, &player_left)
-                && is_tile_map_point_empty(tile_map, &player_right)
-            {
-                if !are_on_same_tile(&(*game_state).player_p, &new_player_p) {
-                    let new_tile_value = get_tile_value(tile_map, &new_player_p);
-
-                    if new_tile_value == 3 {
-                        new_player_p.abs_tile_z += 1;
-                    } else if new_tile_value == 4 {
-                        new_player_p.abs_tile_z -= 1;
-                    }
-                }
-                (*game_state).player_p = new_player_p
+            if d_player_x != 0.0 || d_player_y != 0.0 {
+                // NOTE: Steering by hand cancels any click-to-move
+                (*game_state).has_move_target = false;
+
+                let new_player_p = offset_position(
+                    tile_map,
+                    &(*game_state).player_p,
+                    (*input).dt_for_frame * d_player_x,
+                    (*input).dt_for_frame * d_player_y,
+                );
+                move_player(tile_map, game_state, new_player_p);
             }
 
             (*game_state).camera_p.abs_tile_z = (*game_state).player_p.abs_tile_z;
 
-            let diff = subtract(tile_map, &(*game_state).player_p, &(*game_state).camera_p);
-            if diff.dx > (9.0 * tile_map.tile_side_in_meters) {
-                (*game_state).camera_p.abs_tile_x += 17;
+            // NOTE: Zoomed out the screen is more tiles across, the camera
+            // jumps by a screen's worth of them at any zoom
+            let zoom_scale = 1.0 / (*game_state).camera_zoom;
+            let jump_x = (17.0 * zoom_scale).round() as u32;
+            let jump_y = (9.0 * zoom_scale).round() as u32;
+            let edge_x = 9.0 * zoom_scale * tile_map.tile_side_in_meters;
+            let edge_y = 5.0 * zoom_scale * tile_map.tile_side_in_meters;
+
+            let camera_p = &mut (*game_state).camera_p;
+            let diff = subtract(tile_map, &(*game_state).player_p, camera_p);
+            if diff.dx > edge_x {
+                camera_p.abs_tile_x += jump_x;
             }
-            if diff.dx < -(9.0 * tile_map.tile_side_in_meters) {
-                (*game_state).camera_p.abs_tile_x -= 17;
+            if diff.dx < -edge_x {
+                camera_p.abs_tile_x = camera_p.abs_tile_x.saturating_sub(jump_x);
             }
-            if diff.dy > (5.0 * tile_map.tile_side_in_meters) {
-                (*game_state).camera_p.abs_tile_y += 9;
+            if diff.dy > edge_y {
+                camera_p.abs_tile_y += jump_y;
             }
-            if diff.dy < -(5.0 * tile_map.tile_side_in_meters) {
-                (*game_state).camera_p.abs_tile_y -= 9;
+            if diff.dy < -edge_y {
+                camera_p.abs_tile_y = camera_p.abs_tile_y.saturating_sub(jump_y);
             }
         }
     }
 
-    draw_image(
-        &(*buffer),
+    let walk_frame = (*game_state).character_walk_frame;
+    if walk_frame != last_walk_frame && FOOTSTEP_FRAMES.contains(&walk_frame) {
+        let audio_state = &mut (*game_state).audio_state;
+        if let Some(footstep) = play_sound(audio_state, (*game_state).footstep_sound, false) {
+            // NOTE: Vary the steps a little so they don't sound mechanical
+            let rng = &mut (*game_state).footstep_rng;
+            change_volume(
+                audio_state,
+                footstep,
+                rng.gen_range(0.6, 1.0),
+                rng.gen_range(-0.2, 0.2),
+            );
+        }
+    }
+
+    let mut render_group = RenderGroup::default();
+
+    push_clear(&mut render_group, 0.0, 0.0, 0.0);
+    push_bitmap(
+        &mut render_group,
+        BACKDROP_SORT_KEY,
         &(*game_state).backdrop,
         0.0,
         0.0,
-        (*game_state).backdrop.width(),
-        0,
+        1.0,
     );
 
+    let camera_zoom = (*game_state).camera_zoom;
+    let tile_side_in_pixels = camera_zoom * TILE_SIDE_IN_PIXELS;
+    let meters_to_pixels = meters_to_pixels(tile_map, camera_zoom);
+
     let screen_center_x = 0.5 * (*buffer).width as f32;
     let screen_center_y = 0.5 * (*buffer).height as f32;
 
-    for r in 0..20 {
-        for c in 0..40 {
-            let rel_row = r - 10;
-            let rel_column = c - 20;
+    // Enough tiles to cover the buffer from the camera out to each edge, plus
+    // one extra on each side for the camera offset within its tile
+    let tile_span_x = (screen_center_x / tile_side_in_pixels).ceil() as i32 + 1;
+    let tile_span_y = (screen_center_y / tile_side_in_pixels).ceil() as i32 + 1;
+
+    for rel_row in -tile_span_y..=tile_span_y {
+        for rel_column in -tile_span_x..=tile_span_x {
             let column = ((*game_state).camera_p.abs_tile_x as i32 + rel_column) as u32;
             let row = ((*game_state).camera_p.abs_tile_y as i32 + rel_row) as u32;
             let tile_id =
@@ -330,26 +818,86 @@ pub unsafe extern "C" fn update_and_render(
                 };
 
                 let cen_x = screen_center_x - meters_to_pixels * (*game_state).camera_p.offset_x
-                    + (rel_column * tile_side_in_pixels) as f32;
+                    + rel_column as f32 * tile_side_in_pixels;
                 let cen_y = screen_center_y + meters_to_pixels * (*game_state).camera_p.offset_y
-                    - (rel_row * tile_side_in_pixels) as f32;
-                let min_x = cen_x - 0.5 * tile_side_in_pixels as f32;
-                let min_y = cen_y - 0.5 * tile_side_in_pixels as f32;
-                let max_x = cen_x + 0.5 * tile_side_in_pixels as f32;
-                let max_y = cen_y + 0.5 * tile_side_in_pixels as f32;
-                draw_rectangle(&(*buffer), min_x, min_y, max_x, max_y, gray, gray, gray);
+                    - rel_row as f32 * tile_side_in_pixels;
+                let min_x = cen_x - 0.5 * tile_side_in_pixels;
+                let min_y = cen_y - 0.5 * tile_side_in_pixels;
+                let max_x = cen_x + 0.5 * tile_side_in_pixels;
+                let max_y = cen_y + 0.5 * tile_side_in_pixels;
+                push_rectangle(
+                    &mut render_group,
+                    TILE_SORT_KEY,
+                    min_x,
+                    min_y,
+                    max_x,
+                    max_y,
+                    gray,
+                    gray,
+                    gray,
+                );
             }
         }
     }
 
+    // NOTE: Outline the hovered tile
+    let hovered_p = &(*game_state).hovered_p;
+    let hover_diff = subtract(tile_map, hovered_p, &(*game_state).camera_p);
+    let hover_cen_x = screen_center_x + meters_to_pixels * (hover_diff.dx - hovered_p.offset_x);
+    let hover_cen_y = screen_center_y - meters_to_pixels * (hover_diff.dy - hovered_p.offset_y);
+    let hover_min_x = hover_cen_x - 0.5 * tile_side_in_pixels;
+    let hover_min_y = hover_cen_y - 0.5 * tile_side_in_pixels;
+    let hover_max_x = hover_cen_x + 0.5 * tile_side_in_pixels;
+    let hover_max_y = hover_cen_y + 0.5 * tile_side_in_pixels;
+    let thickness = 2.0;
+    for &(min_x, min_y, max_x, max_y) in &[
+        (
+            hover_min_x,
+            hover_min_y,
+            hover_max_x,
+            hover_min_y + thickness,
+        ),
+        (
+            hover_min_x,
+            hover_max_y - thickness,
+            hover_max_x,
+            hover_max_y,
+        ),
+        (
+            hover_min_x,
+            hover_min_y,
+            hover_min_x + thickness,
+            hover_max_y,
+        ),
+        (
+            hover_max_x - thickness,
+            hover_min_y,
+            hover_max_x,
+            hover_max_y,
+        ),
+    ] {
+        push_rectangle(
+            &mut render_group,
+            HOVER_SORT_KEY,
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            1.0,
+            1.0,
+            0.0,
+        );
+    }
+
     let diff = subtract(tile_map, &(*game_state).player_p, &(*game_state).camera_p);
 
     let player_ground_point_x = screen_center_x + meters_to_pixels * diff.dx;
     let player_ground_point_y = screen_center_y - meters_to_pixels * diff.dy;
 
     let character_image = &(*game_state).character_image;
-    draw_animated_image(
-        &(*buffer),
+    push_sprite(
+        &mut render_group,
+        CHARACTER_SORT_KEY,
         &character_image.image,
         player_ground_point_x,
         player_ground_point_y,
@@ -357,153 +905,224 @@ pub unsafe extern "C" fn update_and_render(
         character_image.align_y,
         character_image.frame_width,
         (*game_state).character_walk_frame,
+        camera_zoom,
     );
-}
-
-fn draw_rectangle(
-    buffer: &GameOffscreenBuffer,
-    real_min_x: f32,
-    real_min_y: f32,
-    real_max_x: f32,
-    real_max_y: f32,
-    r: f32,
-    g: f32,
-    b: f32,
-) {
-    // TODO: Floating point color
-
-    let mut min_x = real_min_x.round() as i32;
-    let mut min_y = real_min_y.round() as i32;
-    let mut max_x = real_max_x.round() as i32;
-    let mut max_y = real_max_y.round() as i32;
-
-    if min_x < 0 {
-        min_x = 0
-    };
-
-    if min_y < 0 {
-        min_y = 0
-    };
 
-    if max_x > buffer.width {
-        max_x = buffer.width
-    };
+    let font = &(*game_state).font;
+    let mut hud_text = format!(
+        "Tile {}, {}, {}",
+        hovered_p.abs_tile_x, hovered_p.abs_tile_y, hovered_p.abs_tile_z
+    );
+    if (*game_state).hud_message_seconds > 0.0 {
+        hud_text.push_str("  ");
+        hud_text.push_str(get_hud_message(&*game_state));
+    }
+    let hud_margin = 4.0;
+    let hud_height = HUD_TEXT_SCALE * font.line_height as f32 + 2.0 * hud_margin;
+    push_rectangle(
+        &mut render_group,
+        HUD_SORT_KEY,
+        0.0,
+        0.0,
+        text_width(font, &hud_text, HUD_TEXT_SCALE) + 2.0 * hud_margin,
+        hud_height,
+        0.0,
+        0.0,
+        0.0,
+    );
+    push_text(
+        &mut render_group,
+        HUD_SORT_KEY,
+        font,
+        hud_margin,
+        hud_margin,
+        HUD_TEXT_SCALE,
+        &hud_text,
+    );
 
-    if max_y > buffer.height {
-        max_y = buffer.height
-    };
+    if (*(*game_state).debug_overlay).is_visible {
+        let player_p = &(*game_state).player_p;
+        let camera_p = &(*game_state).camera_p;
+        let debug_text = format!(
+            "Player {}, {}, {} +{:.2}, {:.2}\n\
+             Camera {}, {}, {} zoom {:.2}\n\
+             Tile value {}\n\
+             Chunks loaded {} / {}\n\
+             World seed {}\n\
+             {}",
+            player_p.abs_tile_x,
+            player_p.abs_tile_y,
+            player_p.abs_tile_z,
+            player_p.offset_x,
+            player_p.offset_y,
+            camera_p.abs_tile_x,
+            camera_p.abs_tile_y,
+            camera_p.abs_tile_z,
+            (*game_state).camera_zoom,
+            get_tile_value(tile_map, player_p),
+            get_loaded_chunk_count(tile_map),
+            get_tile_chunk_count(tile_map),
+            (*game_state).world_seed,
+            get_replay_status_line(&(*memory).replay_status),
+        );
+        push_debug_overlay(
+            &mut render_group,
+            DEBUG_OVERLAY_SORT_KEY,
+            &*(*game_state).debug_overlay,
+            font,
+            (*buffer).width,
+            (*input).target_seconds_per_frame,
+            &debug_text,
+        );
+    }
+    push_replay_indicator(
+        &mut render_group,
+        DEBUG_OVERLAY_SORT_KEY,
+        &(*memory).replay_status,
+        font,
+        (*buffer).width,
+    );
+    push_memory_view(
+        &mut render_group,
+        DEBUG_OVERLAY_SORT_KEY,
+        &*(*game_state).debug_overlay,
+        &[&(*game_state).world_arena, &(*game_state).transient_arena],
+        font,
+        hud_height,
+    );
+    push_profiler_view(
+        &mut render_group,
+        DEBUG_OVERLAY_SORT_KEY,
+        &*(*game_state).debug_overlay,
+        &*(*game_state).profiler,
+        font,
+        (*buffer).width,
+        (*buffer).height,
+    );
 
-    let color = ((r * 255.0).round() as u32) << 16
-        | ((g * 255.0).round() as u32) << 8
-        | (b * 255.0).round() as u32;
-
-    unsafe {
-        let mut row = (buffer.memory as *mut u8)
-            .offset((min_x * buffer.bytes_per_pixel) as isize)
-            .offset((min_y * buffer.pitch) as isize);
-        for _y in min_y..max_y {
-            #[allow(clippy::cast_ptr_alignment)]
-            let mut pixel = row as *mut u32;
-            for _x in min_x..max_x {
-                *pixel = color;
-                pixel = pixel.offset(1);
-            }
-            row = row.offset(buffer.pitch as isize);
+    match (
+        (*memory).platform_add_entry,
+        (*memory).platform_complete_all_work,
+    ) {
+        (Some(add_entry), Some(complete_all_work)) if !(*memory).high_priority_queue.is_null() => {
+            tiled_render_group_to_output(
+                (*memory).high_priority_queue,
+                add_entry,
+                complete_all_work,
+                &mut render_group,
+                &(*buffer),
+            )
         }
+        _ => render_group_to_output(&mut render_group, &(*buffer)),
     }
 }
 
-fn draw_animated_image(
-    buffer: &GameOffscreenBuffer,
-    bitmap: &DynamicImage,
-    real_x: f32,
-    real_y: f32,
-    align_x: i32,
-    align_y: i32,
-    frame_width: u32,
-    frame: u32,
-) {
-    let x = real_x - align_x as f32;
-    let y = real_y - align_y as f32;
-
-    draw_image(
-        buffer,
-        bitmap,
-        x,
-        y,
-        frame_width,
-        (frame * frame_width) as i32,
-    )
+/// # Safety
+///
+/// Called by the platform layer with valid pointers, after `update_and_render`
+/// for the same frame.
+#[no_mangle]
+pub unsafe extern "C" fn get_sound_samples(
+    memory: *mut GameMemory,
+    sound_buffer: *mut GameSoundOutputBuffer,
+) -> bool {
+    initialize_logging(&(*memory).config);
+    catch_game_panic(memory, || run_get_sound_samples(memory, sound_buffer))
 }
 
-fn draw_image(
-    buffer: &GameOffscreenBuffer,
-    bitmap: &DynamicImage,
-    real_x: f32,
-    real_y: f32,
-    width: u32,
-    x_offset: i32,
-) {
-    let mut min_x = real_x.round() as i32;
-    let mut min_y = real_y.round() as i32;
-    let mut max_x = real_x as i32 + width as i32;
-    let mut max_y = real_y as i32 + bitmap.height() as i32;
-
-    let mut source_offset_x = 0;
-    if min_x < 0 {
-        source_offset_x = -min_x;
-        min_x = 0;
-    }
-    source_offset_x += x_offset;
+unsafe fn run_get_sound_samples(memory: *mut GameMemory, sound_buffer: *mut GameSoundOutputBuffer) {
+    #[allow(clippy::cast_ptr_alignment)]
+    let game_state = (*memory).permanent_storage as *mut State;
+
+    GLOBAL_DEBUG_TABLE.store((*memory).debug_table, Ordering::Release);
+    timed_block!("get_sound_samples");
 
-    let mut source_offset_y = 0;
-    if min_y < 0 {
-        source_offset_y = -min_y;
-        min_y = 0;
+    if (*memory).is_initialized {
+        output_playing_sounds(
+            &mut (*game_state).audio_state,
+            &*memory,
+            &mut (*game_state).transient_arena,
+            &mut (*sound_buffer),
+        );
+    } else {
+        let samples = (*sound_buffer).samples;
+        samples.write_bytes(0, 2 * (*sound_buffer).sample_count as usize);
     }
+}
 
-    if max_x > buffer.width {
-        max_x = buffer.width;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{ffi::c_void, mem::zeroed, sync::atomic::AtomicU32};
+    use std::alloc::{alloc_zeroed, Layout};
+
+    static WRITE_COUNT: AtomicU32 = AtomicU32::new(0);
+
+    unsafe extern "C" fn count_write(
+        _name: *const u8,
+        _name_length: usize,
+        _memory: *const u8,
+        _size: usize,
+    ) -> bool {
+        WRITE_COUNT.fetch_add(1, Ordering::Relaxed);
+        true
     }
 
-    if max_y > buffer.height {
-        max_y = buffer.height;
+    fn press_key(input: &mut GameInput, key: Key) {
+        let button = &mut input.keys[key_index(key).unwrap()];
+        button.half_transition_count = 1;
+        button.ended_down = true;
     }
 
-    unsafe {
-        let mut dest_row = (buffer.memory as *mut u8)
-            .offset((min_x * buffer.bytes_per_pixel + min_y * buffer.pitch) as isize);
-        for y in min_y..max_y {
-            #[allow(clippy::cast_ptr_alignment)]
-            let mut dest = dest_row as *mut u32;
-            for x in min_x..max_x {
-                let source_x = (source_offset_x + x - min_x) as u32;
-                let source_y = (source_offset_y + y - min_y) as u32;
-
-                if source_x < bitmap.width() && source_y < bitmap.height() {
-                    let pixel = bitmap.get_pixel(source_x, source_y);
-
-                    let a = pixel[3] as f32 / 255.0;
-                    let sr = pixel[0] as f32;
-                    let sg = pixel[1] as f32;
-                    let sb = pixel[2] as f32;
-
-                    let dr = ((*dest >> 16) & 0xFF) as f32;
-                    let dg = ((*dest >> 8) & 0xFF) as f32;
-                    let db = (*dest & 0xFF) as f32;
-
-                    // TODO: Investigate premultiplied alpha
-                    let r = (1.0 - a) * dr + a * sr;
-                    let g = (1.0 - a) * dg + a * sg;
-                    let b = (1.0 - a) * db + a * sb;
-
-                    *dest = (((r + 0.5) as u32) << 16) | ((g + 0.5) as u32) << 8 | (b + 0.5) as u32;
-                }
+    #[test]
+    fn replayed_save_keys_write_nothing() {
+        unsafe {
+            let mut memory: GameMemory = zeroed();
+            memory.permanent_storage_size = 64 << 20;
+            memory.permanent_storage =
+                alloc_zeroed(Layout::from_size_align(memory.permanent_storage_size, 4096).unwrap());
+            memory.transient_storage_size = 16 << 20;
+            memory.transient_storage =
+                alloc_zeroed(Layout::from_size_align(memory.transient_storage_size, 4096).unwrap());
+            memory.debug_table = allocate_debug_table();
+            memory.platform_write_entire_file = Some(count_write);
+            copy_name(
+                &mut memory.config.data_directory,
+                concat!(env!("CARGO_MANIFEST_DIR"), "/data"),
+            );
+            memory.config.seed = Some(7);
+
+            let mut pixels = vec![0u32; 320 * 180];
+            let mut buffer = GameOffscreenBuffer {
+                memory: pixels.as_mut_ptr() as *mut c_void,
+                width: 320,
+                height: 180,
+                pitch: 320 * 4,
+                bytes_per_pixel: 4,
+            };
 
-                dest = dest.add(1);
+            // NOTE: Ctrl+1 in the recording, while the player presses nothing
+            let mut replayed_input: GameInput = zeroed();
+            press_key(&mut replayed_input, Key::Control);
+            press_key(&mut replayed_input, Key::Char('1'));
+            let live_input: GameInput = zeroed();
+            memory.live_input = &live_input;
+            for _ in 0..3 {
+                assert!(update_and_render(
+                    &mut memory,
+                    &mut replayed_input,
+                    &mut buffer
+                ));
             }
-
-            dest_row = dest_row.offset(buffer.pitch as isize);
+            assert_eq!(WRITE_COUNT.load(Ordering::Relaxed), 0);
+
+            memory.live_input = &replayed_input;
+            assert!(update_and_render(
+                &mut memory,
+                &mut replayed_input,
+                &mut buffer
+            ));
+            assert_eq!(WRITE_COUNT.load(Ordering::Relaxed), 1);
         }
     }
 }
@@ -0,0 +1,468 @@
+//! Maps physical keys and gamepad buttons to the buttons of a
+//! `GameControllerInput`. The mapping comes from a config file that is
+//! reloaded whenever it changes, falling back to the built-in defaults if it
+//! doesn't load at startup. Key and gamepad button names are not case
+//! sensitive.
+//!
+//! ```text
+//! # comment
+//! [keyboard]
+//! move_up = W, Up
+//! [gamepad]
+//! action_down = A
+//! ```
+
+use crate::common::*;
+use std::{fs, path::PathBuf, time::SystemTime};
+
+/// In the data directory, next to the assets
+pub const BINDINGS_FILE_NAME: &str = "bindings.cfg";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameButton {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    ActionUp,
+    ActionDown,
+    ActionLeft,
+    ActionRight,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PadButton {
+    A,
+    B,
+    X,
+    Y,
+    LeftShoulder,
+    RightShoulder,
+    Start,
+    Back,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+pub struct Bindings {
+    pub keys: Vec<(Key, GameButton)>,
+    pub pad_buttons: Vec<(PadButton, GameButton)>,
+}
+
+/// A bindings config file and what was last loaded from it
+pub struct BindingsFile {
+    path: PathBuf,
+    last_write_time: Option<SystemTime>,
+    pub bindings: Bindings,
+}
+
+const GAME_BUTTON_NAMES: [(&str, GameButton); 12] = [
+    ("move_up", GameButton::MoveUp),
+    ("move_down", GameButton::MoveDown),
+    ("move_left", GameButton::MoveLeft),
+    ("move_right", GameButton::MoveRight),
+    ("action_up", GameButton::ActionUp),
+    ("action_down", GameButton::ActionDown),
+    ("action_left", GameButton::ActionLeft),
+    ("action_right", GameButton::ActionRight),
+    ("left_shoulder", GameButton::LeftShoulder),
+    ("right_shoulder", GameButton::RightShoulder),
+    ("select", GameButton::Select),
+    ("start", GameButton::Start),
+];
+
+const PAD_BUTTON_NAMES: [(&str, PadButton); 12] = [
+    ("A", PadButton::A),
+    ("B", PadButton::B),
+    ("X", PadButton::X),
+    ("Y", PadButton::Y),
+    ("LeftShoulder", PadButton::LeftShoulder),
+    ("RightShoulder", PadButton::RightShoulder),
+    ("Start", PadButton::Start),
+    ("Back", PadButton::Back),
+    ("DPadUp", PadButton::DPadUp),
+    ("DPadDown", PadButton::DPadDown),
+    ("DPadLeft", PadButton::DPadLeft),
+    ("DPadRight", PadButton::DPadRight),
+];
+
+const NAMED_KEYS: [(&str, Key); 11] = [
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Escape", Key::Escape),
+    ("Space", Key::Space),
+    ("Enter", Key::Enter),
+    ("Tab", Key::Tab),
+    ("Backspace", Key::Backspace),
+    ("Shift", Key::Shift),
+    ("Control", Key::Control),
+];
+
+/// What the game was hardcoded to before bindings were configurable
+pub fn default_bindings() -> Bindings {
+    Bindings {
+        keys: vec![
+            (Key::Char('W'), GameButton::MoveUp),
+            (Key::Char('S'), GameButton::MoveDown),
+            (Key::Char('A'), GameButton::MoveLeft),
+            (Key::Char('D'), GameButton::MoveRight),
+            (Key::Up, GameButton::ActionUp),
+            (Key::Down, GameButton::ActionDown),
+            (Key::Left, GameButton::ActionLeft),
+            (Key::Right, GameButton::ActionRight),
+            (Key::Char('Q'), GameButton::LeftShoulder),
+            (Key::Char('E'), GameButton::RightShoulder),
+            (Key::Space, GameButton::Select),
+            (Key::Escape, GameButton::Start),
+        ],
+        // NOTE: The d-pad and the stick already drive the move buttons
+        pad_buttons: vec![
+            (PadButton::X, GameButton::ActionUp),
+            (PadButton::B, GameButton::ActionDown),
+            (PadButton::Y, GameButton::ActionLeft),
+            (PadButton::A, GameButton::ActionRight),
+            (PadButton::LeftShoulder, GameButton::LeftShoulder),
+            (PadButton::RightShoulder, GameButton::RightShoulder),
+            (PadButton::Back, GameButton::Select),
+            (PadButton::Start, GameButton::Start),
+        ],
+    }
+}
+
+pub fn all_game_buttons() -> impl Iterator<Item = GameButton> {
+    GAME_BUTTON_NAMES.iter().map(|&(_, button)| button)
+}
+
+pub fn game_button(
+    controller: &mut GameControllerInput,
+    button: GameButton,
+) -> &mut GameButtonState {
+    match button {
+        GameButton::MoveUp => &mut controller.move_up,
+        GameButton::MoveDown => &mut controller.move_down,
+        GameButton::MoveLeft => &mut controller.move_left,
+        GameButton::MoveRight => &mut controller.move_right,
+        GameButton::ActionUp => &mut controller.action_up,
+        GameButton::ActionDown => &mut controller.action_down,
+        GameButton::ActionLeft => &mut controller.action_left,
+        GameButton::ActionRight => &mut controller.action_right,
+        GameButton::LeftShoulder => &mut controller.left_shoulder,
+        GameButton::RightShoulder => &mut controller.right_shoulder,
+        GameButton::Select => &mut controller.select,
+        GameButton::Start => &mut controller.start,
+    }
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        let c = c.to_ascii_uppercase();
+        if c.is_ascii_uppercase() || c.is_ascii_digit() {
+            return Some(Key::Char(c));
+        }
+    }
+
+    if let Some(number) = name
+        .strip_prefix(|c| c == 'F' || c == 'f')
+        .and_then(|n| n.parse().ok())
+    {
+        if (1..=12).contains(&number) {
+            return Some(Key::Function(number));
+        }
+    }
+
+    NAMED_KEYS
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+}
+
+fn parse_pad_button(name: &str) -> Option<PadButton> {
+    PAD_BUTTON_NAMES
+        .iter()
+        .find(|(button_name, _)| button_name.eq_ignore_ascii_case(name))
+        .map(|&(_, button)| button)
+}
+
+/// Returns a description of the first problem found, with its line number.
+pub fn parse_bindings(text: &str) -> Result<Bindings, String> {
+    let mut result = Bindings {
+        keys: Vec::new(),
+        pad_buttons: Vec::new(),
+    };
+
+    let mut section = "";
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = &line[1..line.len() - 1];
+            if section != "keyboard" && section != "gamepad" {
+                return Err(format!(
+                    "line {}: unknown section [{}]",
+                    line_number, section
+                ));
+            }
+            continue;
+        }
+
+        let (button_name, inputs) = match line.find('=') {
+            Some(equals) => (line[..equals].trim(), &line[equals + 1..]),
+            None => return Err(format!("line {}: expected button = inputs", line_number)),
+        };
+        let button = match GAME_BUTTON_NAMES
+            .iter()
+            .find(|(name, _)| *name == button_name)
+        {
+            Some(&(_, button)) => button,
+            None => {
+                return Err(format!(
+                    "line {}: unknown game button {}",
+                    line_number, button_name
+                ))
+            }
+        };
+
+        for input in inputs
+            .split(',')
+            .map(str::trim)
+            .filter(|input| !input.is_empty())
+        {
+            match section {
+                "keyboard" => match parse_key(input) {
+                    Some(key) => result.keys.push((key, button)),
+                    None => return Err(format!("line {}: unknown key {}", line_number, input)),
+                },
+                "gamepad" => match parse_pad_button(input) {
+                    Some(pad_button) => result.pad_buttons.push((pad_button, button)),
+                    None => {
+                        return Err(format!(
+                            "line {}: unknown gamepad button {}",
+                            line_number, input
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(format!(
+                        "line {}: binding outside of [keyboard] or [gamepad]",
+                        line_number
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn load_bindings(path: &PathBuf) -> Result<Bindings, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {:?}: {}", path, e))?;
+    let bindings = parse_bindings(&text).map_err(|e| format!("{:?} {}", path, e))?;
+    info!("loaded bindings from {:?}", path);
+    Ok(bindings)
+}
+
+fn get_last_write_time(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+pub fn open_bindings_file(path: PathBuf) -> BindingsFile {
+    let bindings = load_bindings(&path).unwrap_or_else(|e| {
+        info!("using default bindings, {}", e);
+        default_bindings()
+    });
+    BindingsFile {
+        last_write_time: get_last_write_time(&path),
+        bindings,
+        path,
+    }
+}
+
+/// Call once a frame, so edits to the file take effect without a restart. A
+/// file that doesn't load leaves the bindings as they were, so a typo made
+/// while playing doesn't take the controls away.
+pub fn reload_bindings_if_changed(file: &mut BindingsFile) {
+    let last_write_time = get_last_write_time(&file.path);
+    if last_write_time != file.last_write_time {
+        file.last_write_time = last_write_time;
+        match load_bindings(&file.path) {
+            Ok(bindings) => file.bindings = bindings,
+            Err(e) => error!("keeping the bindings from before, {}", e),
+        }
+    }
+}
+
+/// Whether any pad button bound to `button` is down
+pub fn is_bound_pad_button_down(
+    bindings: &Bindings,
+    button: GameButton,
+    is_pad_button_down: impl Fn(PadButton) -> bool,
+) -> bool {
+    bindings
+        .pad_buttons
+        .iter()
+        .any(|&(pad_button, bound)| bound == button && is_pad_button_down(pad_button))
+}
+
+/// The game buttons `key` is bound to
+pub fn buttons_for_key(bindings: &Bindings, key: Key) -> impl Iterator<Item = GameButton> + '_ {
+    bindings
+        .keys
+        .iter()
+        .filter(move |(bound_key, _)| *bound_key == key)
+        .map(|&(_, button)| button)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_parse_error(text: &str) -> String {
+        match parse_bindings(text) {
+            Ok(_) => panic!("the bindings parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn parses_key_names() {
+        assert_eq!(parse_key("w"), Some(Key::Char('W')));
+        assert_eq!(parse_key("W"), Some(Key::Char('W')));
+        assert_eq!(parse_key("7"), Some(Key::Char('7')));
+        assert_eq!(parse_key("F1"), Some(Key::Function(1)));
+        assert_eq!(parse_key("f12"), Some(Key::Function(12)));
+        assert_eq!(parse_key("up"), Some(Key::Up));
+        assert_eq!(parse_key("ESCAPE"), Some(Key::Escape));
+        assert_eq!(parse_key("F"), Some(Key::Char('F')));
+        for bad_key in &["F0", "F13", "-", "Upp", "Ü", ""] {
+            assert_eq!(parse_key(bad_key), None, "{}", bad_key);
+        }
+    }
+
+    #[test]
+    fn parses_both_sections() {
+        let bindings = parse_bindings(
+            "# comment\n\
+             [keyboard]\n\
+             move_up = w, Up # trailing comment\n\
+             \n\
+             start = f10,\n\
+             [gamepad]\n\
+             action_down = a, dpaddown\n",
+        )
+        .unwrap();
+        assert_eq!(
+            bindings.keys,
+            vec![
+                (Key::Char('W'), GameButton::MoveUp),
+                (Key::Up, GameButton::MoveUp),
+                (Key::Function(10), GameButton::Start),
+            ]
+        );
+        assert_eq!(
+            bindings.pad_buttons,
+            vec![
+                (PadButton::A, GameButton::ActionDown),
+                (PadButton::DPadDown, GameButton::ActionDown),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_bound_buttons() {
+        let bindings = default_bindings();
+        assert_eq!(
+            buttons_for_key(&bindings, Key::Char('W')).collect::<Vec<_>>(),
+            vec![GameButton::MoveUp]
+        );
+        assert_eq!(buttons_for_key(&bindings, Key::Char('Z')).count(), 0);
+        assert!(is_bound_pad_button_down(
+            &bindings,
+            GameButton::Start,
+            |pad_button| pad_button == PadButton::Start
+        ));
+        assert!(!is_bound_pad_button_down(
+            &bindings,
+            GameButton::Start,
+            |pad_button| pad_button == PadButton::A
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_lines_with_their_number() {
+        let bad_bindings = [
+            ("[mouse]", "line 1: unknown section [mouse]"),
+            (
+                "move_up = W",
+                "line 1: binding outside of [keyboard] or [gamepad]",
+            ),
+            ("[keyboard]\nmove_up W", "line 2: expected button = inputs"),
+            (
+                "[keyboard]\n\njump = Space",
+                "line 3: unknown game button jump",
+            ),
+            ("[keyboard]\nmove_up = Tilde", "line 2: unknown key Tilde"),
+            ("[gamepad]\nstart = Z", "line 2: unknown gamepad button Z"),
+        ];
+        for (text, message) in bad_bindings.iter() {
+            assert_eq!(get_parse_error(text), *message);
+        }
+    }
+
+    #[test]
+    fn keeps_the_bindings_when_a_reload_fails() {
+        let directory = std::env::temp_dir().join(format!("bindings_test_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(BINDINGS_FILE_NAME);
+        fs::write(&path, "[keyboard]\nmove_up = I\n").unwrap();
+        let mut file = open_bindings_file(path.clone());
+        assert_eq!(
+            file.bindings.keys,
+            vec![(Key::Char('I'), GameButton::MoveUp)]
+        );
+
+        fs::write(&path, "[keyboard]\nmove_up = Tilde\n").unwrap();
+        // NOTE: So the write counts as a change however coarse the file times
+        file.last_write_time = None;
+        reload_bindings_if_changed(&mut file);
+        assert_eq!(
+            file.bindings.keys,
+            vec![(Key::Char('I'), GameButton::MoveUp)]
+        );
+
+        fs::remove_file(&path).unwrap();
+        let file = open_bindings_file(path);
+        assert_eq!(file.bindings.keys, default_bindings().keys);
+        fs::remove_dir(&directory).unwrap();
+    }
+
+    #[test]
+    fn every_game_button_has_its_own_controller_button() {
+        let mut controller = GameControllerInput::default();
+        for (index, button) in all_game_buttons().enumerate() {
+            game_button(&mut controller, button).half_transition_count = index as i32 + 1;
+        }
+        for (index, button) in all_game_buttons().enumerate() {
+            assert_eq!(
+                game_button(&mut controller, button).half_transition_count,
+                index as i32 + 1,
+                "{:?}",
+                button
+            );
+        }
+    }
+}
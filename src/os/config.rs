@@ -0,0 +1,421 @@
+//! Settings the platform layer reads once at startup, from a config file and
+//! then from the command line, which wins. The ones the game needs are passed
+//! on through `GameMemory::config`.
+//!
+//! ```text
+//! # comment
+//! width = 1280
+//! update_hz = 60
+//! transient_storage = 512M
+//! ```
+//!
+//! On the command line the same settings are `--width 1280`, with dashes for
+//! underscores, and `--fullscreen` on its own means `--fullscreen true`.
+//! `--config FILE` reads FILE instead of config.cfg in the data directory.
+
+use crate::common::*;
+use log::LevelFilter;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// In the data directory
+pub const CONFIG_FILE_NAME: &str = "config.cfg";
+/// Relative to the working directory, like everything else in it
+pub const DEFAULT_DATA_DIRECTORY: &str = "data";
+
+const MAX_BUFFER_DIMENSION: u32 = 8192;
+const MAX_UPDATE_HZ: f32 = 1000.0;
+
+pub struct Config {
+    /// Of the offscreen buffer the game draws into
+    pub width: u32,
+    pub height: u32,
+    /// None leaves it to the platform layer, which goes by the monitor's
+    /// refresh rate where it knows it
+    pub update_hz: Option<f32>,
+    pub permanent_storage_size: usize,
+    pub transient_storage_size: usize,
+    /// Ignored when RUST_LOG is set, that can pick levels per module
+    pub log_level: LevelFilter,
+    /// For generating the world, None for a different world every run
+    pub seed: Option<u64>,
+    /// Windows only, there is no window on Linux yet
+    pub fullscreen: bool,
+    pub data_directory: String,
+}
+
+/// What the platform layers were hardcoded to before they were configurable
+pub fn default_config() -> Config {
+    Config {
+        width: 960,
+        height: 540,
+        update_hz: None,
+        permanent_storage_size: megabytes(64),
+        transient_storage_size: gigabytes(1),
+        log_level: LevelFilter::Error,
+        seed: None,
+        fullscreen: false,
+        data_directory: DEFAULT_DATA_DIRECTORY.to_string(),
+    }
+}
+
+fn parse_dimension(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(dimension) if (1..=MAX_BUFFER_DIMENSION).contains(&dimension) => Ok(dimension),
+        _ => Err(format!(
+            "\"{}\" is not a size from 1 to {} pixels",
+            value, MAX_BUFFER_DIMENSION
+        )),
+    }
+}
+
+/// Bytes, or kilobytes, megabytes, gigabytes or terabytes with a K, M, G or T
+/// on the end
+fn parse_memory_size(value: &str) -> Result<usize, String> {
+    let (number, unit): (&str, fn(usize) -> usize) = match value.char_indices().last() {
+        Some((index, 'K')) | Some((index, 'k')) => (&value[..index], kilobytes),
+        Some((index, 'M')) | Some((index, 'm')) => (&value[..index], megabytes),
+        Some((index, 'G')) | Some((index, 'g')) => (&value[..index], gigabytes),
+        Some((index, 'T')) | Some((index, 't')) => (&value[..index], terabytes),
+        _ => (value, |bytes| bytes),
+    };
+    match number.trim().parse::<usize>() {
+        Ok(count) if count > 0 && unit(1).checked_mul(count).is_some() => Ok(unit(count)),
+        _ => Err(format!(
+            "\"{}\" is not a memory size, like 64M or 1G",
+            value
+        )),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("\"{}\" is not true or false", value)),
+    }
+}
+
+/// Sets the setting called `name` from `value`. Returns false if there is no
+/// such setting.
+fn set_config_value(config: &mut Config, name: &str, value: &str) -> Result<bool, String> {
+    match name {
+        "width" => config.width = parse_dimension(value)?,
+        "height" => config.height = parse_dimension(value)?,
+        "update_hz" => {
+            config.update_hz = if value == "auto" {
+                None
+            } else {
+                match value.parse::<f32>() {
+                    Ok(hz) if hz > 0.0 && hz <= MAX_UPDATE_HZ => Some(hz),
+                    _ => {
+                        return Err(format!(
+                            "\"{}\" is not auto or a rate up to {}Hz",
+                            value, MAX_UPDATE_HZ
+                        ))
+                    }
+                }
+            }
+        }
+        "permanent_storage" => config.permanent_storage_size = parse_memory_size(value)?,
+        "transient_storage" => config.transient_storage_size = parse_memory_size(value)?,
+        "log_level" => {
+            config.log_level = value.parse().map_err(|_| {
+                format!(
+                    "\"{}\" is not off, error, warn, info, debug or trace",
+                    value
+                )
+            })?
+        }
+        "seed" => {
+            config.seed = if value == "random" {
+                None
+            } else {
+                Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("\"{}\" is not random or a number", value))?,
+                )
+            }
+        }
+        "fullscreen" => config.fullscreen = parse_bool(value)?,
+        "data_directory" => {
+            // NOTE: The game gets it in a fixed size buffer
+            if value.is_empty() || value.len() >= MAX_DATA_DIRECTORY_LENGTH {
+                return Err(format!(
+                    "the data directory has to be 1 to {} bytes long",
+                    MAX_DATA_DIRECTORY_LENGTH - 1
+                ));
+            }
+            config.data_directory = value.to_string();
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Lines with problems are skipped with a warning, the rest still count
+fn parse_config_file(config: &mut Config, text: &str, path: &Path) {
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, value) = match line.find('=') {
+            Some(equals) => (line[..equals].trim(), line[equals + 1..].trim()),
+            None => {
+                warn!("{:?} line {}: expected setting = value", path, line_number);
+                continue;
+            }
+        };
+        match set_config_value(config, name, value) {
+            Ok(true) => {}
+            Ok(false) => warn!("{:?} line {}: unknown setting {}", path, line_number, name),
+            Err(e) => warn!("{:?} line {}: {}", path, line_number, e),
+        }
+    }
+}
+
+/// Pairs each argument with the one after it, unless that is the next
+/// `--name`
+fn split_arguments(args: impl Iterator<Item = String>) -> Vec<(String, Option<String>)> {
+    let mut args = args.peekable();
+    let mut result = Vec::new();
+    while let Some(arg) = args.next() {
+        let value = match args.peek() {
+            Some(next) if !next.starts_with("--") => args.next(),
+            _ => None,
+        };
+        result.push((arg, value));
+    }
+    result
+}
+
+fn find_argument<'a>(arguments: &'a [(String, Option<String>)], name: &str) -> Option<&'a str> {
+    arguments
+        .iter()
+        .find(|(argument, _)| argument == name)
+        .and_then(|(_, value)| value.as_deref())
+}
+
+/// Reads the config file and then the settings on the command line over it,
+/// and sets the log level. Returns the arguments that aren't settings, for
+/// the platform layer to make sense of.
+pub fn load_config(args: impl Iterator<Item = String>) -> (Config, Vec<(String, Option<String>)>) {
+    let arguments = split_arguments(args);
+    let mut config = default_config();
+
+    let config_path = match find_argument(&arguments, "--config") {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(
+            find_argument(&arguments, "--data-directory").unwrap_or(DEFAULT_DATA_DIRECTORY),
+        )
+        .join(CONFIG_FILE_NAME),
+    };
+    match fs::read_to_string(&config_path) {
+        Ok(text) => {
+            parse_config_file(&mut config, &text, &config_path);
+            info!("loaded config from {:?}", config_path);
+        }
+        Err(e) => info!(
+            "using the default config, could not read {:?}: {}",
+            config_path, e
+        ),
+    }
+
+    let mut other_arguments = Vec::new();
+    for (argument, value) in arguments {
+        let name = match argument.strip_prefix("--") {
+            Some("config") => continue,
+            Some(name) => name.replace('-', "_"),
+            None => {
+                other_arguments.push((argument, value));
+                continue;
+            }
+        };
+        let value_text = match (&value, name.as_str()) {
+            (Some(value), _) => value.as_str(),
+            (None, "fullscreen") => "true",
+            (None, _) => "",
+        };
+        match set_config_value(&mut config, &name, value_text) {
+            Ok(true) => {}
+            Ok(false) => other_arguments.push((argument, value)),
+            Err(e) => warn!("ignoring {}, {}", argument, e),
+        }
+    }
+
+    if env::var_os("RUST_LOG").is_none() {
+        log::set_max_level(config.log_level);
+    }
+
+    (config, other_arguments)
+}
+
+pub fn get_game_config(config: &Config) -> GameConfig {
+    let mut result = GameConfig {
+        data_directory: [0; MAX_DATA_DIRECTORY_LENGTH],
+        seed: config.seed,
+        log_level: config.log_level,
+    };
+    copy_name(&mut result.data_directory, &config.data_directory);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parses_memory_sizes() {
+        assert_eq!(parse_memory_size("4096"), Ok(4096));
+        assert_eq!(parse_memory_size("64K"), Ok(kilobytes(64)));
+        assert_eq!(parse_memory_size("64m"), Ok(megabytes(64)));
+        assert_eq!(parse_memory_size("1G"), Ok(gigabytes(1)));
+        assert_eq!(parse_memory_size("2 T"), Ok(terabytes(2)));
+        for bad_size in &[
+            "",
+            "0",
+            "0M",
+            "-1M",
+            "1.5G",
+            "M",
+            "lots",
+            "99999999999999999T",
+        ] {
+            assert!(parse_memory_size(bad_size).is_err(), "{}", bad_size);
+        }
+    }
+
+    #[test]
+    fn parses_dimensions_and_bools() {
+        assert_eq!(parse_dimension("1"), Ok(1));
+        assert_eq!(parse_dimension("8192"), Ok(8192));
+        assert!(parse_dimension("0").is_err());
+        assert!(parse_dimension("8193").is_err());
+        assert!(parse_dimension("-5").is_err());
+
+        assert_eq!(parse_bool("Yes"), Ok(true));
+        assert_eq!(parse_bool("1"), Ok(true));
+        assert_eq!(parse_bool("OFF"), Ok(false));
+        assert!(parse_bool("maybe").is_err());
+    }
+
+    #[test]
+    fn sets_values_by_name() {
+        let mut config = default_config();
+        assert_eq!(set_config_value(&mut config, "update_hz", "60"), Ok(true));
+        assert_eq!(config.update_hz, Some(60.0));
+        assert_eq!(set_config_value(&mut config, "update_hz", "auto"), Ok(true));
+        assert_eq!(config.update_hz, None);
+        assert!(set_config_value(&mut config, "update_hz", "0").is_err());
+        assert!(set_config_value(&mut config, "update_hz", "1001").is_err());
+
+        assert_eq!(set_config_value(&mut config, "seed", "42"), Ok(true));
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(set_config_value(&mut config, "seed", "random"), Ok(true));
+        assert_eq!(config.seed, None);
+        assert!(set_config_value(&mut config, "seed", "-1").is_err());
+
+        assert_eq!(
+            set_config_value(&mut config, "log_level", "debug"),
+            Ok(true)
+        );
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert!(set_config_value(&mut config, "log_level", "loud").is_err());
+
+        assert!(set_config_value(&mut config, "data_directory", "").is_err());
+        let long_directory = "d".repeat(MAX_DATA_DIRECTORY_LENGTH);
+        assert!(set_config_value(&mut config, "data_directory", &long_directory).is_err());
+
+        assert_eq!(set_config_value(&mut config, "colour", "blue"), Ok(false));
+    }
+
+    #[test]
+    fn config_file_skips_bad_lines() {
+        let mut config = default_config();
+        let text = "# comment\n\
+                    width = 1280 # trailing comment\n\
+                    height = lots\n\
+                    no equals sign\n\
+                    unknown = 3\n\
+                    \n\
+                    transient_storage=256M\n";
+        parse_config_file(&mut config, text, Path::new("test.cfg"));
+        assert_eq!(config.width, 1280);
+        assert_eq!(config.height, default_config().height);
+        assert_eq!(config.transient_storage_size, megabytes(256));
+    }
+
+    #[test]
+    fn splits_arguments_into_pairs() {
+        let arguments = split_arguments(to_args(&[
+            "--fullscreen",
+            "--width",
+            "640",
+            "--frames",
+            "9",
+        ]));
+        assert_eq!(
+            arguments,
+            vec![
+                ("--fullscreen".to_string(), None),
+                ("--width".to_string(), Some("640".to_string())),
+                ("--frames".to_string(), Some("9".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_wins_over_the_file() {
+        let path = env::temp_dir().join(format!("config_test_{}.cfg", std::process::id()));
+        fs::write(&path, "width = 1280\nheight = 720\n").unwrap();
+        let (config, other_arguments) = load_config(to_args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--width",
+            "640",
+            "--fullscreen",
+            "--update-hz",
+            "50",
+            "--height",
+            "0",
+            "--frames",
+            "9",
+        ]));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.width, 640);
+        assert_eq!(config.height, 720);
+        assert!(config.fullscreen);
+        assert_eq!(config.update_hz, Some(50.0));
+        assert_eq!(
+            other_arguments,
+            vec![("--frames".to_string(), Some("9".to_string()))]
+        );
+    }
+
+    #[test]
+    fn game_config_is_nul_padded() {
+        let mut config = default_config();
+        config.seed = Some(7);
+        let game_config = get_game_config(&config);
+        assert_eq!(
+            get_name(&game_config.data_directory),
+            DEFAULT_DATA_DIRECTORY
+        );
+        assert_eq!(game_config.seed, Some(7));
+        assert_eq!(game_config.log_level, config.log_level);
+    }
+}
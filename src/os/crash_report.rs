@@ -0,0 +1,90 @@
+//! What the platform layers keep when the game code panics, so it can be
+//! looked into afterwards
+//!
+//! Each crash gets a directory under crashes/, named after when it happened,
+//! holding report.txt, game memory at the time of the crash as a compressed
+//! snapshot, and on Windows the replay slot that was in use, so the lead up
+//! to the crash can be looped.
+
+use super::snapshot::write_compressed_snapshot;
+use crate::common::*;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Relative to the working directory, like the saves
+pub const CRASH_REPORT_DIRECTORY: &str = "crashes";
+
+pub fn describe_game_error(error: &GameError) -> String {
+    format!(
+        "the game panicked in update {} at {}:{}:{}: {}",
+        error.frame_index,
+        get_name(&error.file),
+        error.line,
+        error.column,
+        get_name(&error.message)
+    )
+}
+
+/// A fresh directory, even for two crashes in the same second
+fn create_crash_report_directory() -> io::Result<PathBuf> {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    fs::create_dir_all(CRASH_REPORT_DIRECTORY)?;
+    let mut attempt = 0;
+    loop {
+        let path = if attempt == 0 {
+            Path::new(CRASH_REPORT_DIRECTORY).join(format!("crash_{}", seconds))
+        } else {
+            Path::new(CRASH_REPORT_DIRECTORY).join(format!("crash_{}_{}", seconds, attempt))
+        };
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// `notes` go in report.txt under the error, for whatever the platform layer
+/// knows about the crash. `replay` is game memory from the start of the
+/// replay slot in use and the file with the input recorded since. Returns the
+/// directory the report is in.
+pub fn write_crash_report(
+    error: &GameError,
+    notes: &str,
+    game_memory: &[u8],
+    replay: Option<(&[u8], &Path)>,
+) -> io::Result<PathBuf> {
+    let directory = create_crash_report_directory()?;
+
+    let mut report = fs::File::create(directory.join("report.txt"))?;
+    writeln!(report, "{}", describe_game_error(error))?;
+    writeln!(
+        report,
+        "{} build, version {}",
+        if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        },
+        env!("CARGO_PKG_VERSION")
+    )?;
+    if !notes.is_empty() {
+        writeln!(report, "{}", notes)?;
+    }
+    drop(report);
+
+    write_compressed_snapshot(&directory.join("game_memory.snap"), game_memory)?;
+    if let Some((replay_memory, replay_input_path)) = replay {
+        write_compressed_snapshot(&directory.join("replay.snap"), replay_memory)?;
+        fs::copy(replay_input_path, directory.join("replay.input"))?;
+    }
+
+    Ok(directory)
+}
@@ -0,0 +1,135 @@
+//! The file services every platform layer hands the game through
+//! `GameMemory`. Nothing here is platform specific, std does the work.
+
+use crate::common::*;
+use core::{
+    ptr::{null_mut, slice_from_raw_parts_mut},
+    slice,
+};
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// The file `read_data_from_file` read from last, left open for the next
+/// piece of it
+static OPEN_FILE: Mutex<Option<(String, fs::File)>> = Mutex::new(None);
+
+unsafe fn get_file_name<'a>(name: *const u8, name_length: usize) -> Option<&'a str> {
+    match std::str::from_utf8(slice::from_raw_parts(name, name_length)) {
+        Ok(name) => Some(name),
+        Err(e) => {
+            warn!("file name from the game is not UTF-8: {}", e);
+            None
+        }
+    }
+}
+
+pub unsafe extern "C" fn read_entire_file(
+    name: *const u8,
+    name_length: usize,
+) -> PlatformFileContents {
+    let mut result = PlatformFileContents {
+        contents: null_mut(),
+        size: 0,
+    };
+
+    if let Some(name) = get_file_name(name, name_length) {
+        match fs::read(name) {
+            Ok(contents) => {
+                let contents = Box::into_raw(contents.into_boxed_slice());
+                result.size = contents.len();
+                result.contents = contents as *mut u8;
+            }
+            // NOTE: Asking for a file that isn't there is how the game finds
+            // out it isn't there
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("could not read {}: {}", name, e),
+        }
+    }
+
+    result
+}
+
+pub unsafe extern "C" fn free_file_memory(file: PlatformFileContents) {
+    if !file.contents.is_null() {
+        drop(Box::from_raw(slice_from_raw_parts_mut(
+            file.contents,
+            file.size,
+        )));
+    }
+}
+
+fn read_data(name: &str, offset: u64, destination: &mut [u8]) -> io::Result<()> {
+    let mut open_file = OPEN_FILE
+        .lock()
+        .map_err(|_| io::Error::other("a read from another thread panicked"))?;
+    let is_open = matches!(&*open_file, Some((open_name, _)) if open_name == name);
+    if !is_open {
+        *open_file = Some((name.to_string(), fs::File::open(name)?));
+    }
+
+    if let Some((_, file)) = &mut *open_file {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(destination)?;
+    }
+    Ok(())
+}
+
+pub unsafe extern "C" fn read_data_from_file(
+    name: *const u8,
+    name_length: usize,
+    offset: u64,
+    size: usize,
+    destination: *mut u8,
+) -> bool {
+    match get_file_name(name, name_length) {
+        Some(name) => match read_data(name, offset, slice::from_raw_parts_mut(destination, size)) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("could not read {} bytes at {} in {}: {}", size, offset, name, e);
+                false
+            }
+        },
+        None => false,
+    }
+}
+
+/// Writes next to the file first and renames over it, so a crash part way
+/// through never leaves half a file behind
+fn write_file_through_temp(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_path, path)
+}
+
+pub unsafe extern "C" fn write_entire_file(
+    name: *const u8,
+    name_length: usize,
+    memory: *const u8,
+    size: usize,
+) -> bool {
+    match get_file_name(name, name_length) {
+        Some(name) => {
+            match write_file_through_temp(Path::new(name), slice::from_raw_parts(memory, size)) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("could not write {}: {}", name, e);
+                    false
+                }
+            }
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,182 @@
+//! Frame pacing shared by the platform layers: measures how long frames
+//! really take, decides how many game updates to run and with what dt, and
+//! waits out the rest of each frame.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimestepMode {
+    /// Every update advances the game by exactly the target frame time. Time
+    /// lost to slow frames is caught up with extra updates.
+    Fixed,
+    /// One update per frame, advancing the game by however long the last
+    /// frame really took
+    Variable,
+}
+
+/// What the platform should run this frame: `count` updates of `dt` seconds
+/// each. `count` is 0 when a fixed timestep frame came in early.
+pub struct FrameUpdates {
+    pub count: u32,
+    pub dt: f32,
+    /// How long the last frame really took, for display
+    pub frame_seconds: f32,
+}
+
+pub struct FrameStats {
+    pub fps: f32,
+    pub average_ms: f32,
+    pub min_ms: f32,
+    pub max_ms: f32,
+    /// Frames that took longer than the target since the pacer was made
+    pub missed_frame_count: u32,
+}
+
+const FRAME_TIME_HISTORY: usize = 120;
+
+/// Caps the catch-up after a long stall (a breakpoint, dragging the window)
+/// so the game doesn't spend the next frames simulating the stall
+const MAX_UPDATES_PER_FRAME: u32 = 4;
+
+/// Frames this close to the target count as exactly on target. Without it the
+/// accumulator drifts with timer jitter and now and then runs 0 or 2 updates.
+const SNAP_SECONDS: f32 = 0.0005;
+
+/// The sleep can overshoot by about a scheduler tick, so the last bit of the
+/// frame is spent spinning instead
+const SPIN_SECONDS: f32 = 0.002;
+
+pub struct FramePacer {
+    pub mode: TimestepMode,
+    pub target_seconds_per_frame: f32,
+    sleep_is_granular: bool,
+
+    frame_start: Option<Instant>,
+    accumulator: f32,
+
+    frame_times: [f32; FRAME_TIME_HISTORY],
+    frame_time_count: usize,
+    next_frame_time_index: usize,
+    missed_frame_count: u32,
+}
+
+/// `sleep_is_granular` says whether the OS can sleep with roughly millisecond
+/// precision. If it can't, the pacer spins for the whole wait.
+pub fn make_frame_pacer(mode: TimestepMode, target_hz: f32, sleep_is_granular: bool) -> FramePacer {
+    FramePacer {
+        mode,
+        target_seconds_per_frame: 1.0 / target_hz,
+        sleep_is_granular,
+        frame_start: None,
+        accumulator: 0.0,
+        frame_times: [0.0; FRAME_TIME_HISTORY],
+        frame_time_count: 0,
+        next_frame_time_index: 0,
+        missed_frame_count: 0,
+    }
+}
+
+/// Call at the start of every frame, before gathering input.
+pub fn begin_frame(pacer: &mut FramePacer) -> FrameUpdates {
+    let now = Instant::now();
+    let target = pacer.target_seconds_per_frame;
+
+    let mut frame_seconds = match pacer.frame_start {
+        Some(frame_start) => (now - frame_start).as_secs_f32(),
+        // NOTE: Nothing to measure on the first frame, pretend it was on time
+        None => target,
+    };
+    if pacer.frame_start.is_some() {
+        pacer.frame_times[pacer.next_frame_time_index] = frame_seconds;
+        pacer.next_frame_time_index = (pacer.next_frame_time_index + 1) % FRAME_TIME_HISTORY;
+        pacer.frame_time_count = (pacer.frame_time_count + 1).min(FRAME_TIME_HISTORY);
+    }
+    pacer.frame_start = Some(now);
+    let measured_seconds = frame_seconds;
+
+    if (frame_seconds - target).abs() < SNAP_SECONDS {
+        frame_seconds = target;
+    }
+    let max_seconds = MAX_UPDATES_PER_FRAME as f32 * target;
+
+    match pacer.mode {
+        TimestepMode::Fixed => {
+            pacer.accumulator = (pacer.accumulator + frame_seconds).min(max_seconds);
+            // NOTE: Nudged so an accumulator of exactly one frame isn't
+            // rounded down to zero updates
+            let count = ((pacer.accumulator + 0.0001 * target) / target) as u32;
+            pacer.accumulator = (pacer.accumulator - count as f32 * target).max(0.0);
+            FrameUpdates {
+                count,
+                dt: target,
+                frame_seconds: measured_seconds,
+            }
+        }
+        TimestepMode::Variable => {
+            pacer.accumulator = 0.0;
+            FrameUpdates {
+                count: 1,
+                dt: frame_seconds.min(max_seconds),
+                frame_seconds: measured_seconds,
+            }
+        }
+    }
+}
+
+/// Call once the frame's work is done. Waits until the frame has taken the
+/// target time, or returns straight away if it already took longer.
+pub fn end_frame(pacer: &mut FramePacer) {
+    let frame_start = match pacer.frame_start {
+        Some(frame_start) => frame_start,
+        None => return,
+    };
+    let frame_end = frame_start + Duration::from_secs_f32(pacer.target_seconds_per_frame);
+
+    let now = Instant::now();
+    if now >= frame_end {
+        pacer.missed_frame_count += 1;
+        trace!("missed frame rate");
+        return;
+    }
+
+    if pacer.sleep_is_granular {
+        let remaining = frame_end - now;
+        let spin = Duration::from_secs_f32(SPIN_SECONDS);
+        if remaining > spin {
+            thread::sleep(remaining - spin);
+        }
+    }
+
+    while Instant::now() < frame_end {
+        std::hint::spin_loop();
+    }
+}
+
+/// Statistics over the last couple of seconds of frames
+pub fn frame_stats(pacer: &FramePacer) -> FrameStats {
+    let frame_times = &pacer.frame_times[..pacer.frame_time_count];
+    if frame_times.is_empty() {
+        return FrameStats {
+            fps: 0.0,
+            average_ms: 0.0,
+            min_ms: 0.0,
+            max_ms: 0.0,
+            missed_frame_count: pacer.missed_frame_count,
+        };
+    }
+
+    let average = frame_times.iter().sum::<f32>() / frame_times.len() as f32;
+    let min = frame_times.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = frame_times.iter().cloned().fold(0.0, f32::max);
+
+    FrameStats {
+        fps: 1.0 / average,
+        average_ms: 1000.0 * average,
+        min_ms: 1000.0 * min,
+        max_ms: 1000.0 * max,
+        missed_frame_count: pacer.missed_frame_count,
+    }
+}
@@ -0,0 +1,854 @@
+//! equivalent to win32_handmade.cpp, for Linux
+//!
+//! There is no window yet, so this layer runs the game headless: it renders
+//! into an offscreen buffer nobody sees and sends the game's sound to one of
+//! the sinks below.
+//!
+//! Besides the settings in config.rs it takes:
+//!
+//! --audio device   pipe the samples to a `pacat` or `aplay` process (the
+//!                  default), no sound if neither is installed
+//! --audio null     throw the samples away
+//! --audio FILE.wav write the samples to a WAV file
+//! --frames N       quit after N frames instead of running forever
+//! --timestep fixed|variable (fixed by default, see `TimestepMode`)
+//! --snapshot FILE  after the first second, save game memory to FILE, load it
+//!                  at a different address, run an update from both copies,
+//!                  check they come out the same and carry on in the new
+//!                  one. FILE.snap is compressed, any other name is a
+//!                  memory-mapped copy.
+
+// NOTE: Without a window there is no keyboard to bind, the bindings are only
+// built here for their tests
+#[cfg(test)]
+mod bindings;
+mod config;
+mod crash_report;
+mod files;
+mod frame_pacing;
+mod snapshot;
+
+use crate::common::*;
+use config::*;
+use core::{
+    mem::*,
+    ptr::{self, null_mut},
+    slice,
+};
+use crash_report::*;
+use files::*;
+use frame_pacing::*;
+use snapshot::*;
+use std::{
+    env,
+    ffi::{c_void, CString},
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::atomic::Ordering,
+    time::SystemTime,
+};
+
+/// Without a monitor to go by
+const DEFAULT_GAME_UPDATE_HZ: f32 = 30.0;
+const SOUND_SAMPLES_PER_SECOND: i32 = 48000;
+const SOUND_CHANNEL_COUNT: u16 = 2;
+/// Samples a sound player can fall behind by before new ones are dropped, a
+/// quarter of a second
+const MAX_PENDING_SOUND_BYTES: usize =
+    SOUND_SAMPLES_PER_SECOND as usize / 4 * 2 * SOUND_CHANNEL_COUNT as usize;
+
+/// Where the game's sound goes once it has been mixed
+enum SoundSink {
+    Null,
+    /// A `pacat` or `aplay` process reading raw interleaved samples on stdin.
+    /// The pipe doesn't block, the bytes it had no room for wait in `pending`.
+    Player {
+        child: Child,
+        pending: Vec<u8>,
+    },
+    Wav {
+        file: BufWriter<File>,
+        sample_count: u32,
+    },
+}
+
+struct GameCode {
+    library: *mut c_void,
+    library_last_write_time: Option<SystemTime>,
+    update_and_render: Option<GameUpdateAndRender>,
+    get_sound_samples: Option<GameGetSoundSamples>,
+    /// Which of the two temp copies of the library this was loaded from
+    temp_library_index: usize,
+}
+
+const NO_GAME_CODE: GameCode = GameCode {
+    library: null_mut(),
+    library_last_write_time: None,
+    update_and_render: None,
+    get_sound_samples: None,
+    temp_library_index: 0,
+};
+
+struct Options {
+    audio: String,
+    frame_count: Option<u64>,
+    timestep_mode: TimestepMode,
+    snapshot_path: Option<PathBuf>,
+}
+
+/// From the arguments `load_config` didn't take
+fn parse_options(arguments: Vec<(String, Option<String>)>) -> Options {
+    let mut options = Options {
+        audio: "device".to_string(),
+        frame_count: None,
+        timestep_mode: TimestepMode::Fixed,
+        snapshot_path: None,
+    };
+
+    for (arg, value) in arguments {
+        match (arg.as_str(), value) {
+            ("--audio", Some(audio)) => options.audio = audio,
+            ("--frames", Some(frames)) => match frames.parse() {
+                Ok(frame_count) => options.frame_count = Some(frame_count),
+                Err(_) => warn!("ignoring --frames {}, it is not a frame count", frames),
+            },
+            ("--timestep", Some(mode)) => match mode.as_str() {
+                "fixed" => options.timestep_mode = TimestepMode::Fixed,
+                "variable" => options.timestep_mode = TimestepMode::Variable,
+                _ => warn!("ignoring --timestep {}, use fixed or variable", mode),
+            },
+            ("--snapshot", Some(path)) => options.snapshot_path = Some(PathBuf::from(path)),
+            (arg, _) => warn!("ignoring unknown argument {}", arg),
+        }
+    }
+
+    options
+}
+
+/// Anonymous mappings come back zeroed, which the game relies on. When
+/// `base_address` isn't 0 the memory has to land exactly there.
+unsafe fn allocate_game_memory(base_address: usize, size: usize) -> io::Result<*mut u8> {
+    let mut flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE;
+    if base_address != 0 {
+        // NOTE: Unlike MAP_FIXED this fails rather than replacing whatever is
+        // mapped there already. Kernels older than 4.17 ignore it and take
+        // the address as a hint, the check below catches those.
+        flags |= libc::MAP_FIXED_NOREPLACE;
+    }
+
+    let memory = libc::mmap(
+        base_address as *mut c_void,
+        size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        flags,
+        -1,
+        0,
+    );
+    if memory == libc::MAP_FAILED {
+        let error = io::Error::last_os_error();
+        return if base_address == 0 {
+            Err(error)
+        } else {
+            Err(io::Error::new(
+                error.kind(),
+                format!(
+                    "{} bytes at {:#x} are not free: {}",
+                    size, base_address, error
+                ),
+            ))
+        };
+    }
+    if base_address != 0 && memory as usize != base_address {
+        libc::munmap(memory, size);
+        return Err(io::Error::other(format!(
+            "asked for {:#x} but the kernel picked {:p}",
+            base_address, memory
+        )));
+    }
+
+    Ok(memory as *mut u8)
+}
+
+fn copy_through_snapshot(path: &Path, memory: &[u8], moved_memory: &mut [u8]) -> io::Result<()> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "snap")
+    {
+        write_compressed_snapshot(path, memory)?;
+        read_compressed_snapshot(path, moved_memory)?;
+    } else {
+        let mut snapshot = open_mapped_snapshot(path, memory.len())?;
+        if snapshot.was_existing {
+            debug!("overwriting the snapshot already in {}", path.display());
+        }
+        unsafe {
+            save_mapped_snapshot(&snapshot, memory.as_ptr());
+            restore_mapped_snapshot(&snapshot, moved_memory.as_mut_ptr());
+        }
+        close_mapped_snapshot(&mut snapshot);
+    }
+
+    info!(
+        "snapshot {} ({} on disk) loaded at {:p}, game memory is at {:p}",
+        path.display(),
+        fs::metadata(path)?.len(),
+        moved_memory.as_ptr(),
+        memory.as_ptr()
+    );
+    Ok(())
+}
+
+/// Where two blocks first differ, if they do
+fn find_first_difference<T: PartialEq>(a: &[T], b: &[T]) -> Option<usize> {
+    a.iter().zip(b.iter()).position(|(a, b)| a != b)
+}
+
+/// Runs one update from game memory where it is and one from the copy at
+/// `moved_memory`, each with the same input and its own pixels. Any pointer
+/// the game keeps into game memory that isn't relative still points at the
+/// original block, so the copy reads the original's state where it should
+/// read its own, and the two come out different. On success `game_memory`
+/// is left pointing at the copy, on failure at the original block.
+///
+/// # Safety
+///
+/// `moved_memory` must hold a copy of game memory, and `input` and `buffer`
+/// what the next update would get.
+unsafe fn check_moved_game_memory(
+    update_and_render: GameUpdateAndRender,
+    game_memory: &mut GameMemory,
+    input: &GameInput,
+    moved_memory: *mut u8,
+    buffer: &GameOffscreenBuffer,
+) -> io::Result<()> {
+    let mut moved_game_memory: GameMemory = ptr::read(game_memory);
+    moved_game_memory.permanent_storage = moved_memory;
+    moved_game_memory.transient_storage =
+        moved_memory.wrapping_add(game_memory.permanent_storage_size);
+
+    let pixel_count = buffer.width as usize * buffer.height as usize;
+    let mut pixels = vec![0u32; pixel_count];
+    let mut moved_pixels = vec![0u32; pixel_count];
+    let mut original_buffer = GameOffscreenBuffer {
+        memory: pixels.as_mut_ptr() as *mut c_void,
+        ..*buffer
+    };
+    let mut moved_buffer = GameOffscreenBuffer {
+        memory: moved_pixels.as_mut_ptr() as *mut c_void,
+        ..*buffer
+    };
+
+    let mut original_input: GameInput = ptr::read(input);
+    let mut moved_input: GameInput = ptr::read(input);
+    if !update_and_render(game_memory, &mut original_input, &mut original_buffer) {
+        return Err(io::Error::other(format!(
+            "the update before moving failed: {}",
+            describe_game_error(&game_memory.error)
+        )));
+    }
+    if !update_and_render(&mut moved_game_memory, &mut moved_input, &mut moved_buffer) {
+        return Err(io::Error::other(format!(
+            "the update after moving failed: {}",
+            describe_game_error(&moved_game_memory.error)
+        )));
+    }
+
+    let permanent_storage = slice::from_raw_parts(
+        game_memory.permanent_storage,
+        game_memory.permanent_storage_size,
+    );
+    let moved_permanent_storage =
+        slice::from_raw_parts(moved_memory, game_memory.permanent_storage_size);
+    if let Some(offset) = find_first_difference(permanent_storage, moved_permanent_storage) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "game state differs after an update at byte {:#x} of permanent storage",
+                offset
+            ),
+        ));
+    }
+    if let Some(index) = find_first_difference(&pixels, &moved_pixels) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the frame differs after an update at pixel ({}, {})",
+                index % buffer.width as usize,
+                index / buffer.width as usize
+            ),
+        ));
+    }
+
+    *game_memory = moved_game_memory;
+    Ok(())
+}
+
+/// Saves game memory to a snapshot file, loads it into a new block at
+/// another address and runs an update on both to check the game doesn't
+/// depend on where its memory is. Returns the new block once they come out
+/// the same, with `game_memory` pointing at it for the game to carry on in.
+///
+/// # Safety
+///
+/// `memory` must be the game memory block, valid for `size` bytes.
+unsafe fn move_through_snapshot(
+    path: &Path,
+    update_and_render: GameUpdateAndRender,
+    game_memory: &mut GameMemory,
+    input: &GameInput,
+    buffer: &GameOffscreenBuffer,
+    memory: *mut u8,
+    size: usize,
+) -> io::Result<*mut u8> {
+    let moved_memory = allocate_game_memory(0, size)?;
+    let mut result = copy_through_snapshot(
+        path,
+        slice::from_raw_parts(memory, size),
+        slice::from_raw_parts_mut(moved_memory, size),
+    );
+    if result.is_ok() {
+        result =
+            check_moved_game_memory(update_and_render, game_memory, input, moved_memory, buffer);
+    }
+    match result {
+        Ok(()) => Ok(moved_memory),
+        Err(e) => {
+            libc::munmap(moved_memory as *mut c_void, size);
+            Err(e)
+        }
+    }
+}
+
+fn set_nonblocking(file: &impl AsRawFd) -> io::Result<()> {
+    unsafe {
+        let fd = file.as_raw_fd();
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Tries `pacat`, for PulseAudio (or PipeWire's pulse server), first and
+/// ALSA's `aplay` second. The errors are for saying why there is no sound.
+fn start_sound_player() -> Result<Child, Vec<String>> {
+    let rate = SOUND_SAMPLES_PER_SECOND.to_string();
+    let channels = SOUND_CHANNEL_COUNT.to_string();
+    let players: [(&str, Vec<&str>); 2] = [
+        (
+            "pacat",
+            vec![
+                "--playback",
+                "--raw",
+                "--format=s16le",
+                "--rate",
+                &rate,
+                "--channels",
+                &channels,
+            ],
+        ),
+        (
+            "aplay",
+            vec![
+                "-q", "-t", "raw", "-f", "S16_LE", "-r", &rate, "-c", &channels,
+            ],
+        ),
+    ];
+
+    let mut errors = Vec::new();
+    for (program, args) in players.iter() {
+        let result = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                // NOTE: A blocking pipe would pace the frames by the player
+                // rather than the frame pacer
+                match child.stdin.as_ref().map_or(Ok(()), set_nonblocking) {
+                    Ok(()) => Ok(child),
+                    Err(e) => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        Err(e)
+                    }
+                }
+            });
+        match result {
+            Ok(child) => {
+                info!("playing sound through {}", program);
+                return Ok(child);
+            }
+            Err(e) => errors.push(format!("{}: {}", program, e)),
+        }
+    }
+
+    Err(errors)
+}
+
+fn write_wav_header(file: &mut impl Write, sample_count: u32) -> io::Result<()> {
+    let block_align = 2 * SOUND_CHANNEL_COUNT;
+    let data_size = sample_count * block_align as u32;
+
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_size).to_le_bytes())?;
+    file.write_all(b"WAVE")?;
+
+    file.write_all(b"fmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?; // PCM
+    file.write_all(&SOUND_CHANNEL_COUNT.to_le_bytes())?;
+    file.write_all(&(SOUND_SAMPLES_PER_SECOND as u32).to_le_bytes())?;
+    file.write_all(&(SOUND_SAMPLES_PER_SECOND as u32 * block_align as u32).to_le_bytes())?;
+    file.write_all(&block_align.to_le_bytes())?;
+    file.write_all(&16u16.to_le_bytes())?; // bits per sample
+
+    file.write_all(b"data")?;
+    file.write_all(&data_size.to_le_bytes())
+}
+
+fn open_sound_sink(audio: &str) -> SoundSink {
+    match audio {
+        "null" => SoundSink::Null,
+        "device" => match start_sound_player() {
+            Ok(child) => SoundSink::Player {
+                child,
+                pending: Vec::with_capacity(MAX_PENDING_SOUND_BYTES),
+            },
+            Err(errors) => {
+                warn!(
+                    "could not start a sound player ({}), sound is disabled",
+                    errors.join(", ")
+                );
+                SoundSink::Null
+            }
+        },
+        file_name => {
+            // NOTE: The sizes in the header are patched in close_sound_sink
+            let result = File::create(file_name).and_then(|file| {
+                let mut file = BufWriter::new(file);
+                write_wav_header(&mut file, 0)?;
+                Ok(file)
+            });
+            match result {
+                Ok(file) => SoundSink::Wav {
+                    file,
+                    sample_count: 0,
+                },
+                Err(e) => {
+                    error!("could not create {}: {}, sound is disabled", file_name, e);
+                    SoundSink::Null
+                }
+            }
+        }
+    }
+}
+
+/// `samples` is interleaved stereo. `bytes` is reused from frame to frame to
+/// put them in little endian order. A sink that fails is replaced by the null
+/// sink so the game keeps running.
+fn write_sound_samples(sink: &mut SoundSink, samples: &[i16], bytes: &mut Vec<u8>) {
+    bytes.clear();
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    let result = match sink {
+        SoundSink::Null => Ok(()),
+        SoundSink::Player { child, pending } => {
+            if pending.len() + bytes.len() <= MAX_PENDING_SOUND_BYTES {
+                pending.extend_from_slice(bytes);
+            } else {
+                debug!("the sound player is behind, dropping {} bytes", bytes.len());
+            }
+            match child.stdin.as_mut() {
+                Some(stdin) => write_pending_sound(stdin, pending),
+                None => Ok(()),
+            }
+        }
+        SoundSink::Wav { file, sample_count } => {
+            *sample_count += samples.len() as u32 / SOUND_CHANNEL_COUNT as u32;
+            file.write_all(bytes)
+        }
+    };
+
+    if let Err(e) = result {
+        error!("could not write sound samples: {}, sound is disabled", e);
+        close_sound_sink(replace(sink, SoundSink::Null));
+    }
+}
+
+/// Writes as much of `pending` as the pipe has room for, leaving the rest
+fn write_pending_sound(stdin: &mut impl Write, pending: &mut Vec<u8>) -> io::Result<()> {
+    while !pending.is_empty() {
+        match stdin.write(pending) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => {
+                pending.drain(..written);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn close_sound_sink(sink: SoundSink) {
+    match sink {
+        SoundSink::Null => {}
+        SoundSink::Player { mut child, .. } => {
+            drop(child.stdin.take());
+            if let Err(e) = child.wait() {
+                warn!("sound player process did not exit cleanly: {}", e);
+            }
+        }
+        SoundSink::Wav {
+            mut file,
+            sample_count,
+        } => {
+            let result = file
+                .seek(SeekFrom::Start(0))
+                .and_then(|_| write_wav_header(&mut file, sample_count))
+                .and_then(|_| file.flush());
+            if let Err(e) = result {
+                error!("could not finish the WAV file: {}", e);
+            }
+        }
+    }
+}
+
+fn get_last_write_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn dl_error() -> String {
+    unsafe {
+        let message = libc::dlerror();
+        if message.is_null() {
+            "unknown error".to_string()
+        } else {
+            std::ffi::CStr::from_ptr(message)
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
+
+unsafe fn load_game_code(source_library_path: &Path, temp_library_path: &Path) -> GameCode {
+    trace!("==load_game_code==");
+    let mut result = GameCode {
+        library_last_write_time: get_last_write_time(source_library_path),
+        ..NO_GAME_CODE
+    };
+
+    // NOTE: Load a copy so the build can overwrite the original while we run
+    if let Err(e) = fs::copy(source_library_path, temp_library_path) {
+        error!("could not copy game code library: {}", e);
+        return result;
+    }
+
+    let c_path = CString::new(temp_library_path.to_string_lossy().into_owned()).unwrap();
+    result.library = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW);
+    if result.library.is_null() {
+        error!("could not load game code library: {}", dl_error());
+        return result;
+    }
+
+    let c_update_and_render = CString::new("update_and_render").unwrap();
+    let c_get_sound_samples = CString::new("get_sound_samples").unwrap();
+
+    let update_and_render_ptr = libc::dlsym(result.library, c_update_and_render.as_ptr());
+    let get_sound_samples_ptr = libc::dlsym(result.library, c_get_sound_samples.as_ptr());
+
+    if !update_and_render_ptr.is_null() && !get_sound_samples_ptr.is_null() {
+        result.update_and_render = Some(transmute::<*mut c_void, GameUpdateAndRender>(
+            update_and_render_ptr,
+        ));
+        result.get_sound_samples = Some(transmute::<*mut c_void, GameGetSoundSamples>(
+            get_sound_samples_ptr,
+        ));
+        trace!("successfully loaded game functions")
+    } else {
+        error!("could not get the function pointers: {}", dl_error());
+    }
+
+    trace!("==load_game_code DONE==");
+    result
+}
+
+unsafe fn unload_game_code(game_code: &mut GameCode) {
+    trace!("==unload_game_code==");
+    game_code.update_and_render = None;
+    game_code.get_sound_samples = None;
+    if !game_code.library.is_null() {
+        libc::dlclose(game_code.library);
+        game_code.library = null_mut();
+    }
+    trace!("==unload_game_code DONE==")
+}
+
+/// Loads the rebuilt game code next to the code that is running, which is
+/// kept to fall back on should the new code fail to load or panic
+unsafe fn reload_game_code(
+    game: &mut GameCode,
+    previous_game: &mut GameCode,
+    source_library_path: &Path,
+    temp_library_paths: &[PathBuf; 2],
+) {
+    // NOTE: The previous code's copy is the one to overwrite. dlopen hands
+    // back whatever is already loaded from a path, so the running code's copy
+    // can't be reused.
+    unload_game_code(previous_game);
+    let temp_library_index = (game.temp_library_index + 1) % temp_library_paths.len();
+    let mut new_game = load_game_code(source_library_path, &temp_library_paths[temp_library_index]);
+    new_game.temp_library_index = temp_library_index;
+
+    if new_game.update_and_render.is_some() {
+        *previous_game = replace(game, new_game);
+    } else {
+        error!("keeping the game code that was running");
+        // NOTE: Not again until it is rebuilt
+        game.library_last_write_time = new_game.library_last_write_time;
+        unload_game_code(&mut new_game);
+    }
+}
+
+/// After the game panicked, goes back to the code from before the last reload
+/// if there is any. Otherwise the game stops until its code is rebuilt.
+unsafe fn recover_from_game_error(game: &mut GameCode, previous_game: &mut GameCode) {
+    if previous_game.update_and_render.is_some() {
+        error!("going back to the game code from before the last reload");
+        let failed_write_time = game.library_last_write_time;
+        unload_game_code(game);
+        *game = replace(previous_game, NO_GAME_CODE);
+        // NOTE: So the code that panicked isn't loaded again until it is
+        // rebuilt
+        game.library_last_write_time = failed_write_time;
+    } else {
+        error!("stopping the game until its code is rebuilt");
+        game.update_and_render = None;
+        game.get_sound_samples = None;
+    }
+}
+
+unsafe fn report_game_error(
+    game_memory: &GameMemory,
+    game_memory_block: *mut u8,
+    total_size: usize,
+) {
+    error!("{}", describe_game_error(&game_memory.error));
+    match write_crash_report(
+        &game_memory.error,
+        "",
+        slice::from_raw_parts(game_memory_block, total_size),
+        None,
+    ) {
+        Ok(directory) => error!("wrote a crash report to {}", directory.display()),
+        Err(e) => error!("could not write a crash report: {}", e),
+    }
+}
+
+pub fn main() {
+    let (config, arguments) = load_config(env::args().skip(1));
+    let options = parse_options(arguments);
+    if config.fullscreen {
+        debug!("ignoring fullscreen, there is no window to make fullscreen");
+    }
+
+    let exe_dir = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from("."));
+    let source_library_path = exe_dir.join("libgame.so");
+    let temp_library_paths = [
+        exe_dir.join("libgame_temp_0.so"),
+        exe_dir.join("libgame_temp_1.so"),
+    ];
+
+    let game_update_hz = config.update_hz.unwrap_or(DEFAULT_GAME_UPDATE_HZ);
+    // NOTE: For the things done about once a second
+    let updates_per_second = (game_update_hz.round() as u64).max(1);
+    let mut pacer = make_frame_pacer(options.timestep_mode, game_update_hz, true);
+
+    let mut sound_sink = open_sound_sink(&options.audio);
+    // NOTE: Interleaved stereo, a full second is more than any frame asks for
+    let mut sound_samples = vec![0i16; 2 * SOUND_SAMPLES_PER_SECOND as usize];
+    let mut sound_bytes = Vec::with_capacity(size_of_val(&sound_samples[..]));
+
+    let mut pixels = vec![0u32; config.width as usize * config.height as usize];
+
+    unsafe {
+        let mut game_memory: GameMemory = zeroed();
+        game_memory.permanent_storage_size = config.permanent_storage_size;
+        game_memory.transient_storage_size = config.transient_storage_size;
+        game_memory.config = get_game_config(&config);
+
+        // NOTE: Debug builds put game memory at the same address every run,
+        // like win32.rs, so pointers into it look the same from one run to
+        // the next when debugging. The game is meant to keep only relative
+        // pointers into it, which --snapshot checks by moving it elsewhere.
+        let base_address = if cfg!(debug_assertions) {
+            terabytes(2)
+        } else {
+            0
+        };
+        let total_size = game_memory.permanent_storage_size + game_memory.transient_storage_size;
+        let mut game_memory_block = match allocate_game_memory(base_address, total_size) {
+            Ok(block) => block,
+            Err(e) => {
+                error!("could not allocate game memory: {}", e);
+                close_sound_sink(sound_sink);
+                return;
+            }
+        };
+        game_memory.permanent_storage = game_memory_block;
+        game_memory.transient_storage = game_memory
+            .permanent_storage
+            .wrapping_add(game_memory.permanent_storage_size);
+        game_memory.debug_table = allocate_debug_table();
+        GLOBAL_DEBUG_TABLE.store(game_memory.debug_table, Ordering::Release);
+        game_memory.platform_read_entire_file = Some(read_entire_file);
+        game_memory.platform_free_file_memory = Some(free_file_memory);
+        game_memory.platform_write_entire_file = Some(write_entire_file);
+        game_memory.platform_read_data_from_file = Some(read_data_from_file);
+
+        // TODO: A work queue so the renderer can go wide here too
+
+        let mut input: GameInput = zeroed();
+        // NOTE: Nothing is replayed here, the input is always live
+        game_memory.live_input = &input;
+        let mut game = load_game_code(&source_library_path, &temp_library_paths[0]);
+        let mut previous_game = NO_GAME_CODE;
+
+        let mut frame_index = 0;
+        while options.frame_count.is_none_or(|count| frame_index < count) {
+            let updates = begin_frame(&mut pacer);
+            record_frame_marker(updates.frame_seconds);
+
+            let library_write_time = get_last_write_time(&source_library_path);
+            if library_write_time != game.library_last_write_time {
+                reload_game_code(
+                    &mut game,
+                    &mut previous_game,
+                    &source_library_path,
+                    &temp_library_paths,
+                );
+            }
+
+            // TODO: Keyboard and gamepad input once there is a window
+            input.dt_for_frame = updates.dt;
+            input.frame_seconds = updates.frame_seconds;
+            input.target_seconds_per_frame = pacer.target_seconds_per_frame;
+            (*get_controller(&mut input, 0)).is_connected = true;
+
+            let mut buffer = GameOffscreenBuffer {
+                memory: pixels.as_mut_ptr() as *mut c_void,
+                width: config.width as i32,
+                height: config.height as i32,
+                pitch: config.width as i32 * 4,
+                bytes_per_pixel: 4,
+            };
+
+            if let (Some(update_and_render), Some(get_sound_samples)) =
+                (game.update_and_render, game.get_sound_samples)
+            {
+                // TODO: Split update from render so catch-up updates don't
+                // each draw a frame nobody sees
+                let mut is_game_ok = true;
+                {
+                    timed_block!("game update");
+                    for _ in 0..updates.count {
+                        is_game_ok = update_and_render(&mut game_memory, &mut input, &mut buffer);
+                        if !is_game_ok {
+                            break;
+                        }
+                        input.frame_seconds = 0.0;
+                    }
+                }
+
+                let seconds_simulated = updates.count as f32 * updates.dt;
+                let mut sound_buffer = GameSoundOutputBuffer {
+                    samples_per_second: SOUND_SAMPLES_PER_SECOND,
+                    sample_count: ((SOUND_SAMPLES_PER_SECOND as f32 * seconds_simulated).round()
+                        as i32)
+                        .min(SOUND_SAMPLES_PER_SECOND),
+                    samples: sound_samples.as_mut_ptr(),
+                };
+                if is_game_ok {
+                    timed_block!("sound");
+                    is_game_ok = get_sound_samples(&mut game_memory, &mut sound_buffer);
+                    if is_game_ok {
+                        write_sound_samples(
+                            &mut sound_sink,
+                            &sound_samples[..2 * sound_buffer.sample_count as usize],
+                            &mut sound_bytes,
+                        );
+                    }
+                }
+
+                if !is_game_ok {
+                    report_game_error(&game_memory, game_memory_block, total_size);
+                    recover_from_game_error(&mut game, &mut previous_game);
+                }
+            }
+
+            {
+                timed_block!("end_frame");
+                end_frame(&mut pacer);
+            }
+
+            frame_index += 1;
+            if frame_index == updates_per_second {
+                if let (Some(snapshot_path), Some(update_and_render)) =
+                    (&options.snapshot_path, game.update_and_render)
+                {
+                    let buffer = GameOffscreenBuffer {
+                        memory: pixels.as_mut_ptr() as *mut c_void,
+                        width: config.width as i32,
+                        height: config.height as i32,
+                        pitch: config.width as i32 * 4,
+                        bytes_per_pixel: 4,
+                    };
+                    match move_through_snapshot(
+                        snapshot_path,
+                        update_and_render,
+                        &mut game_memory,
+                        &input,
+                        &buffer,
+                        game_memory_block,
+                        total_size,
+                    ) {
+                        Ok(moved_memory_block) => {
+                            libc::munmap(game_memory_block as *mut c_void, total_size);
+                            game_memory_block = moved_memory_block;
+                        }
+                        Err(e) => error!("snapshot check failed: {}", e),
+                    }
+                }
+            }
+            if frame_index % updates_per_second == 0 {
+                let stats = frame_stats(&pacer);
+                debug!(
+                    "{:.1}f/s, {:.2}ms/f (min {:.2}, max {:.2}), {} missed",
+                    stats.fps,
+                    stats.average_ms,
+                    stats.min_ms,
+                    stats.max_ms,
+                    stats.missed_frame_count
+                );
+            }
+        }
+
+        unload_game_code(&mut game);
+        unload_game_code(&mut previous_game);
+        libc::munmap(game_memory_block as *mut c_void, total_size);
+    }
+
+    close_sound_sink(sound_sink);
+}
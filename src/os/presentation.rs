@@ -0,0 +1,59 @@
+//! Where the offscreen buffer goes in the window: scaled up as far as it fits
+//! without changing its aspect ratio, centered, with black bars around it.
+
+/// The part of the window the buffer is drawn into, in window pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PresentRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Picks the largest scale that fits the buffer in the window. Integer scales
+/// keep the pixels crisp; `allow_fractional` uses all the space instead. A
+/// window smaller than the buffer always gets a fractional scale down.
+pub fn fit_buffer_to_window(
+    buffer_width: i32,
+    buffer_height: i32,
+    window_width: i32,
+    window_height: i32,
+    allow_fractional: bool,
+) -> PresentRect {
+    let fit_scale = (window_width as f32 / buffer_width as f32)
+        .min(window_height as f32 / buffer_height as f32)
+        .max(0.0);
+    let scale = if allow_fractional || fit_scale < 1.0 {
+        fit_scale
+    } else {
+        fit_scale.floor()
+    };
+
+    let width = (scale * buffer_width as f32).round() as i32;
+    let height = (scale * buffer_height as f32).round() as i32;
+
+    PresentRect {
+        x: (window_width - width) / 2,
+        y: (window_height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// Maps a point in the window back to buffer pixels. Points on the black bars
+/// map outside of the buffer, they are not clamped.
+pub fn window_to_buffer(
+    rect: PresentRect,
+    buffer_width: i32,
+    buffer_height: i32,
+    window_x: i32,
+    window_y: i32,
+) -> (i32, i32) {
+    if rect.width <= 0 || rect.height <= 0 {
+        return (0, 0);
+    }
+
+    let x = (window_x - rect.x) as f32 * buffer_width as f32 / rect.width as f32;
+    let y = (window_y - rect.y) as f32 * buffer_height as f32 / rect.height as f32;
+    (x.floor() as i32, y.floor() as i32)
+}
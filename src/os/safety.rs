@@ -1,4 +1,5 @@
 use core::{mem::zeroed, ptr::null_mut};
+use std::io;
 use winapi::{
     ctypes::c_void,
     shared::{minwindef::*, winerror::*},
@@ -8,24 +9,22 @@ use winapi::{
     },
 };
 
-pub fn copy_file_overwrite(source_path: &[u16; MAX_PATH], dest_path: &[u16; MAX_PATH]) {
+/// Fails while the source is being written, the linker holds on to it
+pub fn copy_file_overwrite(
+    source_path: &[u16; MAX_PATH],
+    dest_path: &[u16; MAX_PATH],
+) -> io::Result<()> {
     let result = unsafe { CopyFileW(source_path.as_ptr(), dest_path.as_ptr(), FALSE) };
 
     if result == 0 {
-        panic!("CopyFileW failed");
-    }
-    if unsafe { GetLastError() == ERROR_FILE_NOT_FOUND } {
-        panic!("ERROR_FILE_NOT_FOUND");
-    }
-
-    match unsafe { GetLastError() } {
-        ERROR_FILE_NOT_FOUND => panic!("ERROR_FILE_NOT_FOUND"),
-        ERROR_ACCESS_DENIED => panic!("ERROR_ACCESS_DENIED"),
-        code => panic!("Error code: {}", code),
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
     }
 }
 
-pub fn get_file_attributes(file_name: &[u16; MAX_PATH]) -> WIN32_FILE_ATTRIBUTE_DATA {
+/// None if the file does not exist or can't be looked at
+pub fn get_file_attributes(file_name: &[u16; MAX_PATH]) -> Option<WIN32_FILE_ATTRIBUTE_DATA> {
     unsafe {
         let mut data: WIN32_FILE_ATTRIBUTE_DATA = zeroed();
         let result = GetFileAttributesExW(
@@ -35,13 +34,19 @@ pub fn get_file_attributes(file_name: &[u16; MAX_PATH]) -> WIN32_FILE_ATTRIBUTE_
         );
 
         if result == 0 {
-            panic!("GetFileAttributesExW failed");
+            None
+        } else {
+            Some(data)
         }
-
-        data
     }
 }
 
+/// None if the file does not exist or can't be looked at
+pub fn get_file_size(file_name: &[u16; MAX_PATH]) -> Option<u64> {
+    get_file_attributes(file_name)
+        .map(|data| ((data.nFileSizeHigh as u64) << 32) | data.nFileSizeLow as u64)
+}
+
 pub fn get_module_file_name() -> [u16; MAX_PATH] {
     let mut file_name = [0u16; MAX_PATH];
     let result = unsafe { GetModuleFileNameW(null_mut(), &mut file_name[0], MAX_PATH as u32) };
@@ -56,22 +61,14 @@ pub fn get_module_file_name() -> [u16; MAX_PATH] {
     file_name
 }
 
+/// Null if the module has no such export
 pub fn get_proc_address(h_module: HMODULE, proc_name: LPCSTR) -> FARPROC {
-    let result = unsafe { GetProcAddress(h_module, proc_name) };
-    if result.is_null() {
-        panic!("GetProcAddress failed");
-    }
-
-    result
+    unsafe { GetProcAddress(h_module, proc_name) }
 }
 
+/// Null if the library couldn't be loaded
 pub fn load_library(path: &[u16; MAX_PATH]) -> HMODULE {
-    let result = unsafe { LoadLibraryW(path.as_ptr()) };
-    if result.is_null() {
-        panic!("LoadLibraryW failed");
-    }
-
-    result
+    unsafe { LoadLibraryW(path.as_ptr()) }
 }
 
 pub fn peek_message_remove() -> Option<MSG> {
@@ -0,0 +1,238 @@
+//! Copies of game memory on disk, the same on every platform
+//!
+//! Mapped snapshots are files mapped into memory, so taking or restoring one
+//! is a single copy and the OS writes it out in its own time. They are as big
+//! as game memory, which makes them right for the looped input recording.
+//!
+//! Compressed snapshots are for keeping: game memory is mostly zeros, so
+//! they come out small. They are written in chunks and a chunk of zeros takes
+//! no space at all.
+
+use core::ptr::{copy_nonoverlapping, null_mut};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+#[cfg(windows)]
+use winapi::{
+    ctypes::c_void,
+    um::{handleapi::CloseHandle, memoryapi::*, winnt::*},
+};
+
+const COMPRESSED_SNAPSHOT_MAGIC: [u8; 8] = *b"WSKSNAP\0";
+const COMPRESSED_SNAPSHOT_VERSION: u32 = 1;
+const COMPRESSED_SNAPSHOT_CHUNK_SIZE: usize = 1 << 20;
+/// Fast rather than small, the zero chunks are where most of the savings are
+const COMPRESSION_LEVEL: u8 = 1;
+
+/// Zeroed is a valid snapshot that was never opened.
+pub struct MappedSnapshot {
+    pub memory: *mut u8,
+    pub size: usize,
+    /// Whether the file was already there at this size, holding a snapshot
+    /// from an earlier run
+    pub was_existing: bool,
+    #[cfg(windows)]
+    mapping: HANDLE,
+}
+
+#[cfg(unix)]
+fn map_file(file: &File, size: usize) -> io::Result<*mut u8> {
+    use std::os::unix::io::AsRawFd;
+
+    let memory = unsafe {
+        libc::mmap(
+            null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    if memory == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(memory as *mut u8)
+    }
+}
+
+#[cfg(windows)]
+fn map_file(file: &File, size: usize) -> io::Result<(*mut u8, HANDLE)> {
+    use std::os::windows::io::AsRawHandle;
+
+    unsafe {
+        let mapping = CreateFileMappingW(
+            file.as_raw_handle() as HANDLE,
+            null_mut(),
+            PAGE_READWRITE,
+            (size as u64 >> 32) as u32,
+            size as u32,
+            null_mut(),
+        );
+        if mapping.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let memory = MapViewOfFile(mapping, FILE_MAP_ALL_ACCESS, 0, 0, size) as *mut u8;
+        if memory.is_null() {
+            let error = io::Error::last_os_error();
+            CloseHandle(mapping);
+            return Err(error);
+        }
+        Ok((memory, mapping))
+    }
+}
+
+/// Maps the file at `path` into memory, creating it or resizing it to `size`
+/// bytes as needed. The mapping keeps the file open, so it can be written to
+/// until `close_mapped_snapshot`, or until the process exits.
+pub fn open_mapped_snapshot(path: &Path, size: usize) -> io::Result<MappedSnapshot> {
+    let was_existing = fs::metadata(path).is_ok_and(|metadata| metadata.len() == size as u64);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    file.set_len(size as u64)?;
+
+    #[cfg(unix)]
+    let result = MappedSnapshot {
+        memory: map_file(&file, size)?,
+        size,
+        was_existing,
+    };
+    #[cfg(windows)]
+    let result = {
+        let (memory, mapping) = map_file(&file, size)?;
+        MappedSnapshot {
+            memory,
+            size,
+            was_existing,
+            mapping,
+        }
+    };
+    Ok(result)
+}
+
+pub fn close_mapped_snapshot(snapshot: &mut MappedSnapshot) {
+    if snapshot.memory.is_null() {
+        return;
+    }
+
+    unsafe {
+        #[cfg(unix)]
+        libc::munmap(snapshot.memory as *mut libc::c_void, snapshot.size);
+        #[cfg(windows)]
+        {
+            UnmapViewOfFile(snapshot.memory as *mut c_void);
+            CloseHandle(snapshot.mapping);
+        }
+    }
+    snapshot.memory = null_mut();
+}
+
+/// # Safety
+///
+/// `snapshot` must be open, and `memory` valid for `snapshot.size` bytes.
+pub unsafe fn save_mapped_snapshot(snapshot: &MappedSnapshot, memory: *const u8) {
+    copy_nonoverlapping(memory, snapshot.memory, snapshot.size);
+}
+
+/// # Safety
+///
+/// `snapshot` must be open, and `memory` valid for `snapshot.size` bytes.
+pub unsafe fn restore_mapped_snapshot(snapshot: &MappedSnapshot, memory: *mut u8) {
+    copy_nonoverlapping(snapshot.memory, memory, snapshot.size);
+}
+
+fn invalid_snapshot(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The header, then for each chunk its compressed size followed by the
+/// compressed bytes. A size of 0 is a chunk of zeros.
+pub fn write_compressed_snapshot(path: &Path, memory: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&COMPRESSED_SNAPSHOT_MAGIC)?;
+    file.write_all(&COMPRESSED_SNAPSHOT_VERSION.to_le_bytes())?;
+    file.write_all(&(memory.len() as u64).to_le_bytes())?;
+    file.write_all(&(COMPRESSED_SNAPSHOT_CHUNK_SIZE as u32).to_le_bytes())?;
+
+    for chunk in memory.chunks(COMPRESSED_SNAPSHOT_CHUNK_SIZE) {
+        if chunk.iter().all(|&byte| byte == 0) {
+            file.write_all(&0u32.to_le_bytes())?;
+        } else {
+            let compressed = miniz_oxide::deflate::compress_to_vec(chunk, COMPRESSION_LEVEL);
+            file.write_all(&(compressed.len() as u32).to_le_bytes())?;
+            file.write_all(&compressed)?;
+        }
+    }
+    file.flush()
+}
+
+fn read_u32(file: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    file.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Fills all of `memory` from a snapshot written by
+/// `write_compressed_snapshot`, which must be the same size.
+pub fn read_compressed_snapshot(path: &Path, memory: &mut [u8]) -> io::Result<()> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    if magic != COMPRESSED_SNAPSHOT_MAGIC {
+        return Err(invalid_snapshot(format!(
+            "{} is not a snapshot",
+            path.display()
+        )));
+    }
+    let version = read_u32(&mut file)?;
+    if version != COMPRESSED_SNAPSHOT_VERSION {
+        return Err(invalid_snapshot(format!(
+            "snapshot version {} is not supported",
+            version
+        )));
+    }
+    let mut size = [0; 8];
+    file.read_exact(&mut size)?;
+    let size = u64::from_le_bytes(size);
+    if size != memory.len() as u64 {
+        return Err(invalid_snapshot(format!(
+            "snapshot is {} bytes, game memory is {}",
+            size,
+            memory.len()
+        )));
+    }
+    let chunk_size = read_u32(&mut file)? as usize;
+    if chunk_size == 0 {
+        return Err(invalid_snapshot("snapshot chunk size is 0".to_string()));
+    }
+
+    let mut compressed = Vec::new();
+    for chunk in memory.chunks_mut(chunk_size) {
+        let compressed_size = read_u32(&mut file)? as usize;
+        if compressed_size == 0 {
+            chunk.fill(0);
+            continue;
+        }
+
+        compressed.resize(compressed_size, 0);
+        file.read_exact(&mut compressed)?;
+        let decompressed = miniz_oxide::inflate::decompress_to_vec(&compressed)
+            .map_err(|e| invalid_snapshot(format!("snapshot chunk is corrupt: {:?}", e)))?;
+        if decompressed.len() != chunk.len() {
+            return Err(invalid_snapshot(format!(
+                "snapshot chunk is {} bytes, expected {}",
+                decompressed.len(),
+                chunk.len()
+            )));
+        }
+        chunk.copy_from_slice(&decompressed);
+    }
+    Ok(())
+}
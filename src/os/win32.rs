@@ -6,7 +6,6 @@
   - Saved game locations
   - Getting a handle to our own executable file
   - Asset loading path
-  - Threading (launch a thread)
   - Raw Input (support for multiple keyboards)
   - ClipCursor() (for multimonitor support)
   - QueryCancelAutoplay
@@ -19,33 +18,80 @@
    Just a partial list of stuff!!
 */
 
+mod bindings;
+mod config;
+mod crash_report;
+mod files;
+mod frame_pacing;
+mod presentation;
 mod safety;
+// NOTE: Snapshots are only taken here, for the replay slots and crash reports
+#[allow(dead_code)]
+mod snapshot;
 
 use crate::common::*;
-use core::{iter::once, mem::*, ptr::null_mut};
+use bindings::*;
+use config::*;
+use core::{
+    iter::once,
+    mem::*,
+    ptr::{copy_nonoverlapping, null_mut},
+    slice,
+};
+use crash_report::*;
+use files::*;
+use frame_pacing::*;
+use presentation::*;
 use safety::*;
-use std::{ffi::*, os::windows::ffi::OsStrExt};
+use snapshot::*;
+use std::{
+    env,
+    ffi::*,
+    os::windows::ffi::OsStrExt,
+    path::PathBuf,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+};
 use winapi::{
     ctypes::c_void,
     shared::{minwindef::LRESULT, minwindef::*, windef::*, winerror::*},
     um::{
-        errhandlingapi::GetLastError, fileapi::*, handleapi::*, libloaderapi::*, memoryapi::*,
-        minwinbase::*, mmsystem::*, profileapi::*, synchapi::*, timeapi::*, wingdi::*, winnt::*,
-        winuser::*, xinput::*,
+        fileapi::*,
+        handleapi::*,
+        libloaderapi::*,
+        memoryapi::*,
+        mmsystem::*,
+        synchapi::*,
+        timeapi::*,
+        winbase::{FILE_BEGIN, INFINITE},
+        wingdi::*,
+        winnt::*,
+        winuser::*,
+        xinput::*,
     },
 };
 
 const WINDOW_NAME: &str = "Walking Skeleton";
 const WINDOW_CLASS_NAME: &str = "WalkingSkeletonWindowClass";
 
-const VK_W: i32 = 'W' as i32;
-const VK_A: i32 = 'A' as i32;
-const VK_S: i32 = 'S' as i32;
-const VK_D: i32 = 'D' as i32;
-const VK_Q: i32 = 'Q' as i32;
-const VK_E: i32 = 'E' as i32;
 const VK_P: i32 = 'P' as i32;
+const VK_K: i32 = 'K' as i32;
 const VK_L: i32 = 'L' as i32;
+const VK_T: i32 = 'T' as i32;
+
+const SOUND_SAMPLES_PER_SECOND: i32 = 48000;
+
+const REPLAY_SPEEDS: [f32; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_REPLAY_SPEED_INDEX: usize = 3;
+/// How far PageUp and PageDown jump in a replay, in recorded updates
+const REPLAY_SKIP_FRAME_COUNT: usize = 30;
+const MAX_REPLAY_SNAPSHOT_COUNT: usize = 16;
+/// Recorded updates between snapshots, at least. Longer recordings space
+/// them out so they fit in `MAX_REPLAY_SNAPSHOT_COUNT`.
+const MIN_REPLAY_SNAPSHOT_INTERVAL: usize = 60;
+
+/// Scale the buffer by any factor that fits instead of whole numbers only
+const FRACTIONAL_SCALING: bool = false;
 
 struct OffscreenBuffer {
     info: BITMAPINFO,
@@ -63,6 +109,7 @@ struct WindowDimension {
 
 static mut GLOBAL_RUNNING: bool = true;
 static mut GLOBAL_PAUSE: bool = false;
+static mut GLOBAL_VARIABLE_TIMESTEP: bool = false;
 static mut GLOBAL_BACK_BUFFER: OffscreenBuffer = OffscreenBuffer {
     info: BITMAPINFO {
         bmiHeader: BITMAPINFOHEADER {
@@ -91,7 +138,6 @@ static mut GLOBAL_BACK_BUFFER: OffscreenBuffer = OffscreenBuffer {
     pitch: 0,
     bytes_per_pixel: 4,
 };
-static mut GLOBAL_PERF_COUNT_FREQUENCY: i64 = 0;
 static mut GLOBAL_SHOW_CURSOR: bool = false;
 static mut GLOBAL_WINDOW_POSITION: WINDOWPLACEMENT = WINDOWPLACEMENT {
     length: 0,
@@ -135,34 +181,118 @@ fn build_exe_path_file_name(state: &State, file_name: &str, dest: &mut [u16; MAX
     }
 }
 
+fn build_exe_path(state: &State, file_name: &str) -> PathBuf {
+    let exe_directory =
+        String::from_utf16_lossy(&state.exe_file_name[..state.one_past_last_exe_file_name_slash]);
+    PathBuf::from(exe_directory).join(file_name)
+}
+
 struct GameCode {
     game_code_dll: HMODULE,
     dll_last_write_time: FILETIME,
-    update_and_render: GameUpdateAndRender,
-    is_valid: bool,
+    update_and_render: Option<GameUpdateAndRender>,
+    get_sound_samples: Option<GameGetSoundSamples>,
+    /// Which of the two temp copies of the dll this was loaded from
+    temp_dll_index: usize,
+}
+
+const NO_GAME_CODE: GameCode = GameCode {
+    game_code_dll: null_mut(),
+    dll_last_write_time: FILETIME {
+        dwLowDateTime: 0,
+        dwHighDateTime: 0,
+    },
+    update_and_render: None,
+    get_sound_samples: None,
+    temp_dll_index: 0,
+};
+
+#[derive(Clone, Copy)]
+struct WorkQueueEntry {
+    callback: Option<PlatformWorkQueueCallback>,
+    data: *mut c_void,
+}
+
+const WORK_QUEUE_ENTRY_COUNT: u32 = 256;
+
+/// What a `PlatformWorkQueue` pointer really points to. Only the main thread
+/// adds entries, any thread can take them.
+struct WorkQueue {
+    completion_goal: AtomicU32,
+    completion_count: AtomicU32,
+
+    next_entry_to_write: AtomicU32,
+    next_entry_to_read: AtomicU32,
+    semaphore_handle: HANDLE,
+
+    entries: [WorkQueueEntry; WORK_QUEUE_ENTRY_COUNT as usize],
 }
 
 struct ReplayBuffer {
-    file_handle: HANDLE,
-    memory_map: HANDLE,
-    file_name: [u16; MAX_PATH],
-    memory_block: *mut u8,
+    /// Game memory when the recording began
+    snapshot: MappedSnapshot,
+    /// Whether the state file and the input stream hold a recording, which
+    /// may have been made by an earlier run
+    has_recording: bool,
+    /// `GameMemory::heap_generation` when the snapshot was taken. None for a
+    /// recording from an earlier run, which the game hands what this run
+    /// holds on the heap when it's restored.
+    heap_generation: Option<u64>,
+}
+
+/// Where the looping slot is, and the controls for scrubbing through it
+struct ReplayPlayback {
+    /// Recorded updates run since the start of the loop
+    frame_index: usize,
+    frame_count: usize,
+    is_paused: bool,
+    speed_index: usize,
+    /// Fractions of a recorded update left over at slow speeds
+    update_accumulator: f32,
+    /// Recorded updates to run this frame on top of the speed, after a jump
+    /// or a step
+    pending_update_count: u32,
+
+    /// Copies of permanent storage taken every `snapshot_interval` recorded
+    /// updates the first time playback passes them, so a jump only has to
+    /// run the updates since the closest one. Allocated on first use and
+    /// kept for the next loop.
+    snapshots: [*mut u8; MAX_REPLAY_SNAPSHOT_COUNT],
+    snapshot_heap_generations: [u64; MAX_REPLAY_SNAPSHOT_COUNT],
+    snapshot_count: usize,
+    snapshot_interval: usize,
 }
 
 struct State {
     total_size: usize,
+    /// The part of the game memory block periodic replay snapshots copy
+    permanent_storage_size: usize,
     game_memory_block: *mut u8,
-    replay_buffers: [ReplayBuffer; 4],
+    replay_buffers: [ReplayBuffer; REPLAY_SLOT_COUNT],
+    /// The slot L records into and Shift+L loops, picked with F5 to F8
+    selected_replay_index: usize,
     recording_handle: HANDLE,
-    input_recording_index: usize,
+    input_recording_index: Option<usize>,
     playback_handle: HANDLE,
-    input_playing_index: usize,
+    input_playing_index: Option<usize>,
+    playback: ReplayPlayback,
+    /// `GameMemory::heap_generation` as of the last update. Snapshots from
+    /// another generation aren't restored.
+    heap_generation: u64,
     exe_file_name: [u16; MAX_PATH],
     one_past_last_exe_file_name_slash: usize,
+    /// The first half of a surrogate pair waiting for its WM_CHAR partner
+    high_surrogate: u16,
 }
 
+/// Zeroed if the file isn't there, say half way through a build
 fn get_last_write_time(filename: &[u16; MAX_PATH]) -> FILETIME {
-    get_file_attributes(filename).ftLastWriteTime
+    get_file_attributes(filename).map_or(unsafe { zeroed() }, |data| data.ftLastWriteTime)
+}
+
+/// The build writes the lock file first and deletes it once the dll is done
+fn is_game_code_being_built(lock_file_name: &[u16; MAX_PATH]) -> bool {
+    get_file_attributes(lock_file_name).is_some()
 }
 
 unsafe fn load_game_code(
@@ -171,31 +301,37 @@ unsafe fn load_game_code(
     lock_file_name: &[u16; MAX_PATH],
 ) -> GameCode {
     trace!("==load_game_code==");
-    let mut result: GameCode = zeroed();
-    let mut ignored: WIN32_FILE_ATTRIBUTE_DATA = zeroed();
-    if GetFileAttributesExW(
-        lock_file_name.as_ptr(),
-        GetFileExInfoStandard,
-        &mut ignored as *mut WIN32_FILE_ATTRIBUTE_DATA as *mut c_void,
-    ) == 0
-    {
+    let mut result = GameCode {
+        dll_last_write_time: get_last_write_time(source_dll_path),
+        ..NO_GAME_CODE
+    };
+    if !is_game_code_being_built(lock_file_name) {
         // TODO: Automatic determination of when updates are necessary.
 
-        copy_file_overwrite(source_dll_path, temp_dll_path);
+        if let Err(e) = copy_file_overwrite(source_dll_path, temp_dll_path) {
+            error!("could not copy game code dll: {}", e);
+            return result;
+        }
         result.game_code_dll = load_library(temp_dll_path);
         if !result.game_code_dll.is_null() {
             let c_update_and_render = CString::new("update_and_render").unwrap();
+            let c_get_sound_samples = CString::new("get_sound_samples").unwrap();
 
             let update_and_render_ptr =
                 get_proc_address(result.game_code_dll, c_update_and_render.as_ptr());
-
-            result.update_and_render = transmute(update_and_render_ptr);
-            result.is_valid = !update_and_render_ptr.is_null();
-            if result.is_valid {
+            let get_sound_samples_ptr =
+                get_proc_address(result.game_code_dll, c_get_sound_samples.as_ptr());
+
+            if !update_and_render_ptr.is_null() && !get_sound_samples_ptr.is_null() {
+                result.update_and_render = Some(transmute::<FARPROC, GameUpdateAndRender>(
+                    update_and_render_ptr,
+                ));
+                result.get_sound_samples = Some(transmute::<FARPROC, GameGetSoundSamples>(
+                    get_sound_samples_ptr,
+                ));
                 trace!("successfully loaded game functions")
             } else {
                 error!("could not get the function pointers");
-                result.update_and_render = zeroed();
             }
         } else {
             error!("could not load game code dll");
@@ -214,14 +350,169 @@ unsafe fn unload_game_code(game_code: &mut GameCode) {
     } else {
         warn!("dll memory was already null...")
     }
-    game_code.is_valid = false;
-
-    // These lines will crash the program in release mode
-    // game_code.update_and_render = zeroed();
-    // game_code.get_sound_samples = zeroed();
+    game_code.update_and_render = None;
+    game_code.get_sound_samples = None;
     trace!("==unload_game_code DONE==")
 }
 
+/// Loads the rebuilt game code next to the code that is running, which is
+/// kept to fall back on should the new code fail to load or panic
+unsafe fn reload_game_code(
+    game: &mut GameCode,
+    previous_game: &mut GameCode,
+    source_dll_path: &[u16; MAX_PATH],
+    temp_dll_paths: &[[u16; MAX_PATH]; 2],
+    lock_file_name: &[u16; MAX_PATH],
+) {
+    // NOTE: The previous code's copy is the one to overwrite, the running
+    // code's copy is locked while it is loaded
+    if !previous_game.game_code_dll.is_null() {
+        unload_game_code(previous_game);
+    }
+    let temp_dll_index = (game.temp_dll_index + 1) % temp_dll_paths.len();
+    let mut new_game = load_game_code(
+        source_dll_path,
+        &temp_dll_paths[temp_dll_index],
+        lock_file_name,
+    );
+    new_game.temp_dll_index = temp_dll_index;
+
+    if new_game.update_and_render.is_some() {
+        *previous_game = replace(game, new_game);
+    } else {
+        error!("keeping the game code that was running");
+        // NOTE: Not again until it is rebuilt
+        game.dll_last_write_time = new_game.dll_last_write_time;
+        if !new_game.game_code_dll.is_null() {
+            unload_game_code(&mut new_game);
+        }
+    }
+}
+
+/// After the game panicked, goes back to the code from before the last reload
+/// if there is any. Otherwise the game stops until its code is rebuilt.
+unsafe fn recover_from_game_error(game: &mut GameCode, previous_game: &mut GameCode) {
+    if previous_game.update_and_render.is_some() {
+        error!("going back to the game code from before the last reload");
+        let failed_write_time = game.dll_last_write_time;
+        unload_game_code(game);
+        *game = replace(previous_game, NO_GAME_CODE);
+        // NOTE: So the code that panicked isn't loaded again until it is
+        // rebuilt
+        game.dll_last_write_time = failed_write_time;
+    } else {
+        error!("stopping the game until its code is rebuilt");
+        game.update_and_render = None;
+        game.get_sound_samples = None;
+    }
+}
+
+unsafe extern "C" fn add_entry(
+    queue: *mut PlatformWorkQueue,
+    callback: PlatformWorkQueueCallback,
+    data: *mut c_void,
+) {
+    let queue = queue as *mut WorkQueue;
+    let next_entry_to_write = (*queue).next_entry_to_write.load(Ordering::Relaxed);
+    let new_next_entry_to_write = (next_entry_to_write + 1) % WORK_QUEUE_ENTRY_COUNT;
+    debug_assert!(
+        new_next_entry_to_write != (*queue).next_entry_to_read.load(Ordering::Acquire),
+        "work queue is full"
+    );
+
+    (*queue).entries[next_entry_to_write as usize] = WorkQueueEntry {
+        callback: Some(callback),
+        data,
+    };
+    (*queue).completion_goal.fetch_add(1, Ordering::Relaxed);
+
+    // NOTE: Release so the entry is visible before the new write index is
+    (*queue)
+        .next_entry_to_write
+        .store(new_next_entry_to_write, Ordering::Release);
+    ReleaseSemaphore((*queue).semaphore_handle, 1, null_mut());
+}
+
+/// Returns true when there was nothing to do and the caller should sleep.
+unsafe fn do_next_work_queue_entry(queue: *mut WorkQueue) -> bool {
+    let original_next_entry_to_read = (*queue).next_entry_to_read.load(Ordering::Acquire);
+    let new_next_entry_to_read = (original_next_entry_to_read + 1) % WORK_QUEUE_ENTRY_COUNT;
+    if original_next_entry_to_read != (*queue).next_entry_to_write.load(Ordering::Acquire) {
+        if (*queue)
+            .next_entry_to_read
+            .compare_exchange(
+                original_next_entry_to_read,
+                new_next_entry_to_read,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            let entry = (*queue).entries[original_next_entry_to_read as usize];
+            if let Some(callback) = entry.callback {
+                callback(queue as *mut PlatformWorkQueue, entry.data);
+            }
+            (*queue).completion_count.fetch_add(1, Ordering::AcqRel);
+        }
+        false
+    } else {
+        true
+    }
+}
+
+unsafe extern "C" fn complete_all_work(queue: *mut PlatformWorkQueue) {
+    let queue = queue as *mut WorkQueue;
+    while (*queue).completion_goal.load(Ordering::Acquire)
+        != (*queue).completion_count.load(Ordering::Acquire)
+    {
+        do_next_work_queue_entry(queue);
+    }
+
+    (*queue).completion_goal.store(0, Ordering::Relaxed);
+    (*queue).completion_count.store(0, Ordering::Relaxed);
+}
+
+/// Creates a queue with `thread_count` worker threads. The queue lives for
+/// the rest of the program, since the threads never exit.
+unsafe fn make_queue(thread_count: u32) -> *mut WorkQueue {
+    let queue = Box::into_raw(Box::new(WorkQueue {
+        completion_goal: AtomicU32::new(0),
+        completion_count: AtomicU32::new(0),
+        next_entry_to_write: AtomicU32::new(0),
+        next_entry_to_read: AtomicU32::new(0),
+        semaphore_handle: CreateSemaphoreExW(
+            null_mut(),
+            0,
+            thread_count as i32,
+            null_mut(),
+            0,
+            SEMAPHORE_ALL_ACCESS,
+        ),
+        entries: [WorkQueueEntry {
+            callback: None,
+            data: null_mut(),
+        }; WORK_QUEUE_ENTRY_COUNT as usize],
+    }));
+
+    for thread_index in 0..thread_count {
+        // NOTE: raw pointers aren't Send, so the address goes across instead
+        let queue_address = queue as usize;
+        thread::Builder::new()
+            .name(format!("work queue {}", thread_index))
+            .spawn(move || {
+                let queue = queue_address as *mut WorkQueue;
+                loop {
+                    if do_next_work_queue_entry(queue) {
+                        WaitForSingleObjectEx((*queue).semaphore_handle, INFINITE, FALSE);
+                    }
+                }
+            })
+            .expect("could not start work queue thread");
+    }
+
+    queue
+}
+
 // TODO: investigate XAudio2
 // waiting on https://github.com/retep998/winapi-rs/pull/602
 // or WASAPI
@@ -266,72 +557,63 @@ unsafe fn resize_dib_section(buffer: &mut OffscreenBuffer, width: i32, height: i
     buffer.pitch = buffer.width * buffer.bytes_per_pixel;
 }
 
+fn buffer_rect_in_window(
+    buffer: &OffscreenBuffer,
+    window_width: i32,
+    window_height: i32,
+) -> PresentRect {
+    fit_buffer_to_window(
+        buffer.width,
+        buffer.height,
+        window_width,
+        window_height,
+        FRACTIONAL_SCALING,
+    )
+}
+
 unsafe fn display_buffer_in_window(
     buffer: &OffscreenBuffer,
     device_context: HDC,
     window_width: i32,
     window_height: i32,
 ) {
-    // TODO: Centering / black bars?
-
-    if (window_width >= buffer.width * 2) && (window_height >= buffer.height * 2) {
-        StretchDIBits(
-            device_context,
-            0,
-            0,
-            2 * buffer.width,
-            2 * buffer.height,
-            0,
-            0,
-            buffer.width,
-            buffer.height,
-            buffer.memory,
-            &buffer.info,
-            DIB_RGB_COLORS,
-            SRCCOPY,
-        );
-    } else {
-        let offset_x = 10;
-        let offset_y = 10;
-
-        PatBlt(device_context, 0, 0, window_width, offset_y, BLACKNESS);
-        PatBlt(
-            device_context,
-            0,
-            offset_y + buffer.height,
-            window_width,
-            window_height,
-            BLACKNESS,
-        );
-        PatBlt(device_context, 0, 0, offset_x, window_height, BLACKNESS);
-        PatBlt(
-            device_context,
-            offset_x + buffer.width,
-            0,
-            window_width,
-            window_height,
-            BLACKNESS,
-        );
+    let rect = buffer_rect_in_window(buffer, window_width, window_height);
+
+    // NOTE: Black bars above, below, left and right of the buffer
+    PatBlt(device_context, 0, 0, window_width, rect.y, BLACKNESS);
+    PatBlt(
+        device_context,
+        0,
+        rect.y + rect.height,
+        window_width,
+        window_height - (rect.y + rect.height),
+        BLACKNESS,
+    );
+    PatBlt(device_context, 0, rect.y, rect.x, rect.height, BLACKNESS);
+    PatBlt(
+        device_context,
+        rect.x + rect.width,
+        rect.y,
+        window_width - (rect.x + rect.width),
+        rect.height,
+        BLACKNESS,
+    );
 
-        // For prototyping purposes, we're going to always blit
-        // 1-to-1 pixels to make sure we don't introduce artifacts with
-        // stretching while we are learning to code the renderer
-        StretchDIBits(
-            device_context,
-            offset_x,
-            offset_y,
-            buffer.width,
-            buffer.height,
-            0,
-            0,
-            buffer.width,
-            buffer.height,
-            buffer.memory,
-            &buffer.info,
-            DIB_RGB_COLORS,
-            SRCCOPY,
-        );
-    }
+    StretchDIBits(
+        device_context,
+        rect.x,
+        rect.y,
+        rect.width,
+        rect.height,
+        0,
+        0,
+        buffer.width,
+        buffer.height,
+        buffer.memory,
+        &buffer.info,
+        DIB_RGB_COLORS,
+        SRCCOPY,
+    );
 }
 
 unsafe extern "system" fn main_window_callback(
@@ -414,30 +696,33 @@ fn process_xinput_stick_value(value: SHORT, dead_zone_threshold: SHORT) -> f32 {
     result
 }
 
+fn get_replay_file_name(input_stream: bool, slot_index: usize) -> String {
+    format!(
+        "loop_edit_{}_{}.rec",
+        slot_index + 1,
+        if input_stream { "input" } else { "state" }
+    )
+}
+
 unsafe fn get_input_file_location(
     state: &State,
     input_stream: bool,
     slot_index: usize,
     dest: &mut [u16; MAX_PATH],
 ) {
-    let file_name = format!(
-        "loop_edit_{}_{}.rec",
-        slot_index,
-        if input_stream { "input" } else { "state" }
-    );
-    build_exe_path_file_name(state, &file_name, dest);
+    build_exe_path_file_name(state, &get_replay_file_name(input_stream, slot_index), dest);
 }
 
 unsafe fn get_replay_buffer(state: &mut State, index: usize) -> *mut ReplayBuffer {
-    debug_assert!(index > 0);
     debug_assert!(index < state.replay_buffers.len());
     &mut state.replay_buffers[index]
 }
 
 unsafe fn begin_recording_input(state: &mut State, input_recording_index: usize) {
     let replay_buffer = get_replay_buffer(state, input_recording_index);
-    if !(*replay_buffer).memory_block.is_null() {
-        state.input_recording_index = input_recording_index;
+    if !(*replay_buffer).snapshot.memory.is_null() {
+        state.input_recording_index = Some(input_recording_index);
+        (*replay_buffer).has_recording = true;
 
         let mut file_name = zeroed();
         get_input_file_location(state, true, input_recording_index, &mut file_name);
@@ -450,28 +735,79 @@ unsafe fn begin_recording_input(state: &mut State, input_recording_index: usize)
             0,
             null_mut(),
         );
-        RtlCopyMemory(
-            (*replay_buffer).memory_block as *mut c_void,
-            state.game_memory_block as *mut c_void,
-            state.total_size as usize,
-        );
+        save_mapped_snapshot(&(*replay_buffer).snapshot, state.game_memory_block);
+        (*replay_buffer).heap_generation = Some(state.heap_generation);
     } else {
         warn!("Replay buffer memory block was null when trying to begin recording.");
     }
 }
 
+/// Logs the panic and writes a crash report. A recording or playback in
+/// progress is stopped first so the report gets its slot, ending at the crash.
+unsafe fn report_game_error(state: &mut State, game_memory: &GameMemory) {
+    error!("{}", describe_game_error(&game_memory.error));
+
+    let mut notes = String::new();
+    let replay_index = if let Some(recording_index) = state.input_recording_index {
+        notes = format!("recording replay slot {}", recording_index + 1);
+        end_recording_input(state);
+        Some(recording_index)
+    } else if let Some(playing_index) = state.input_playing_index {
+        notes = format!(
+            "playing back replay slot {}, update {} of {}",
+            playing_index + 1,
+            state.playback.frame_index,
+            state.playback.frame_count
+        );
+        end_input_playback(state);
+        Some(playing_index)
+    } else {
+        None
+    };
+
+    let replay_input_path;
+    let mut replay = None;
+    if let Some(replay_index) = replay_index {
+        replay_input_path = build_exe_path(state, &get_replay_file_name(true, replay_index));
+        let snapshot = &state.replay_buffers[replay_index].snapshot;
+        replay = Some((
+            slice::from_raw_parts(snapshot.memory as *const u8, snapshot.size),
+            replay_input_path.as_path(),
+        ));
+    }
+
+    match write_crash_report(
+        &game_memory.error,
+        &notes,
+        slice::from_raw_parts(state.game_memory_block, state.total_size),
+        replay,
+    ) {
+        Ok(directory) => error!("wrote a crash report to {}", directory.display()),
+        Err(e) => error!("could not write a crash report: {}", e),
+    }
+}
+
 unsafe fn end_recording_input(state: &mut State) {
     CloseHandle(state.recording_handle);
-    state.input_recording_index = 0;
+    state.input_recording_index = None;
 }
 
 unsafe fn begin_input_playback(state: &mut State, input_playing_index: usize) {
     let replay_buffer = get_replay_buffer(state, input_playing_index);
-    if !(*replay_buffer).memory_block.is_null() {
-        state.input_playing_index = input_playing_index;
-
+    if !(*replay_buffer).snapshot.memory.is_null() && (*replay_buffer).has_recording {
         let mut file_name = zeroed();
         get_input_file_location(state, true, input_playing_index, &mut file_name);
+        let frame_count = get_file_size(&file_name).unwrap_or(0) as usize / size_of::<GameInput>();
+        if frame_count == 0 {
+            warn!(
+                "Replay slot {} has no recorded input to play back.",
+                input_playing_index + 1
+            );
+            (*replay_buffer).has_recording = false;
+            return;
+        }
+
+        state.input_playing_index = Some(input_playing_index);
         state.playback_handle = CreateFileW(
             file_name.as_ptr(),
             GENERIC_READ,
@@ -481,19 +817,188 @@ unsafe fn begin_input_playback(state: &mut State, input_playing_index: usize) {
             0,
             null_mut(),
         );
-        RtlCopyMemory(
-            state.game_memory_block as *mut c_void,
-            (*replay_buffer).memory_block as *mut c_void,
-            state.total_size as usize,
-        );
-    } else {
+
+        let playback = &mut state.playback;
+        playback.frame_count = frame_count;
+        playback.is_paused = false;
+        playback.speed_index = NORMAL_REPLAY_SPEED_INDEX;
+        playback.update_accumulator = 0.0;
+        playback.pending_update_count = 0;
+        playback.snapshot_count = 0;
+        playback.snapshot_interval =
+            MIN_REPLAY_SNAPSHOT_INTERVAL.max(frame_count.div_ceil(MAX_REPLAY_SNAPSHOT_COUNT));
+        restart_input_playback(state);
+    } else if (*replay_buffer).snapshot.memory.is_null() {
         warn!("Replay buffer memory block was null when trying to begin playback.");
+    } else {
+        warn!(
+            "Replay slot {} has no recording to play back.",
+            input_playing_index + 1
+        );
     }
 }
 
 unsafe fn end_input_playback(state: &mut State) {
     CloseHandle(state.playback_handle);
-    state.input_playing_index = 0;
+    state.input_playing_index = None;
+}
+
+unsafe fn seek_input_playback(state: &mut State, frame_index: usize) {
+    let mut distance: LARGE_INTEGER = zeroed();
+    *distance.QuadPart_mut() = (frame_index * size_of::<GameInput>()) as i64;
+    SetFilePointerEx(state.playback_handle, distance, null_mut(), FILE_BEGIN);
+    state.playback.frame_index = frame_index;
+}
+
+/// Back to the game memory the loop was recorded from. Playback ends instead
+/// if the game has taken or let go of heap memory since the recording began
+/// in this run, returning false.
+unsafe fn restart_input_playback(state: &mut State) -> bool {
+    if let Some(playing_index) = state.input_playing_index {
+        let replay_buffer = get_replay_buffer(state, playing_index);
+        if (*replay_buffer)
+            .heap_generation
+            .is_some_and(|heap_generation| heap_generation != state.heap_generation)
+        {
+            warn!(
+                "Replay slot {} was recorded before the game state last changed what it \
+                 holds on the heap, stopping playback.",
+                playing_index + 1
+            );
+            end_input_playback(state);
+            return false;
+        }
+        restore_mapped_snapshot(&(*replay_buffer).snapshot, state.game_memory_block);
+        seek_input_playback(state, 0);
+    }
+    true
+}
+
+/// Shows the frame drawn by recorded update `frame_index` (counting from 1),
+/// by restoring the closest snapshot before it and running the updates in
+/// between on the next frame
+unsafe fn jump_to_replay_frame(state: &mut State, frame_index: usize) {
+    let frame_index = frame_index.clamp(1, state.playback.frame_count);
+
+    // NOTE: Snapshots taken before the game state last changed what it holds
+    // on the heap would point at memory that was freed since, they are
+    // dropped along with every one after them
+    let current_heap_generation = state.heap_generation;
+    let playback = &mut state.playback;
+    if let Some(stale_index) = playback.snapshot_heap_generations[..playback.snapshot_count]
+        .iter()
+        .position(|&heap_generation| heap_generation != current_heap_generation)
+    {
+        warn!(
+            "Dropping replay snapshots from {} on, they are from before the game state last \
+             changed what it holds on the heap.",
+            stale_index
+        );
+        playback.snapshot_count = stale_index;
+    }
+
+    // NOTE: Restores from before the frame, so at least one update runs and
+    // draws it
+    let snapshot_count =
+        ((frame_index - 1) / state.playback.snapshot_interval).min(state.playback.snapshot_count);
+    if snapshot_count == 0 {
+        if !restart_input_playback(state) {
+            return;
+        }
+    } else {
+        // NOTE: Transient storage is scratch the game clears every frame, so
+        // periodic snapshots leave it out
+        RtlCopyMemory(
+            state.game_memory_block as *mut c_void,
+            state.playback.snapshots[snapshot_count - 1] as *mut c_void,
+            state.permanent_storage_size,
+        );
+        seek_input_playback(state, snapshot_count * state.playback.snapshot_interval);
+    }
+
+    state.playback.pending_update_count = (frame_index - state.playback.frame_index) as u32;
+    state.playback.update_accumulator = 0.0;
+}
+
+/// Takes the next periodic snapshot if playback has just reached it
+unsafe fn take_replay_snapshot_if_due(state: &mut State) {
+    let playback = &mut state.playback;
+    let snapshot_index = playback.snapshot_count;
+    if snapshot_index < MAX_REPLAY_SNAPSHOT_COUNT
+        && playback.frame_index == (snapshot_index + 1) * playback.snapshot_interval
+    {
+        if playback.snapshots[snapshot_index].is_null() {
+            playback.snapshots[snapshot_index] = VirtualAlloc(
+                null_mut(),
+                state.permanent_storage_size,
+                MEM_RESERVE | MEM_COMMIT,
+                PAGE_READWRITE,
+            ) as *mut u8;
+            if playback.snapshots[snapshot_index].is_null() {
+                warn!("Could not allocate replay snapshot {}", snapshot_index);
+                return;
+            }
+        }
+        RtlCopyMemory(
+            playback.snapshots[snapshot_index] as *mut c_void,
+            state.game_memory_block as *mut c_void,
+            state.permanent_storage_size,
+        );
+        playback.snapshot_heap_generations[snapshot_index] = state.heap_generation;
+        playback.snapshot_count += 1;
+    }
+}
+
+/// How many recorded updates to run this frame, from the replay speed and
+/// any jump or step since the last frame
+fn get_replay_update_count(state: &mut State, update_count: u32) -> u32 {
+    let playback = &mut state.playback;
+    let mut result = take(&mut playback.pending_update_count);
+    if !playback.is_paused {
+        playback.update_accumulator += REPLAY_SPEEDS[playback.speed_index] * update_count as f32;
+        let whole_update_count = playback.update_accumulator.floor();
+        playback.update_accumulator -= whole_update_count;
+        result += whole_update_count as u32;
+    }
+    result
+}
+
+/// K pauses, comma and period step back and forward, [ and ] change speed,
+/// PageUp and PageDown skip, Home and End jump to the ends
+unsafe fn process_replay_control(state: &mut State, vk_code: i32) {
+    let frame_index = state.playback.frame_index;
+    match vk_code {
+        VK_K => {
+            state.playback.is_paused = !state.playback.is_paused;
+            // NOTE: Draws the frame again so it shows the new state
+            jump_to_replay_frame(state, frame_index);
+        }
+        VK_OEM_PERIOD => {
+            state.playback.is_paused = true;
+            state.playback.pending_update_count += 1;
+        }
+        VK_OEM_COMMA => {
+            state.playback.is_paused = true;
+            jump_to_replay_frame(state, frame_index.saturating_sub(1));
+        }
+        VK_OEM_4 | VK_OEM_6 => {
+            state.playback.speed_index = if vk_code == VK_OEM_4 {
+                state.playback.speed_index.saturating_sub(1)
+            } else {
+                (state.playback.speed_index + 1).min(REPLAY_SPEEDS.len() - 1)
+            };
+            if state.playback.is_paused {
+                jump_to_replay_frame(state, frame_index);
+            }
+        }
+        VK_PRIOR => {
+            jump_to_replay_frame(state, frame_index.saturating_sub(REPLAY_SKIP_FRAME_COUNT))
+        }
+        VK_NEXT => jump_to_replay_frame(state, frame_index + REPLAY_SKIP_FRAME_COUNT),
+        VK_HOME => jump_to_replay_frame(state, 1),
+        VK_END => jump_to_replay_frame(state, state.playback.frame_count),
+        _ => {}
+    }
 }
 
 unsafe fn record_input(state: &mut State, new_input: *mut GameInput) {
@@ -508,28 +1013,46 @@ unsafe fn record_input(state: &mut State, new_input: *mut GameInput) {
 }
 
 unsafe fn play_back_input(state: &mut State, new_input: *mut GameInput) {
+    if state.playback.frame_index >= state.playback.frame_count {
+        // We've hit the end of the stream, go back to the beginning
+        if !restart_input_playback(state) {
+            return;
+        }
+    }
+    take_replay_snapshot_if_due(state);
+
     let mut bytes_read = 0;
-    if ReadFile(
+    ReadFile(
         state.playback_handle,
         new_input as *mut c_void,
         size_of::<GameInput>() as u32,
         &mut bytes_read,
         null_mut(),
-    ) != 0
-        && bytes_read == 0
-    {
-        // We've hit the end of the stream, go back to the beginning
-        let playing_index = state.input_playing_index;
-        end_input_playback(state);
-        begin_input_playback(state, playing_index);
-        ReadFile(
-            state.playback_handle,
-            new_input as *mut c_void,
-            size_of::<GameInput>() as u32,
-            &mut bytes_read,
-            null_mut(),
-        );
+    );
+    state.playback.frame_index += 1;
+}
+
+fn get_replay_status(state: &State) -> ReplayStatus {
+    let mut status = ReplayStatus {
+        selected_slot: state.selected_replay_index,
+        slots: [ReplaySlotState::Empty; REPLAY_SLOT_COUNT],
+        frame_index: state.playback.frame_index,
+        frame_count: state.playback.frame_count,
+        is_paused: state.playback.is_paused,
+        speed: REPLAY_SPEEDS[state.playback.speed_index],
+    };
+    for (slot_index, replay_buffer) in state.replay_buffers.iter().enumerate() {
+        status.slots[slot_index] = if state.input_recording_index == Some(slot_index) {
+            ReplaySlotState::Recording
+        } else if state.input_playing_index == Some(slot_index) {
+            ReplaySlotState::Looping
+        } else if replay_buffer.has_recording {
+            ReplaySlotState::Recorded
+        } else {
+            ReplaySlotState::Empty
+        };
     }
+    status
 }
 
 unsafe fn toggle_fullscreen(window: HWND) {
@@ -569,13 +1092,30 @@ unsafe fn toggle_fullscreen(window: HWND) {
     }
 }
 
-unsafe fn process_pending_messages(
-    state: &mut State,
-    keyboard_controller: &mut GameControllerInput,
-) {
+fn xinput_button_bit(pad_button: PadButton) -> WORD {
+    match pad_button {
+        PadButton::A => XINPUT_GAMEPAD_A,
+        PadButton::B => XINPUT_GAMEPAD_B,
+        PadButton::X => XINPUT_GAMEPAD_X,
+        PadButton::Y => XINPUT_GAMEPAD_Y,
+        PadButton::LeftShoulder => XINPUT_GAMEPAD_LEFT_SHOULDER,
+        PadButton::RightShoulder => XINPUT_GAMEPAD_RIGHT_SHOULDER,
+        PadButton::Start => XINPUT_GAMEPAD_START,
+        PadButton::Back => XINPUT_GAMEPAD_BACK,
+        PadButton::DPadUp => XINPUT_GAMEPAD_DPAD_UP,
+        PadButton::DPadDown => XINPUT_GAMEPAD_DPAD_DOWN,
+        PadButton::DPadLeft => XINPUT_GAMEPAD_DPAD_LEFT,
+        PadButton::DPadRight => XINPUT_GAMEPAD_DPAD_RIGHT,
+    }
+}
+
+unsafe fn process_pending_messages(state: &mut State, bindings: &Bindings, input: &mut GameInput) {
     while let Some(message) = peek_message_remove() {
         match message.message {
             WM_QUIT => GLOBAL_RUNNING = false,
+            WM_CHAR => {
+                push_utf16_text_input(input, &mut state.high_surrogate, message.wParam as u16)
+            }
             WM_SYSKEYDOWN | WM_SYSKEYUP | WM_KEYDOWN | WM_KEYUP => {
                 // letting windows handle WM_SYSKEYUP and WM_SYSKEYDOWN
                 // so I don't have to detect Alt-F4 etc.
@@ -583,87 +1123,64 @@ unsafe fn process_pending_messages(
                 let was_down = message.lParam & (1 << 30) != 0;
                 let is_down = message.lParam & (1 << 31) == 0;
 
+                if message.message == WM_KEYDOWN || message.message == WM_KEYUP {
+                    // NOTE: Queues up the WM_CHAR messages for the text input
+                    TranslateMessage(&message);
+                }
+
                 if was_down != is_down {
-                    match vk_code {
-                        VK_W => {
-                            process_keyboard_message(&mut keyboard_controller.move_up, is_down);
-                            debug!("W");
-                        }
-                        VK_A => {
-                            process_keyboard_message(&mut keyboard_controller.move_left, is_down);
-                            debug!("A");
-                        }
-                        VK_S => {
-                            process_keyboard_message(&mut keyboard_controller.move_down, is_down);
-                            debug!("S");
-                        }
-                        VK_D => {
-                            process_keyboard_message(&mut keyboard_controller.move_right, is_down);
-                            debug!("D");
-                        }
-                        VK_Q => {
-                            process_keyboard_message(
-                                &mut keyboard_controller.left_shoulder,
-                                is_down,
-                            );
-                            debug!("Q");
-                        }
-                        VK_E => {
-                            process_keyboard_message(
-                                &mut keyboard_controller.right_shoulder,
-                                is_down,
-                            );
-                            debug!("E");
-                        }
-                        VK_UP => {
-                            process_keyboard_message(&mut keyboard_controller.action_up, is_down);
-                            debug!("UP");
-                        }
-                        VK_LEFT => {
-                            process_keyboard_message(&mut keyboard_controller.action_left, is_down);
-                            debug!("LEFT");
-                        }
-                        VK_DOWN => {
-                            process_keyboard_message(&mut keyboard_controller.action_down, is_down);
-                            debug!("DOWN");
-                        }
-                        VK_RIGHT => {
+                    // NOTE: Keys are numbered by virtual key code, so every
+                    // key gets through whether it has a name or not
+                    if let Some(key_state) = input.keys.get_mut(vk_code as usize) {
+                        process_keyboard_message(key_state, is_down);
+                    }
+                    if let Some(key) = key_from_index(vk_code as usize) {
+                        for button in buttons_for_key(bindings, key) {
                             process_keyboard_message(
-                                &mut keyboard_controller.action_right,
+                                game_button(&mut input.controllers[0], button),
                                 is_down,
                             );
-                            debug!("RIGHT");
                         }
-                        VK_ESCAPE => {
-                            process_keyboard_message(&mut keyboard_controller.start, is_down);
-                            debug!("ESCAPE");
-                        }
-                        VK_SPACE => {
-                            process_keyboard_message(&mut keyboard_controller.select, is_down);
-                            debug!("SPACE");
+                    }
+
+                    match vk_code {
+                        #[cfg(debug_assertions)]
+                        VK_P if is_down => GLOBAL_PAUSE = !GLOBAL_PAUSE,
+                        VK_T if is_down => GLOBAL_VARIABLE_TIMESTEP = !GLOBAL_VARIABLE_TIMESTEP,
+                        #[cfg(debug_assertions)]
+                        VK_F5..=VK_F8 if is_down => {
+                            state.selected_replay_index = (vk_code - VK_F5) as usize;
+                            debug!("Selected replay slot {}.", state.selected_replay_index + 1);
                         }
                         #[cfg(debug_assertions)]
-                        VK_P => {
-                            if is_down {
-                                GLOBAL_PAUSE = !GLOBAL_PAUSE;
-                            }
+                        VK_K | VK_OEM_COMMA | VK_OEM_PERIOD | VK_OEM_4 | VK_OEM_6 | VK_PRIOR
+                        | VK_NEXT | VK_HOME | VK_END
+                            if is_down && state.input_playing_index.is_some() =>
+                        {
+                            process_replay_control(state, vk_code)
                         }
                         #[cfg(debug_assertions)]
-                        VK_L => {
-                            if is_down {
-                                if state.input_playing_index == 0 {
-                                    if state.input_recording_index == 0 {
-                                        debug!("Not recording, starting to record.");
-                                        begin_recording_input(state, 1);
-                                    } else {
-                                        debug!("Recording, starting playback.");
-                                        end_recording_input(state);
-                                        begin_input_playback(state, 1);
-                                    }
-                                } else {
-                                    debug!("Playing, canceling cycle.");
-                                    end_input_playback(state);
-                                }
+                        VK_L if is_down => {
+                            let shift_is_down = GetKeyState(VK_SHIFT) & (1 << 15) != 0;
+                            if let Some(playing_index) = state.input_playing_index {
+                                debug!("Playing slot {}, canceling cycle.", playing_index + 1);
+                                end_input_playback(state);
+                            } else if let Some(recording_index) = state.input_recording_index {
+                                debug!(
+                                    "Recording slot {}, starting playback.",
+                                    recording_index + 1
+                                );
+                                end_recording_input(state);
+                                begin_input_playback(state, recording_index);
+                            } else if shift_is_down {
+                                debug!("Playing back slot {}.", state.selected_replay_index + 1);
+                                begin_input_playback(state, state.selected_replay_index);
+                            } else {
+                                debug!(
+                                    "Not recording, starting to record slot {}.",
+                                    state.selected_replay_index + 1
+                                );
+                                begin_recording_input(state, state.selected_replay_index);
                             }
                         }
                         _ => {}
@@ -688,28 +1205,17 @@ unsafe fn process_pending_messages(
     }
 }
 
-fn get_wall_clock() -> LARGE_INTEGER {
-    let mut result = LARGE_INTEGER::default();
-    unsafe {
-        QueryPerformanceCounter(&mut result);
-    }
-    result
-}
-
-fn get_seconds_elapsed(start: LARGE_INTEGER, end: LARGE_INTEGER) -> f32 {
-    unsafe { (end.QuadPart() - start.QuadPart()) as f32 / GLOBAL_PERF_COUNT_FREQUENCY as f32 }
-}
-
 // TODO: refactor me and remove this allow
 #[allow(clippy::cognitive_complexity)]
 pub fn main() {
+    let (config, arguments) = load_config(env::args().skip(1));
+    for (argument, _) in arguments {
+        warn!("ignoring unknown argument {}", argument);
+    }
+
     unsafe {
         let mut win32_state = zeroed();
 
-        let mut performance_count_frequency_result = zeroed();
-        QueryPerformanceFrequency(&mut performance_count_frequency_result);
-        GLOBAL_PERF_COUNT_FREQUENCY = *performance_count_frequency_result.QuadPart();
-
         get_exe_file_name(&mut win32_state);
         let mut source_game_code_dll_full_path: [u16; MAX_PATH] = [0; MAX_PATH];
         build_exe_path_file_name(
@@ -717,12 +1223,15 @@ pub fn main() {
             "game.dll",
             &mut source_game_code_dll_full_path,
         );
-        let mut temp_game_code_dll_full_path: [u16; MAX_PATH] = [0; MAX_PATH];
-        build_exe_path_file_name(
-            &win32_state,
-            "game_temp.dll",
-            &mut temp_game_code_dll_full_path,
-        );
+        let mut temp_game_code_dll_full_paths: [[u16; MAX_PATH]; 2] = [[0; MAX_PATH]; 2];
+        for (temp_dll_index, temp_dll_path) in temp_game_code_dll_full_paths.iter_mut().enumerate()
+        {
+            build_exe_path_file_name(
+                &win32_state,
+                &format!("game_temp_{}.dll", temp_dll_index),
+                temp_dll_path,
+            );
+        }
         let mut game_code_lock_full_path: [u16; MAX_PATH] = [0; MAX_PATH];
         build_exe_path_file_name(&win32_state, "lock.tmp", &mut game_code_lock_full_path);
 
@@ -752,7 +1261,11 @@ pub fn main() {
             1080 -> 2048 = 2048-1080 -> pixels 968
             1024 + 128 = 1152
         */
-        resize_dib_section(&mut GLOBAL_BACK_BUFFER, 960, 540);
+        resize_dib_section(
+            &mut GLOBAL_BACK_BUFFER,
+            config.width as i32,
+            config.height as i32,
+        );
 
         if RegisterClassW(&window_class) > 0 {
             let window = CreateWindowExW(
@@ -771,13 +1284,19 @@ pub fn main() {
             );
 
             if !window.is_null() {
+                if config.fullscreen {
+                    toggle_fullscreen(window);
+                }
+
                 // TODO: How do we reliably query this on Windows?
                 let refresh_dc = GetDC(window);
                 let refresh_rate = GetDeviceCaps(refresh_dc, VREFRESH);
                 ReleaseDC(window, refresh_dc);
                 let monitor_refresh_hz = if refresh_rate > 1 { refresh_rate } else { 60 };
-                let game_update_hz = monitor_refresh_hz as f32 / 2.0;
-                let target_seconds_per_frame = 1.0 / game_update_hz;
+                let game_update_hz = config.update_hz.unwrap_or(monitor_refresh_hz as f32 / 2.0);
+
+                // NOTE: Interleaved stereo, a full second is more than any frame asks for
+                let mut sound_samples = vec![0i16; 2 * SOUND_SAMPLES_PER_SECOND as usize];
 
                 GLOBAL_RUNNING = true;
 
@@ -787,8 +1306,9 @@ pub fn main() {
                     null_mut::<VOID>()
                 };
                 let mut game_memory: GameMemory = zeroed();
-                game_memory.permanent_storage_size = megabytes(64);
-                game_memory.transient_storage_size = gigabytes(1);
+                game_memory.permanent_storage_size = config.permanent_storage_size;
+                game_memory.transient_storage_size = config.transient_storage_size;
+                game_memory.config = get_game_config(&config);
 
                 // TODO: Handle various memory footprints (using
                 // system metrics)
@@ -801,6 +1321,7 @@ pub fn main() {
                 // former need be saved for state playback
                 win32_state.total_size =
                     game_memory.permanent_storage_size + game_memory.transient_storage_size;
+                win32_state.permanent_storage_size = game_memory.permanent_storage_size;
                 win32_state.game_memory_block = VirtualAlloc(
                     base_address,
                     win32_state.total_size as usize,
@@ -811,55 +1332,46 @@ pub fn main() {
                 game_memory.transient_storage = game_memory
                     .permanent_storage
                     .wrapping_add(game_memory.permanent_storage_size);
-
-                for replay_index in 1..win32_state.replay_buffers.len() {
-                    let replay_buffer =
-                        (&mut win32_state.replay_buffers[replay_index]) as *mut ReplayBuffer;
-                    get_input_file_location(
-                        &win32_state,
-                        false,
-                        replay_index,
-                        &mut (*replay_buffer).file_name,
-                    );
-                    (*replay_buffer).file_handle = CreateFileW(
-                        (*replay_buffer).file_name.as_ptr(),
-                        GENERIC_READ | GENERIC_WRITE,
-                        0,
-                        null_mut(),
-                        CREATE_ALWAYS,
-                        0,
-                        null_mut(),
-                    );
-                    if (*replay_buffer).file_handle == INVALID_HANDLE_VALUE {
-                        let last_error = GetLastError();
-                        error!("Could not create file: {:?}", last_error);
-                    }
-                    let mut max_size: LARGE_INTEGER = zeroed();
-                    *max_size.QuadPart_mut() = win32_state.total_size as i64;
-                    (*replay_buffer).memory_map = CreateFileMappingW(
-                        (*replay_buffer).file_handle,
-                        null_mut(),
-                        PAGE_READWRITE,
-                        max_size.u().HighPart as u32,
-                        max_size.u().LowPart as u32,
-                        null_mut(),
-                    );
-                    let last_error = GetLastError();
-                    if (*replay_buffer).memory_map.is_null() {
-                        error!("Could not map file to object: {:?}", last_error);
-                    } else if last_error == ERROR_ALREADY_EXISTS {
-                        warn!("Object existed before mapping call.");
-                    }
-                    (*replay_buffer).memory_block = MapViewOfFile(
-                        (*replay_buffer).memory_map,
-                        FILE_MAP_ALL_ACCESS,
-                        0,
-                        0,
-                        win32_state.total_size as usize,
-                    ) as *mut u8;
-                    if (*replay_buffer).memory_block.is_null() {
-                        let last_error = GetLastError();
-                        error!("Replay buffer {} error: {:?}", replay_index, last_error);
+                game_memory.debug_table = allocate_debug_table();
+                GLOBAL_DEBUG_TABLE.store(game_memory.debug_table, Ordering::Release);
+
+                // NOTE: The main thread works on the queue too while it waits
+                // in complete_all_work, so leave it a core
+                let worker_thread_count = thread::available_parallelism()
+                    .map(|count| count.get() as u32)
+                    .unwrap_or(1)
+                    .saturating_sub(1)
+                    .max(1);
+                game_memory.high_priority_queue =
+                    make_queue(worker_thread_count) as *mut PlatformWorkQueue;
+                game_memory.platform_add_entry = Some(add_entry);
+                game_memory.platform_complete_all_work = Some(complete_all_work);
+                game_memory.platform_read_entire_file = Some(read_entire_file);
+                game_memory.platform_free_file_memory = Some(free_file_memory);
+                game_memory.platform_write_entire_file = Some(write_entire_file);
+                game_memory.platform_read_data_from_file = Some(read_data_from_file);
+
+                // NOTE: The state files are kept between runs, so a slot
+                // recorded last time can be looped again with Shift+L
+                for replay_index in 0..win32_state.replay_buffers.len() {
+                    let state_path =
+                        build_exe_path(&win32_state, &get_replay_file_name(false, replay_index));
+                    let mut input_file_name = zeroed();
+                    get_input_file_location(&win32_state, true, replay_index, &mut input_file_name);
+                    match open_mapped_snapshot(&state_path, win32_state.total_size) {
+                        Ok(snapshot) => {
+                            let replay_buffer = &mut win32_state.replay_buffers[replay_index];
+                            replay_buffer.has_recording = snapshot.was_existing
+                                && get_file_size(&input_file_name).is_some_and(|size| size > 0);
+                            replay_buffer.heap_generation = None;
+                            replay_buffer.snapshot = snapshot;
+                        }
+                        Err(e) => error!(
+                            "Replay buffer {} could not map {}: {}",
+                            replay_index,
+                            state_path.display(),
+                            e
+                        ),
                     }
                 }
 
@@ -868,24 +1380,45 @@ pub fn main() {
                 {
                     let mut new_input: GameInput = zeroed();
                     let mut old_input: GameInput = zeroed();
-
-                    let mut last_counter = get_wall_clock();
+                    // NOTE: new_input before a replay overwrites it
+                    let mut live_input: GameInput = zeroed();
+                    game_memory.live_input = &live_input;
+
+                    let mut pacer =
+                        make_frame_pacer(TimestepMode::Fixed, game_update_hz, sleep_is_granular);
+                    let mut bindings_file = open_bindings_file(
+                        PathBuf::from(&config.data_directory).join(BINDINGS_FILE_NAME),
+                    );
 
                     let mut game = load_game_code(
                         &source_game_code_dll_full_path,
-                        &temp_game_code_dll_full_path,
+                        &temp_game_code_dll_full_paths[0],
                         &game_code_lock_full_path,
                     );
+                    let mut previous_game = NO_GAME_CODE;
 
                     while GLOBAL_RUNNING {
-                        new_input.dt_for_frame = target_seconds_per_frame;
+                        pacer.mode = if GLOBAL_VARIABLE_TIMESTEP {
+                            TimestepMode::Variable
+                        } else {
+                            TimestepMode::Fixed
+                        };
+                        let updates = begin_frame(&mut pacer);
+                        record_frame_marker(updates.frame_seconds);
+                        new_input.dt_for_frame = updates.dt;
+                        new_input.frame_seconds = updates.frame_seconds;
+                        new_input.target_seconds_per_frame = pacer.target_seconds_per_frame;
+                        reload_bindings_if_changed(&mut bindings_file);
                         let new_dll_write_time =
                             get_last_write_time(&source_game_code_dll_full_path);
-                        if CompareFileTime(&new_dll_write_time, &game.dll_last_write_time) != 0 {
-                            unload_game_code(&mut game);
-                            game = load_game_code(
+                        if CompareFileTime(&new_dll_write_time, &game.dll_last_write_time) != 0
+                            && !is_game_code_being_built(&game_code_lock_full_path)
+                        {
+                            reload_game_code(
+                                &mut game,
+                                &mut previous_game,
                                 &source_game_code_dll_full_path,
-                                &temp_game_code_dll_full_path,
+                                &temp_game_code_dll_full_paths,
                                 &game_code_lock_full_path,
                             );
                         }
@@ -924,18 +1457,50 @@ pub fn main() {
                         (*new_keyboard_controller).terminator.ended_down =
                             (*old_keyboard_controller).terminator.ended_down;
 
+                        for (new_key, old_key) in
+                            new_input.keys.iter_mut().zip(old_input.keys.iter())
+                        {
+                            new_key.ended_down = old_key.ended_down;
+                            new_key.half_transition_count = 0;
+                        }
+                        // NOTE: Text from a frame that ran no update is still
+                        // waiting for the game, anything delivered was cleared
+                        new_input.text_input = old_input.text_input;
+                        new_input.text_input_count = old_input.text_input_count;
+
                         process_pending_messages(
                             &mut win32_state,
-                            new_keyboard_controller.as_mut().unwrap(),
+                            &bindings_file.bindings,
+                            &mut new_input,
                         );
 
                         if !GLOBAL_PAUSE {
                             let mut mouse_p: POINT = zeroed();
                             GetCursorPos(&mut mouse_p);
                             ScreenToClient(window, &mut mouse_p);
-                            new_input.mouse_x = mouse_p.x;
-                            new_input.mouse_y = mouse_p.y;
+                            let dimension = get_window_dimension(window);
+                            let (mouse_x, mouse_y) = window_to_buffer(
+                                buffer_rect_in_window(
+                                    &GLOBAL_BACK_BUFFER,
+                                    dimension.width,
+                                    dimension.height,
+                                ),
+                                GLOBAL_BACK_BUFFER.width,
+                                GLOBAL_BACK_BUFFER.height,
+                                mouse_p.x,
+                                mouse_p.y,
+                            );
+                            new_input.mouse_x = mouse_x;
+                            new_input.mouse_y = mouse_y;
                             new_input.mouse_z = 0;
+                            for (new_button, old_button) in new_input
+                                .mouse_buttons
+                                .iter_mut()
+                                .zip(old_input.mouse_buttons.iter())
+                            {
+                                new_button.ended_down = old_button.ended_down;
+                                new_button.half_transition_count = 0;
+                            }
                             process_keyboard_message(
                                 &mut new_input.mouse_buttons[0],
                                 GetKeyState(VK_LBUTTON) & (1 << 15) != 0,
@@ -1019,97 +1584,32 @@ pub fn main() {
                                     }
 
                                     let threshold = 0.5;
-                                    process_xinput_digital_button(
-                                        if (*new_controller).stick_average_x < -threshold {
-                                            1
-                                        } else {
-                                            0
-                                        },
-                                        &(*old_controller).move_left,
-                                        1,
-                                        &mut (*new_controller).move_left,
-                                    );
-                                    process_xinput_digital_button(
-                                        if (*new_controller).stick_average_x > threshold {
-                                            1
-                                        } else {
-                                            0
-                                        },
-                                        &(*old_controller).move_right,
-                                        1,
-                                        &mut (*new_controller).move_right,
-                                    );
-                                    process_xinput_digital_button(
-                                        if (*new_controller).stick_average_y < -threshold {
-                                            1
-                                        } else {
-                                            0
-                                        },
-                                        &(*old_controller).move_down,
-                                        1,
-                                        &mut (*new_controller).move_down,
-                                    );
-                                    process_xinput_digital_button(
-                                        if (*new_controller).stick_average_y > threshold {
-                                            1
-                                        } else {
-                                            0
-                                        },
-                                        &(*old_controller).move_up,
-                                        1,
-                                        &mut (*new_controller).move_up,
-                                    );
-
-                                    // TODO: How to handle nintendo vs xbox controller differences?
-                                    process_xinput_digital_button(
-                                        pad.wButtons,
-                                        &(*old_controller).action_down,
-                                        XINPUT_GAMEPAD_B,
-                                        &mut (*new_controller).action_down,
-                                    );
-                                    process_xinput_digital_button(
-                                        pad.wButtons,
-                                        &(*old_controller).action_right,
-                                        XINPUT_GAMEPAD_A,
-                                        &mut (*new_controller).action_right,
-                                    );
-                                    process_xinput_digital_button(
-                                        pad.wButtons,
-                                        &(*old_controller).action_left,
-                                        XINPUT_GAMEPAD_Y,
-                                        &mut (*new_controller).action_left,
-                                    );
-                                    process_xinput_digital_button(
-                                        pad.wButtons,
-                                        &(*old_controller).action_up,
-                                        XINPUT_GAMEPAD_X,
-                                        &mut (*new_controller).action_up,
-                                    );
-                                    process_xinput_digital_button(
-                                        pad.wButtons,
-                                        &(*old_controller).left_shoulder,
-                                        XINPUT_GAMEPAD_LEFT_SHOULDER,
-                                        &mut (*new_controller).left_shoulder,
-                                    );
-                                    process_xinput_digital_button(
-                                        pad.wButtons,
-                                        &(*old_controller).right_shoulder,
-                                        XINPUT_GAMEPAD_RIGHT_SHOULDER,
-                                        &mut (*new_controller).right_shoulder,
-                                    );
-
-                                    process_xinput_digital_button(
-                                        pad.wButtons,
-                                        &(*old_controller).start,
-                                        XINPUT_GAMEPAD_START,
-                                        &mut (*new_controller).start,
-                                    );
-                                    process_xinput_digital_button(
-                                        pad.wButtons,
-                                        &(*old_controller).select,
-                                        XINPUT_GAMEPAD_START,
-                                        &mut (*new_controller).select,
-                                    );
+                                    let stick_x = (*new_controller).stick_average_x;
+                                    let stick_y = (*new_controller).stick_average_y;
+                                    for button in all_game_buttons() {
+                                        let stick_down = match button {
+                                            GameButton::MoveLeft => stick_x < -threshold,
+                                            GameButton::MoveRight => stick_x > threshold,
+                                            GameButton::MoveDown => stick_y < -threshold,
+                                            GameButton::MoveUp => stick_y > threshold,
+                                            _ => false,
+                                        };
+                                        let is_down = stick_down
+                                            || is_bound_pad_button_down(
+                                                &bindings_file.bindings,
+                                                button,
+                                                |pad_button| {
+                                                    pad.wButtons & xinput_button_bit(pad_button)
+                                                        != 0
+                                                },
+                                            );
+                                        process_xinput_digital_button(
+                                            if is_down { 1 } else { 0 },
+                                            game_button(&mut *old_controller, button),
+                                            1,
+                                            game_button(&mut *new_controller, button),
+                                        );
+                                    }
                                 } else {
                                     (*new_controller).is_connected = false;
                                     trace!("controller is not available");
@@ -1124,49 +1624,71 @@ pub fn main() {
                                 bytes_per_pixel: GLOBAL_BACK_BUFFER.bytes_per_pixel,
                             };
 
-                            if win32_state.input_recording_index != 0 {
-                                record_input(&mut win32_state, &mut new_input);
-                            }
+                            let mut has_game_panicked = false;
+                            if let Some(update_and_render) = game.update_and_render {
+                                timed_block!("game update");
+                                let update_count = if win32_state.input_playing_index.is_some() {
+                                    get_replay_update_count(&mut win32_state, updates.count)
+                                } else {
+                                    updates.count
+                                };
+                                // TODO: Split update from render so catch-up
+                                // updates don't each draw a frame nobody sees
+                                for _ in 0..update_count {
+                                    if win32_state.input_recording_index.is_some() {
+                                        record_input(&mut win32_state, &mut new_input);
+                                    }
 
-                            if win32_state.input_playing_index != 0 {
-                                play_back_input(&mut win32_state, &mut new_input);
-                            }
-                            (game.update_and_render)(&mut game_memory, &mut new_input, &mut buffer);
-
-                            let work_counter = get_wall_clock();
-                            let work_seconds_elapsed =
-                                get_seconds_elapsed(last_counter, work_counter);
-
-                            // TODO: NOT TESTED YET! PROBABLY BUGGY!!!
-                            let mut seconds_elapsed_for_frame = work_seconds_elapsed;
-                            if seconds_elapsed_for_frame < target_seconds_per_frame {
-                                if sleep_is_granular {
-                                    let sleep_ms = 1000.0
-                                        * (target_seconds_per_frame - seconds_elapsed_for_frame);
-                                    if sleep_ms > 0.0 {
-                                        Sleep(sleep_ms as u32);
+                                    copy_nonoverlapping(&new_input, &mut live_input, 1);
+                                    if win32_state.input_playing_index.is_some() {
+                                        play_back_input(&mut win32_state, &mut new_input);
                                     }
+                                    game_memory.replay_status = get_replay_status(&win32_state);
+                                    if !update_and_render(
+                                        &mut game_memory,
+                                        &mut new_input,
+                                        &mut buffer,
+                                    ) {
+                                        has_game_panicked = true;
+                                        break;
+                                    }
+                                    win32_state.heap_generation = game_memory.heap_generation;
+                                    // NOTE: So catch-up updates don't type it twice
+                                    // or count the frame twice
+                                    new_input.text_input_count = 0;
+                                    new_input.frame_seconds = 0.0;
                                 }
+                            }
 
-                                let test_seconds_elapsed_for_frame =
-                                    get_seconds_elapsed(last_counter, get_wall_clock());
-                                if test_seconds_elapsed_for_frame < target_seconds_per_frame {
-                                    warn!("missed sleep");
-                                }
+                            let seconds_simulated = updates.count as f32 * updates.dt;
+                            let mut sound_buffer = GameSoundOutputBuffer {
+                                samples_per_second: SOUND_SAMPLES_PER_SECOND,
+                                sample_count:
+                                    ((SOUND_SAMPLES_PER_SECOND as f32 * seconds_simulated).round()
+                                        as i32)
+                                        .min(SOUND_SAMPLES_PER_SECOND),
+                                samples: sound_samples.as_mut_ptr(),
+                            };
+                            if let (false, Some(get_sound_samples)) =
+                                (has_game_panicked, game.get_sound_samples)
+                            {
+                                timed_block!("sound");
+                                has_game_panicked =
+                                    !get_sound_samples(&mut game_memory, &mut sound_buffer);
+                                // TODO: Hand the samples to an output device (see XAudio2 note)
+                            }
 
-                                while seconds_elapsed_for_frame < target_seconds_per_frame {
-                                    seconds_elapsed_for_frame =
-                                        get_seconds_elapsed(last_counter, get_wall_clock());
-                                }
-                            } else {
-                                trace!("missed frame rate");
+                            if has_game_panicked {
+                                report_game_error(&mut win32_state, &game_memory);
+                                recover_from_game_error(&mut game, &mut previous_game);
                             }
 
-                            let end_counter = get_wall_clock();
-                            let ms_per_frame =
-                                1000.0 * get_seconds_elapsed(last_counter, end_counter);
-                            last_counter = end_counter;
+                            {
+                                timed_block!("end_frame");
+                                end_frame(&mut pacer);
+                            }
 
+                            timed_block!("display");
                             let dimension = get_window_dimension(window);
                             let device_context = GetDC(window);
                             display_buffer_in_window(
@@ -1179,9 +1701,15 @@ pub fn main() {
 
                             std::mem::swap(&mut new_input, &mut old_input);
 
-                            let fps = 0.0;
-
-                            trace!("{}ms/f, {}f/s", ms_per_frame, fps);
+                            let stats = frame_stats(&pacer);
+                            trace!(
+                                "{:.2}ms/f, {:.1}f/s (min {:.2}ms, max {:.2}ms, {} missed)",
+                                stats.average_ms,
+                                stats.fps,
+                                stats.min_ms,
+                                stats.max_ms,
+                                stats.missed_frame_count
+                            );
                         }
                     }
                 } else {
@@ -0,0 +1,374 @@
+//! Collates the events `timed_block!` records into frames of nested blocks,
+//! keeps the last few frames around for the debug overlay, and writes them
+//! out as Chrome trace JSON (chrome://tracing or ui.perfetto.dev).
+
+use crate::common::*;
+use std::{fs, io, sync::atomic::Ordering};
+
+pub const CHROME_TRACE_FILE_NAME: &str = "profile_trace.json";
+
+const PROFILED_FRAME_COUNT: usize = 32;
+
+pub struct ProfiledBlock {
+    /// Into `Profiler::names`
+    pub name: usize,
+    pub thread_id: u32,
+    pub depth: u32,
+    /// Into the blocks of the same frame. Parents always come first.
+    pub parent: Option<usize>,
+    pub begin_clock: u64,
+    /// 0 while the block is open
+    pub end_clock: u64,
+}
+
+#[derive(Default)]
+pub struct ProfiledFrame {
+    pub begin_clock: u64,
+    pub end_clock: u64,
+    pub seconds_elapsed: f32,
+    /// In the order they began
+    pub blocks: Vec<ProfiledBlock>,
+    pub dropped_event_count: usize,
+}
+
+/// The blocks a thread is inside of, innermost last
+struct OpenBlocks {
+    thread_id: u32,
+    blocks: Vec<usize>,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    names: Vec<String>,
+    /// `names` index for each name in the debug table
+    table_names: Vec<usize>,
+    threads: Vec<OpenBlocks>,
+    current_frame: ProfiledFrame,
+    /// The last complete frames, oldest first from `next_frame_index`
+    frames: Vec<ProfiledFrame>,
+    next_frame_index: usize,
+
+    // NOTE: For turning cycles into seconds
+    total_frame_cycles: u64,
+    total_frame_seconds: f64,
+}
+
+/// Picks up names registered since the last collation. Stops at a name that
+/// is still being written, it is picked up next time.
+unsafe fn sync_debug_names(profiler: &mut Profiler, table: *mut DebugTable) {
+    let name_count =
+        ((*table).name_count.load(Ordering::Acquire) as usize).min(MAX_DEBUG_NAME_COUNT);
+    while profiler.table_names.len() < name_count {
+        let bytes = &(&(*table).names)[profiler.table_names.len()];
+        let length = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(bytes.len());
+        if length == 0 {
+            break;
+        }
+
+        // NOTE: A reloaded game registers its names again, they are merged here
+        let name = String::from_utf8_lossy(&bytes[..length]).into_owned();
+        let index = match profiler.names.iter().position(|known| *known == name) {
+            Some(index) => index,
+            None => {
+                profiler.names.push(name);
+                profiler.names.len() - 1
+            }
+        };
+        profiler.table_names.push(index);
+    }
+}
+
+fn open_blocks_for(threads: &mut Vec<OpenBlocks>, thread_id: u32) -> &mut Vec<usize> {
+    let index = match threads
+        .iter()
+        .position(|thread| thread.thread_id == thread_id)
+    {
+        Some(index) => index,
+        None => {
+            threads.push(OpenBlocks {
+                thread_id,
+                blocks: Vec::new(),
+            });
+            threads.len() - 1
+        }
+    };
+    &mut threads[index].blocks
+}
+
+fn end_profiled_frame(profiler: &mut Profiler, clock: u64, seconds_elapsed: f32) {
+    let mut frame = std::mem::take(&mut profiler.current_frame);
+    frame.end_clock = clock;
+    frame.seconds_elapsed = seconds_elapsed;
+
+    // NOTE: Blocks that are still open are cut at the frame boundary and
+    // carry on in the next frame
+    let mut next_frame = ProfiledFrame {
+        begin_clock: clock,
+        ..ProfiledFrame::default()
+    };
+    for thread in &mut profiler.threads {
+        for (stack_index, open_block) in thread.blocks.iter_mut().enumerate() {
+            let block = &mut frame.blocks[*open_block];
+            block.end_clock = clock;
+            // NOTE: The parent is the continuation pushed just before
+            let parent = if stack_index == 0 {
+                None
+            } else {
+                Some(next_frame.blocks.len() - 1)
+            };
+            next_frame.blocks.push(ProfiledBlock {
+                name: block.name,
+                thread_id: block.thread_id,
+                depth: block.depth,
+                parent,
+                begin_clock: clock,
+                end_clock: 0,
+            });
+            *open_block = next_frame.blocks.len() - 1;
+        }
+    }
+    profiler.current_frame = next_frame;
+
+    // NOTE: The events before the first frame marker are not a whole frame
+    if frame.begin_clock == 0 {
+        return;
+    }
+
+    if seconds_elapsed > 0.0 {
+        profiler.total_frame_cycles += frame.end_clock - frame.begin_clock;
+        profiler.total_frame_seconds += seconds_elapsed as f64;
+    }
+
+    if profiler.frames.len() < PROFILED_FRAME_COUNT {
+        profiler.frames.push(frame);
+    } else {
+        profiler.frames[profiler.next_frame_index] = frame;
+        profiler.next_frame_index = (profiler.next_frame_index + 1) % PROFILED_FRAME_COUNT;
+    }
+}
+
+/// Call once per update, before any timed blocks of the update begin.
+///
+/// # Safety
+///
+/// `table` must be null or the table the platform layer records into.
+pub unsafe fn collate_debug_events(profiler: &mut Profiler, table: *mut DebugTable) {
+    if table.is_null() {
+        return;
+    }
+
+    let (events, dropped_event_count) = swap_debug_event_arrays(table);
+    profiler.current_frame.dropped_event_count += dropped_event_count;
+    sync_debug_names(profiler, table);
+
+    for event in events {
+        match event.event_type {
+            DebugEventType::FrameMarker { seconds_elapsed } => {
+                end_profiled_frame(profiler, event.clock, seconds_elapsed)
+            }
+            DebugEventType::BeginBlock => {
+                let name = match profiler.table_names.get(event.name_index as usize) {
+                    Some(&name) => name,
+                    None => continue,
+                };
+                let block_index = profiler.current_frame.blocks.len();
+                let open_blocks = open_blocks_for(&mut profiler.threads, event.thread_id);
+                let parent = open_blocks.last().cloned();
+                let depth = open_blocks.len() as u32;
+                open_blocks.push(block_index);
+                profiler.current_frame.blocks.push(ProfiledBlock {
+                    name,
+                    thread_id: event.thread_id,
+                    depth,
+                    parent,
+                    begin_clock: event.clock,
+                    end_clock: 0,
+                });
+            }
+            DebugEventType::EndBlock => {
+                let name = match profiler.table_names.get(event.name_index as usize) {
+                    Some(&name) => name,
+                    None => continue,
+                };
+                let blocks = &mut profiler.current_frame.blocks;
+                let open_blocks = open_blocks_for(&mut profiler.threads, event.thread_id);
+                // NOTE: Blocks whose end was dropped are closed along with
+                // their parent
+                if let Some(position) = open_blocks
+                    .iter()
+                    .rposition(|&block| blocks[block].name == name)
+                {
+                    for block in open_blocks.drain(position..) {
+                        blocks[block].end_clock = event.clock;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn get_profiled_block_name<'a>(profiler: &'a Profiler, block: &ProfiledBlock) -> &'a str {
+    &profiler.names[block.name]
+}
+
+/// The most recent complete frame
+pub fn get_last_profiled_frame(profiler: &Profiler) -> Option<&ProfiledFrame> {
+    if profiler.frames.is_empty() {
+        None
+    } else {
+        let count = profiler.frames.len();
+        Some(&profiler.frames[(profiler.next_frame_index + count - 1) % count])
+    }
+}
+
+/// Cycles per second, measured over every profiled frame so far. 0 until a
+/// whole frame has been seen.
+pub fn get_cycles_per_second(profiler: &Profiler) -> f64 {
+    if profiler.total_frame_seconds > 0.0 {
+        profiler.total_frame_cycles as f64 / profiler.total_frame_seconds
+    } else {
+        0.0
+    }
+}
+
+fn get_block_end_clock(frame: &ProfiledFrame, block: &ProfiledBlock) -> u64 {
+    if block.end_clock == 0 {
+        frame.end_clock
+    } else {
+        block.end_clock
+    }
+}
+
+struct ProfileTreeNode {
+    name: usize,
+    parent: Option<usize>,
+    cycles: u64,
+    hit_count: u32,
+}
+
+fn push_profile_tree_lines(
+    profiler: &Profiler,
+    nodes: &[ProfileTreeNode],
+    parent: Option<usize>,
+    depth: usize,
+    lines: &mut Vec<String>,
+) {
+    let cycles_per_second = get_cycles_per_second(profiler);
+    for (node_index, node) in nodes.iter().enumerate() {
+        if node.parent != parent {
+            continue;
+        }
+
+        let time = if cycles_per_second > 0.0 {
+            format!("{:.2}ms", 1000.0 * node.cycles as f64 / cycles_per_second)
+        } else {
+            format!("{:.2}Mcy", node.cycles as f64 / 1_000_000.0)
+        };
+        lines.push(format!(
+            "{:indent$}{} {} {}x",
+            "",
+            profiler.names[node.name],
+            time,
+            node.hit_count,
+            indent = 2 * depth
+        ));
+        push_profile_tree_lines(profiler, nodes, Some(node_index), depth + 1, lines);
+    }
+}
+
+/// One line per block of the last frame, indented under its parent. Blocks
+/// with the same name under the same parent are added together, across
+/// threads too.
+pub fn get_profile_tree_lines(profiler: &Profiler) -> Vec<String> {
+    let frame = match get_last_profiled_frame(profiler) {
+        Some(frame) => frame,
+        None => return Vec::new(),
+    };
+
+    let mut nodes: Vec<ProfileTreeNode> = Vec::new();
+    let mut block_nodes = Vec::with_capacity(frame.blocks.len());
+    for block in &frame.blocks {
+        let parent = block.parent.map(|parent| block_nodes[parent]);
+        let node_index = match nodes
+            .iter()
+            .position(|node| node.parent == parent && node.name == block.name)
+        {
+            Some(node_index) => node_index,
+            None => {
+                nodes.push(ProfileTreeNode {
+                    name: block.name,
+                    parent,
+                    cycles: 0,
+                    hit_count: 0,
+                });
+                nodes.len() - 1
+            }
+        };
+        nodes[node_index].cycles +=
+            get_block_end_clock(frame, block).saturating_sub(block.begin_clock);
+        nodes[node_index].hit_count += 1;
+        block_nodes.push(node_index);
+    }
+
+    let mut lines = Vec::new();
+    push_profile_tree_lines(profiler, &nodes, None, 0, &mut lines);
+    lines
+}
+
+fn escape_json(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Writes every frame the profiler still has, oldest first, with times in
+/// microseconds from the start of the oldest frame.
+pub fn write_chrome_trace(profiler: &Profiler, file_name: &str) -> io::Result<()> {
+    let cycles_per_second = get_cycles_per_second(profiler);
+    if cycles_per_second <= 0.0 {
+        return Err(io::Error::other("no whole frames profiled yet"));
+    }
+
+    let count = profiler.frames.len();
+    let frames =
+        (0..count).map(|index| &profiler.frames[(profiler.next_frame_index + index) % count]);
+    let base_clock = frames.clone().next().map_or(0, |frame| frame.begin_clock);
+    let microseconds = |cycles: u64| 1_000_000.0 * cycles as f64 / cycles_per_second;
+
+    // NOTE: Blocks from worker threads were timed on other cores, whose cycle
+    // counters can be a little behind, so differences stop at 0
+    let mut events = Vec::new();
+    for frame in frames {
+        events.push(format!(
+            r#"{{"name":"frame","ph":"i","s":"g","ts":{:.3},"pid":0,"tid":0}}"#,
+            microseconds(frame.begin_clock.saturating_sub(base_clock))
+        ));
+        for block in &frame.blocks {
+            events.push(format!(
+                r#"{{"name":"{}","ph":"X","ts":{:.3},"dur":{:.3},"pid":0,"tid":{}}}"#,
+                escape_json(get_profiled_block_name(profiler, block)),
+                microseconds(block.begin_clock.saturating_sub(base_clock)),
+                microseconds(get_block_end_clock(frame, block).saturating_sub(block.begin_clock)),
+                block.thread_id
+            ));
+        }
+    }
+
+    fs::write(
+        file_name,
+        format!(
+            "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n{}\n]}}\n",
+            events.join(",\n")
+        ),
+    )
+}
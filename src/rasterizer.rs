@@ -0,0 +1,452 @@
+//! Inner loops that fill and blend pixels into a `GameOffscreenBuffer`.
+//!
+//! The SSE2 and AVX2 paths must produce exactly the same pixels as the scalar
+//! path. They do the same float operations in the same order, just 4 or 8
+//! pixels at a time, so keep them in sync when changing the blend.
+
+use crate::asset::*;
+use crate::common::*;
+use crate::render_group::*;
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rasterizer {
+    Scalar,
+    Sse2,
+    Avx2,
+}
+
+pub fn is_rasterizer_supported(rasterizer: Rasterizer) -> bool {
+    match rasterizer {
+        Rasterizer::Scalar => true,
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Rasterizer::Sse2 => is_x86_feature_detected!("sse2"),
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Rasterizer::Avx2 => is_x86_feature_detected!("avx2"),
+        #[allow(unreachable_patterns)]
+        _ => false,
+    }
+}
+
+pub fn best_rasterizer() -> Rasterizer {
+    [Rasterizer::Avx2, Rasterizer::Sse2]
+        .iter()
+        .copied()
+        .find(|rasterizer| is_rasterizer_supported(*rasterizer))
+        .unwrap_or(Rasterizer::Scalar)
+}
+
+/// A bitmap draw that has already been clipped to the buffer.
+pub struct BitmapBlit<'a> {
+    pub bitmap: &'a LoadedBitmap,
+    /// Where the top left corner of the (unclipped) bitmap lands in the buffer
+    pub origin_x: i32,
+    pub origin_y: i32,
+    /// The buffer pixels to write, max exclusive
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+    /// First bitmap column to sample from, for picking animation frames
+    pub x_offset: i32,
+    /// First bitmap row to sample from, for picking glyphs out of an atlas
+    pub y_offset: i32,
+    /// Bitmap pixels per buffer pixel
+    pub inv_scale: f32,
+}
+
+unsafe fn pixel_pointer(buffer: &GameOffscreenBuffer, x: i32, y: i32) -> *mut u32 {
+    #[allow(clippy::cast_ptr_alignment)]
+    let result = (buffer.memory as *mut u8)
+        .offset((x * buffer.bytes_per_pixel + y * buffer.pitch) as isize)
+        as *mut u32;
+    result
+}
+
+/// Fills the buffer pixels from min to max (exclusive), which must already be
+/// clipped to the buffer.
+pub fn fill_rectangle(
+    rasterizer: Rasterizer,
+    buffer: &GameOffscreenBuffer,
+    min_x: i32,
+    min_y: i32,
+    max_x: i32,
+    max_y: i32,
+    color: u32,
+) {
+    if min_x >= max_x || min_y >= max_y {
+        return;
+    }
+
+    debug_assert!(is_rasterizer_supported(rasterizer));
+    unsafe {
+        match rasterizer {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Rasterizer::Sse2 => fill_rectangle_sse2(buffer, min_x, min_y, max_x, max_y, color),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Rasterizer::Avx2 => fill_rectangle_avx2(buffer, min_x, min_y, max_x, max_y, color),
+            _ => fill_rectangle_scalar(buffer, min_x, min_y, max_x, max_y, color),
+        }
+    }
+}
+
+/// Blends the bitmap into the buffer. Texels that fall outside the bitmap are
+/// skipped, leaving the buffer untouched.
+pub fn blit_bitmap(rasterizer: Rasterizer, buffer: &GameOffscreenBuffer, blit: &BitmapBlit) {
+    if blit.min_x >= blit.max_x || blit.min_y >= blit.max_y {
+        return;
+    }
+
+    debug_assert!(is_rasterizer_supported(rasterizer));
+    unsafe {
+        match rasterizer {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Rasterizer::Sse2 => blit_bitmap_sse2(buffer, blit),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Rasterizer::Avx2 => blit_bitmap_avx2(buffer, blit),
+            _ => blit_bitmap_scalar(buffer, blit),
+        }
+    }
+}
+
+unsafe fn fill_rectangle_scalar(
+    buffer: &GameOffscreenBuffer,
+    min_x: i32,
+    min_y: i32,
+    max_x: i32,
+    max_y: i32,
+    color: u32,
+) {
+    for y in min_y..max_y {
+        let mut pixel = pixel_pointer(buffer, min_x, y);
+        for _x in min_x..max_x {
+            *pixel = color;
+            pixel = pixel.add(1);
+        }
+    }
+}
+
+/// Premultiplied "over": dest * (1 - alpha) + texel, done in linear space.
+/// Both colors are 0x__RRGGBB in sRGB, the result has no alpha.
+fn blend_premultiplied(dest: u32, texel: u32) -> u32 {
+    let sa = (texel >> 24) as f32 / 255.0;
+    let sr = srgb255_to_linear1(((texel >> 16) & 0xFF) as f32);
+    let sg = srgb255_to_linear1(((texel >> 8) & 0xFF) as f32);
+    let sb = srgb255_to_linear1((texel & 0xFF) as f32);
+
+    let dr = srgb255_to_linear1(((dest >> 16) & 0xFF) as f32);
+    let dg = srgb255_to_linear1(((dest >> 8) & 0xFF) as f32);
+    let db = srgb255_to_linear1((dest & 0xFF) as f32);
+
+    let r = linear1_to_srgb255((1.0 - sa) * dr + sr).min(255.0);
+    let g = linear1_to_srgb255((1.0 - sa) * dg + sg).min(255.0);
+    let b = linear1_to_srgb255((1.0 - sa) * db + sb).min(255.0);
+
+    (((r + 0.5) as u32) << 16) | ((g + 0.5) as u32) << 8 | (b + 0.5) as u32
+}
+
+/// Returns the offset of the first texel of the bitmap row that buffer row
+/// `y` samples from, or None if that row is outside the bitmap.
+fn source_row_for(blit: &BitmapBlit, y: i32) -> Option<usize> {
+    let source_y = (blit.y_offset + ((y - blit.origin_y) as f32 * blit.inv_scale) as i32) as u32;
+    if source_y < blit.bitmap.height {
+        Some((source_y * blit.bitmap.width) as usize)
+    } else {
+        None
+    }
+}
+
+unsafe fn blit_pixel_scalar(blit: &BitmapBlit, source_row: usize, x: i32, dest: *mut u32) {
+    let source_x = (blit.x_offset + ((x - blit.origin_x) as f32 * blit.inv_scale) as i32) as u32;
+    if source_x < blit.bitmap.width {
+        let texel = blit.bitmap.memory[source_row + source_x as usize];
+        *dest = blend_premultiplied(*dest, texel);
+    }
+}
+
+unsafe fn blit_bitmap_scalar(buffer: &GameOffscreenBuffer, blit: &BitmapBlit) {
+    for y in blit.min_y..blit.max_y {
+        if let Some(source_row) = source_row_for(blit, y) {
+            let mut dest = pixel_pointer(buffer, blit.min_x, y);
+            for x in blit.min_x..blit.max_x {
+                blit_pixel_scalar(blit, source_row, x, dest);
+                dest = dest.add(1);
+            }
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn fill_rectangle_sse2(
+    buffer: &GameOffscreenBuffer,
+    min_x: i32,
+    min_y: i32,
+    max_x: i32,
+    max_y: i32,
+    color: u32,
+) {
+    let wide_color = _mm_set1_epi32(color as i32);
+    for y in min_y..max_y {
+        let mut pixel = pixel_pointer(buffer, min_x, y);
+        let mut x = min_x;
+        while x + 4 <= max_x {
+            _mm_storeu_si128(pixel as *mut __m128i, wide_color);
+            pixel = pixel.add(4);
+            x += 4;
+        }
+        while x < max_x {
+            *pixel = color;
+            pixel = pixel.add(1);
+            x += 1;
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn srgb255_to_linear1_sse2(value: __m128) -> __m128 {
+    let result = _mm_div_ps(value, _mm_set1_ps(255.0));
+    _mm_mul_ps(result, result)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn linear1_to_srgb255_sse2(value: __m128) -> __m128 {
+    _mm_mul_ps(_mm_set1_ps(255.0), _mm_sqrt_ps(value))
+}
+
+/// Pulls the 8 bit channel at `shift` out of each pixel as a float
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn unpack_channel_sse2(pixels: __m128i, shift: i32) -> __m128 {
+    let shifted = _mm_srl_epi32(pixels, _mm_cvtsi32_si128(shift));
+    _mm_cvtepi32_ps(_mm_and_si128(shifted, _mm_set1_epi32(0xFF)))
+}
+
+/// Four pixel version of `blend_premultiplied`
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn blend_premultiplied_sse2(dest: __m128i, texel: __m128i) -> __m128i {
+    let sa = _mm_div_ps(unpack_channel_sse2(texel, 24), _mm_set1_ps(255.0));
+    let sr = srgb255_to_linear1_sse2(unpack_channel_sse2(texel, 16));
+    let sg = srgb255_to_linear1_sse2(unpack_channel_sse2(texel, 8));
+    let sb = srgb255_to_linear1_sse2(unpack_channel_sse2(texel, 0));
+
+    let dr = srgb255_to_linear1_sse2(unpack_channel_sse2(dest, 16));
+    let dg = srgb255_to_linear1_sse2(unpack_channel_sse2(dest, 8));
+    let db = srgb255_to_linear1_sse2(unpack_channel_sse2(dest, 0));
+
+    let inv_sa = _mm_sub_ps(_mm_set1_ps(1.0), sa);
+    let max_value = _mm_set1_ps(255.0);
+    let r = _mm_min_ps(
+        linear1_to_srgb255_sse2(_mm_add_ps(_mm_mul_ps(inv_sa, dr), sr)),
+        max_value,
+    );
+    let g = _mm_min_ps(
+        linear1_to_srgb255_sse2(_mm_add_ps(_mm_mul_ps(inv_sa, dg), sg)),
+        max_value,
+    );
+    let b = _mm_min_ps(
+        linear1_to_srgb255_sse2(_mm_add_ps(_mm_mul_ps(inv_sa, db), sb)),
+        max_value,
+    );
+
+    let half = _mm_set1_ps(0.5);
+    let r = _mm_cvttps_epi32(_mm_add_ps(r, half));
+    let g = _mm_cvttps_epi32(_mm_add_ps(g, half));
+    let b = _mm_cvttps_epi32(_mm_add_ps(b, half));
+
+    _mm_or_si128(_mm_or_si128(_mm_slli_epi32(r, 16), _mm_slli_epi32(g, 8)), b)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn blit_bitmap_sse2(buffer: &GameOffscreenBuffer, blit: &BitmapBlit) {
+    let inv_scale = _mm_set1_ps(blit.inv_scale);
+    let lane_offsets = _mm_setr_epi32(0, 1, 2, 3);
+    let x_offset = _mm_set1_epi32(blit.x_offset);
+    let bitmap_width = _mm_set1_epi32(blit.bitmap.width as i32);
+
+    for y in blit.min_y..blit.max_y {
+        if let Some(source_row) = source_row_for(blit, y) {
+            let mut dest = pixel_pointer(buffer, blit.min_x, y);
+            let mut x = blit.min_x;
+            while x + 4 <= blit.max_x {
+                let rel_x = _mm_add_epi32(_mm_set1_epi32(x - blit.origin_x), lane_offsets);
+                let source_x = _mm_add_epi32(
+                    x_offset,
+                    _mm_cvttps_epi32(_mm_mul_ps(_mm_cvtepi32_ps(rel_x), inv_scale)),
+                );
+                let write_mask = _mm_cmplt_epi32(source_x, bitmap_width);
+
+                // NOTE: SSE2 has no gather, so fetch the texels one at a time
+                let mut source_xs = [0i32; 4];
+                _mm_storeu_si128(source_xs.as_mut_ptr() as *mut __m128i, source_x);
+                let mut texels = [0u32; 4];
+                for (texel, source_x) in texels.iter_mut().zip(source_xs.iter()) {
+                    if (*source_x as u32) < blit.bitmap.width {
+                        *texel = blit.bitmap.memory[source_row + *source_x as usize];
+                    }
+                }
+
+                let texel = _mm_loadu_si128(texels.as_ptr() as *const __m128i);
+                let original_dest = _mm_loadu_si128(dest as *const __m128i);
+                let blended = blend_premultiplied_sse2(original_dest, texel);
+                let out = _mm_or_si128(
+                    _mm_and_si128(write_mask, blended),
+                    _mm_andnot_si128(write_mask, original_dest),
+                );
+                _mm_storeu_si128(dest as *mut __m128i, out);
+
+                dest = dest.add(4);
+                x += 4;
+            }
+            while x < blit.max_x {
+                blit_pixel_scalar(blit, source_row, x, dest);
+                dest = dest.add(1);
+                x += 1;
+            }
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn fill_rectangle_avx2(
+    buffer: &GameOffscreenBuffer,
+    min_x: i32,
+    min_y: i32,
+    max_x: i32,
+    max_y: i32,
+    color: u32,
+) {
+    let wide_color = _mm256_set1_epi32(color as i32);
+    for y in min_y..max_y {
+        let mut pixel = pixel_pointer(buffer, min_x, y);
+        let mut x = min_x;
+        while x + 8 <= max_x {
+            _mm256_storeu_si256(pixel as *mut __m256i, wide_color);
+            pixel = pixel.add(8);
+            x += 8;
+        }
+        while x < max_x {
+            *pixel = color;
+            pixel = pixel.add(1);
+            x += 1;
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn srgb255_to_linear1_avx2(value: __m256) -> __m256 {
+    let result = _mm256_div_ps(value, _mm256_set1_ps(255.0));
+    _mm256_mul_ps(result, result)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn linear1_to_srgb255_avx2(value: __m256) -> __m256 {
+    _mm256_mul_ps(_mm256_set1_ps(255.0), _mm256_sqrt_ps(value))
+}
+
+/// Pulls the 8 bit channel at `shift` out of each pixel as a float
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn unpack_channel_avx2(pixels: __m256i, shift: i32) -> __m256 {
+    let shifted = _mm256_srl_epi32(pixels, _mm_cvtsi32_si128(shift));
+    _mm256_cvtepi32_ps(_mm256_and_si256(shifted, _mm256_set1_epi32(0xFF)))
+}
+
+/// Eight pixel version of `blend_premultiplied`
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn blend_premultiplied_avx2(dest: __m256i, texel: __m256i) -> __m256i {
+    let sa = _mm256_div_ps(unpack_channel_avx2(texel, 24), _mm256_set1_ps(255.0));
+    let sr = srgb255_to_linear1_avx2(unpack_channel_avx2(texel, 16));
+    let sg = srgb255_to_linear1_avx2(unpack_channel_avx2(texel, 8));
+    let sb = srgb255_to_linear1_avx2(unpack_channel_avx2(texel, 0));
+
+    let dr = srgb255_to_linear1_avx2(unpack_channel_avx2(dest, 16));
+    let dg = srgb255_to_linear1_avx2(unpack_channel_avx2(dest, 8));
+    let db = srgb255_to_linear1_avx2(unpack_channel_avx2(dest, 0));
+
+    let inv_sa = _mm256_sub_ps(_mm256_set1_ps(1.0), sa);
+    let max_value = _mm256_set1_ps(255.0);
+    let r = _mm256_min_ps(
+        linear1_to_srgb255_avx2(_mm256_add_ps(_mm256_mul_ps(inv_sa, dr), sr)),
+        max_value,
+    );
+    let g = _mm256_min_ps(
+        linear1_to_srgb255_avx2(_mm256_add_ps(_mm256_mul_ps(inv_sa, dg), sg)),
+        max_value,
+    );
+    let b = _mm256_min_ps(
+        linear1_to_srgb255_avx2(_mm256_add_ps(_mm256_mul_ps(inv_sa, db), sb)),
+        max_value,
+    );
+
+    let half = _mm256_set1_ps(0.5);
+    let r = _mm256_cvttps_epi32(_mm256_add_ps(r, half));
+    let g = _mm256_cvttps_epi32(_mm256_add_ps(g, half));
+    let b = _mm256_cvttps_epi32(_mm256_add_ps(b, half));
+
+    _mm256_or_si256(
+        _mm256_or_si256(_mm256_slli_epi32(r, 16), _mm256_slli_epi32(g, 8)),
+        b,
+    )
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn blit_bitmap_avx2(buffer: &GameOffscreenBuffer, blit: &BitmapBlit) {
+    let inv_scale = _mm256_set1_ps(blit.inv_scale);
+    let lane_offsets = _mm256_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7);
+    let x_offset = _mm256_set1_epi32(blit.x_offset);
+    let bitmap_width = _mm256_set1_epi32(blit.bitmap.width as i32);
+
+    for y in blit.min_y..blit.max_y {
+        if let Some(source_row) = source_row_for(blit, y) {
+            let source_base = blit.bitmap.memory.as_ptr().add(source_row) as *const i32;
+            let mut dest = pixel_pointer(buffer, blit.min_x, y);
+            let mut x = blit.min_x;
+            while x + 8 <= blit.max_x {
+                let rel_x = _mm256_add_epi32(_mm256_set1_epi32(x - blit.origin_x), lane_offsets);
+                let source_x = _mm256_add_epi32(
+                    x_offset,
+                    _mm256_cvttps_epi32(_mm256_mul_ps(_mm256_cvtepi32_ps(rel_x), inv_scale)),
+                );
+                let write_mask = _mm256_cmpgt_epi32(bitmap_width, source_x);
+
+                // NOTE: Lanes outside the bitmap are masked off, so the gather
+                // never reads past the end of the row
+                let texel = _mm256_mask_i32gather_epi32(
+                    _mm256_setzero_si256(),
+                    source_base,
+                    source_x,
+                    write_mask,
+                    4,
+                );
+                let original_dest = _mm256_loadu_si256(dest as *const __m256i);
+                let blended = blend_premultiplied_avx2(original_dest, texel);
+                let out = _mm256_or_si256(
+                    _mm256_and_si256(write_mask, blended),
+                    _mm256_andnot_si256(write_mask, original_dest),
+                );
+                _mm256_storeu_si256(dest as *mut __m256i, out);
+
+                dest = dest.add(8);
+                x += 8;
+            }
+            while x < blit.max_x {
+                blit_pixel_scalar(blit, source_row, x, dest);
+                dest = dest.add(1);
+                x += 1;
+            }
+        }
+    }
+}
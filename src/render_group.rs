@@ -0,0 +1,568 @@
+//! equivalent to handmade_render_group.h & handmade_render_group.cpp
+//!
+//! The game pushes render entries into a `RenderGroup` while it simulates, and
+//! `render_group_to_output` rasterizes them afterwards. Nothing is drawn until
+//! then, so entries can be sorted, batched or split into tiles without the
+//! game code knowing about it.
+
+use crate::asset::*;
+use crate::common::*;
+use crate::font::*;
+use crate::game_error::*;
+use crate::rasterizer::*;
+use std::any::Any;
+#[cfg(not(windows))]
+use std::ffi::c_void;
+#[cfg(windows)]
+use winapi::ctypes::c_void;
+
+pub enum RenderEntry<'a> {
+    Clear {
+        r: f32,
+        g: f32,
+        b: f32,
+    },
+    Rectangle {
+        min_x: f32,
+        min_y: f32,
+        max_x: f32,
+        max_y: f32,
+        r: f32,
+        g: f32,
+        b: f32,
+    },
+    Bitmap {
+        bitmap: &'a LoadedBitmap,
+        x: f32,
+        y: f32,
+        scale: f32,
+    },
+    Sprite {
+        bitmap: &'a LoadedBitmap,
+        x: f32,
+        y: f32,
+        align_x: i32,
+        align_y: i32,
+        frame_width: u32,
+        frame: u32,
+        scale: f32,
+    },
+    Glyph {
+        font: &'a LoadedFont,
+        glyph: FontGlyph,
+        x: f32,
+        y: f32,
+        scale: f32,
+    },
+}
+
+struct SortedRenderEntry<'a> {
+    sort_key: f32,
+    entry: RenderEntry<'a>,
+}
+
+/// Entries with a lower sort key are drawn first. Entries with equal sort
+/// keys are drawn in the order they were pushed.
+#[derive(Default)]
+pub struct RenderGroup<'a> {
+    entries: Vec<SortedRenderEntry<'a>>,
+}
+
+// NOTE: These approximate the sRGB curve with a gamma of 2, which is close
+// enough for blending and cheap to do in bulk.
+
+pub fn srgb255_to_linear1(value: f32) -> f32 {
+    let result = value / 255.0;
+    result * result
+}
+
+pub fn linear1_to_srgb255(value: f32) -> f32 {
+    255.0 * value.sqrt()
+}
+
+pub fn push_entry<'a>(render_group: &mut RenderGroup<'a>, sort_key: f32, entry: RenderEntry<'a>) {
+    render_group
+        .entries
+        .push(SortedRenderEntry { sort_key, entry });
+}
+
+/// Clears always sort before everything else in the group.
+pub fn push_clear(render_group: &mut RenderGroup, r: f32, g: f32, b: f32) {
+    push_entry(
+        render_group,
+        f32::NEG_INFINITY,
+        RenderEntry::Clear { r, g, b },
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn push_rectangle(
+    render_group: &mut RenderGroup,
+    sort_key: f32,
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+    r: f32,
+    g: f32,
+    b: f32,
+) {
+    push_entry(
+        render_group,
+        sort_key,
+        RenderEntry::Rectangle {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            r,
+            g,
+            b,
+        },
+    );
+}
+
+pub fn push_bitmap<'a>(
+    render_group: &mut RenderGroup<'a>,
+    sort_key: f32,
+    bitmap: &'a LoadedBitmap,
+    x: f32,
+    y: f32,
+    scale: f32,
+) {
+    push_entry(
+        render_group,
+        sort_key,
+        RenderEntry::Bitmap {
+            bitmap,
+            x,
+            y,
+            scale,
+        },
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn push_sprite<'a>(
+    render_group: &mut RenderGroup<'a>,
+    sort_key: f32,
+    bitmap: &'a LoadedBitmap,
+    x: f32,
+    y: f32,
+    align_x: i32,
+    align_y: i32,
+    frame_width: u32,
+    frame: u32,
+    scale: f32,
+) {
+    push_entry(
+        render_group,
+        sort_key,
+        RenderEntry::Sprite {
+            bitmap,
+            x,
+            y,
+            align_x,
+            align_y,
+            frame_width,
+            frame,
+            scale,
+        },
+    );
+}
+
+/// Pushes a glyph for every character of `text`, with the top left corner of
+/// the first line at (`x`, `y`). Returns where the pen ended up, so more
+/// text can be pushed right after it.
+#[allow(clippy::too_many_arguments)]
+pub fn push_text<'a>(
+    render_group: &mut RenderGroup<'a>,
+    sort_key: f32,
+    font: &'a LoadedFont,
+    x: f32,
+    y: f32,
+    scale: f32,
+    text: &str,
+) -> (f32, f32) {
+    let mut pen_x = x;
+    let mut pen_y = y;
+    let mut previous = None;
+    for c in text.chars() {
+        if c == '\n' {
+            pen_x = x;
+            pen_y += scale * font.line_height as f32;
+            previous = None;
+            continue;
+        }
+
+        if let Some(previous) = previous {
+            pen_x += scale * get_kerning(font, previous, c) as f32;
+        }
+        if let Some(glyph) = get_glyph(font, c) {
+            if glyph.width > 0 && glyph.height > 0 {
+                push_entry(
+                    render_group,
+                    sort_key,
+                    RenderEntry::Glyph {
+                        font,
+                        glyph,
+                        x: pen_x + scale * glyph.offset_x as f32,
+                        y: pen_y + scale * glyph.offset_y as f32,
+                        scale,
+                    },
+                );
+            }
+            pen_x += scale * glyph.advance as f32;
+        }
+        previous = Some(c);
+    }
+
+    (pen_x, pen_y)
+}
+
+/// A pixel rectangle in the output buffer, max exclusive
+#[derive(Clone, Copy)]
+pub struct ClipRect {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+/// Where and how the entries of a render group get rasterized. Nothing
+/// outside of `clip_rect` is touched.
+struct RenderTarget<'a> {
+    buffer: &'a GameOffscreenBuffer,
+    clip_rect: ClipRect,
+    rasterizer: Rasterizer,
+}
+
+/// One tile's worth of work for `tiled_render_group_to_output`
+struct TileRenderWork<'a> {
+    render_group: &'a RenderGroup<'a>,
+    target: RenderTarget<'a>,
+    /// Caught on the worker, to carry on with on the main thread
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+const TILE_COUNT_X: i32 = 4;
+const TILE_COUNT_Y: i32 = 4;
+
+fn sort_entries(render_group: &mut RenderGroup) {
+    // NOTE: sort_by is stable, so equal keys keep their push order
+    render_group.entries.sort_by(|a, b| {
+        a.sort_key
+            .partial_cmp(&b.sort_key)
+            .expect("render entry sort keys must not be NaN")
+    });
+}
+
+fn whole_buffer(buffer: &GameOffscreenBuffer) -> ClipRect {
+    ClipRect {
+        min_x: 0,
+        min_y: 0,
+        max_x: buffer.width,
+        max_y: buffer.height,
+    }
+}
+
+pub fn render_group_to_output(render_group: &mut RenderGroup, output_target: &GameOffscreenBuffer) {
+    render_group_to_output_using(render_group, output_target, best_rasterizer());
+}
+
+/// Like `render_group_to_output`, but with a specific rasterizer path instead
+/// of the fastest one the CPU supports. Panics if the CPU can't run it.
+pub fn render_group_to_output_using(
+    render_group: &mut RenderGroup,
+    output_target: &GameOffscreenBuffer,
+    rasterizer: Rasterizer,
+) {
+    timed_block!("render_group_to_output");
+    assert!(
+        is_rasterizer_supported(rasterizer),
+        "{:?} rasterizer is not supported on this CPU",
+        rasterizer
+    );
+
+    sort_entries(render_group);
+    render_sorted_entries(
+        render_group,
+        &RenderTarget {
+            buffer: output_target,
+            clip_rect: whole_buffer(output_target),
+            rasterizer,
+        },
+    );
+}
+
+/// Splits the output into tiles and rasterizes them in parallel on `queue`.
+/// Every tile is clipped to its own pixels, so the result is identical to
+/// `render_group_to_output`.
+pub fn tiled_render_group_to_output(
+    queue: *mut PlatformWorkQueue,
+    add_entry: PlatformAddEntry,
+    complete_all_work: PlatformCompleteAllWork,
+    render_group: &mut RenderGroup,
+    output_target: &GameOffscreenBuffer,
+) {
+    timed_block!("tiled_render_group_to_output");
+    sort_entries(render_group);
+
+    let rasterizer = best_rasterizer();
+    let mut work = Vec::with_capacity((TILE_COUNT_X * TILE_COUNT_Y) as usize);
+    for tile_y in 0..TILE_COUNT_Y {
+        for tile_x in 0..TILE_COUNT_X {
+            work.push(TileRenderWork {
+                render_group,
+                target: RenderTarget {
+                    buffer: output_target,
+                    clip_rect: ClipRect {
+                        min_x: tile_x * output_target.width / TILE_COUNT_X,
+                        min_y: tile_y * output_target.height / TILE_COUNT_Y,
+                        max_x: (tile_x + 1) * output_target.width / TILE_COUNT_X,
+                        max_y: (tile_y + 1) * output_target.height / TILE_COUNT_Y,
+                    },
+                    rasterizer,
+                },
+                panic: None,
+            });
+        }
+    }
+
+    // NOTE: `work` must not move or grow from here until all the work is done
+    unsafe {
+        for tile in work.iter_mut() {
+            add_entry(
+                queue,
+                do_tile_render_work,
+                tile as *mut TileRenderWork as *mut _,
+            );
+        }
+        complete_all_work(queue);
+    }
+    for tile in work.iter_mut() {
+        resume_worker_panic(tile.panic.take());
+    }
+}
+
+unsafe extern "C" fn do_tile_render_work(_queue: *mut PlatformWorkQueue, data: *mut c_void) {
+    timed_block!("render tile");
+    let work = &mut *(data as *mut TileRenderWork);
+    work.panic = catch_worker_panic(|| render_sorted_entries(work.render_group, &work.target));
+}
+
+fn render_sorted_entries(render_group: &RenderGroup, target: &RenderTarget) {
+    for sorted in &render_group.entries {
+        match sorted.entry {
+            RenderEntry::Clear { r, g, b } => draw_rectangle(
+                target,
+                0.0,
+                0.0,
+                target.buffer.width as f32,
+                target.buffer.height as f32,
+                r,
+                g,
+                b,
+            ),
+            RenderEntry::Rectangle {
+                min_x,
+                min_y,
+                max_x,
+                max_y,
+                r,
+                g,
+                b,
+            } => draw_rectangle(target, min_x, min_y, max_x, max_y, r, g, b),
+            RenderEntry::Bitmap {
+                bitmap,
+                x,
+                y,
+                scale,
+            } => draw_image(
+                target,
+                bitmap,
+                x,
+                y,
+                0,
+                0,
+                bitmap.width,
+                bitmap.height,
+                scale,
+            ),
+            RenderEntry::Sprite {
+                bitmap,
+                x,
+                y,
+                align_x,
+                align_y,
+                frame_width,
+                frame,
+                scale,
+            } => draw_animated_image(
+                target,
+                bitmap,
+                x,
+                y,
+                align_x,
+                align_y,
+                frame_width,
+                frame,
+                scale,
+            ),
+            RenderEntry::Glyph {
+                font,
+                glyph,
+                x,
+                y,
+                scale,
+            } => draw_image(
+                target,
+                &font.atlas,
+                x,
+                y,
+                glyph.x as i32,
+                glyph.y as i32,
+                glyph.width,
+                glyph.height,
+                scale,
+            ),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_rectangle(
+    target: &RenderTarget,
+    real_min_x: f32,
+    real_min_y: f32,
+    real_max_x: f32,
+    real_max_y: f32,
+    r: f32,
+    g: f32,
+    b: f32,
+) {
+    // TODO: Floating point color
+
+    let clip_rect = target.clip_rect;
+    let mut min_x = real_min_x.round() as i32;
+    let mut min_y = real_min_y.round() as i32;
+    let mut max_x = real_max_x.round() as i32;
+    let mut max_y = real_max_y.round() as i32;
+
+    if min_x < clip_rect.min_x {
+        min_x = clip_rect.min_x
+    };
+
+    if min_y < clip_rect.min_y {
+        min_y = clip_rect.min_y
+    };
+
+    if max_x > clip_rect.max_x {
+        max_x = clip_rect.max_x
+    };
+
+    if max_y > clip_rect.max_y {
+        max_y = clip_rect.max_y
+    };
+
+    let color = ((r * 255.0).round() as u32) << 16
+        | ((g * 255.0).round() as u32) << 8
+        | (b * 255.0).round() as u32;
+
+    fill_rectangle(
+        target.rasterizer,
+        target.buffer,
+        min_x,
+        min_y,
+        max_x,
+        max_y,
+        color,
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_animated_image(
+    target: &RenderTarget,
+    bitmap: &LoadedBitmap,
+    real_x: f32,
+    real_y: f32,
+    align_x: i32,
+    align_y: i32,
+    frame_width: u32,
+    frame: u32,
+    scale: f32,
+) {
+    let x = real_x - scale * align_x as f32;
+    let y = real_y - scale * align_y as f32;
+
+    draw_image(
+        target,
+        bitmap,
+        x,
+        y,
+        (frame * frame_width) as i32,
+        0,
+        frame_width,
+        bitmap.height,
+        scale,
+    )
+}
+
+/// Draws the `width` by `height` part of `bitmap` starting at (`x_offset`,
+/// `y_offset`), with its top left corner at (`real_x`, `real_y`). Each bitmap
+/// pixel covers `scale` buffer pixels in each direction (nearest neighbor
+/// sampling).
+#[allow(clippy::too_many_arguments)]
+fn draw_image(
+    target: &RenderTarget,
+    bitmap: &LoadedBitmap,
+    real_x: f32,
+    real_y: f32,
+    x_offset: i32,
+    y_offset: i32,
+    width: u32,
+    height: u32,
+    scale: f32,
+) {
+    let clip_rect = target.clip_rect;
+    let origin_x = real_x.round() as i32;
+    let origin_y = real_y.round() as i32;
+
+    let mut min_x = origin_x;
+    let mut min_y = origin_y;
+    let mut max_x = origin_x + (scale * width as f32).round() as i32;
+    let mut max_y = origin_y + (scale * height as f32).round() as i32;
+
+    if min_x < clip_rect.min_x {
+        min_x = clip_rect.min_x;
+    }
+
+    if min_y < clip_rect.min_y {
+        min_y = clip_rect.min_y;
+    }
+
+    if max_x > clip_rect.max_x {
+        max_x = clip_rect.max_x;
+    }
+
+    if max_y > clip_rect.max_y {
+        max_y = clip_rect.max_y;
+    }
+
+    blit_bitmap(
+        target.rasterizer,
+        target.buffer,
+        &BitmapBlit {
+            bitmap,
+            origin_x,
+            origin_y,
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            x_offset,
+            y_offset,
+            inv_scale: 1.0 / scale,
+        },
+    );
+}
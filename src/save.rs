@@ -0,0 +1,646 @@
+//! Save games: the parts of `State` worth keeping between runs, in versioned
+//! files the platform layer reads and writes for us
+//!
+//! A save is a header and then RIFF style chunks, each a tag, a size and the
+//! data. Loading skips tags it doesn't know and ignores anything in a chunk
+//! past the fields it reads, so an older build still loads what it
+//! understands of a newer save as long as new fields go at the end of a chunk
+//! and anything else gets a new tag. Older saves are brought up to date by
+//! `SAVE_MIGRATIONS`.
+//!
+//! There are no entities yet, they get a chunk of their own when there are.
+
+use crate::{asset::*, common::*, tile::*, State, MAX_CAMERA_ZOOM, MIN_CAMERA_ZOOM};
+use core::slice;
+use std::io;
+
+pub const SAVE_SLOT_COUNT: usize = 4;
+
+const SAVE_MAGIC: [u8; 8] = *b"WSKSAVE\0";
+/// Bump this and add a migration whenever a chunk changes in a way older
+/// saves have to be converted for
+const SAVE_VERSION: u32 = 1;
+/// Run in order on a save from version `index + 1` to bring it up to
+/// `SAVE_VERSION`
+const SAVE_MIGRATIONS: [fn(&mut SavedGame); SAVE_VERSION as usize - 1] = [];
+
+const TILE_MAP_TAG: [u8; 4] = *b"TMAP";
+const PLAYER_TAG: [u8; 4] = *b"PLYR";
+const CAMERA_TAG: [u8; 4] = *b"CAMR";
+
+/// Bigger tile maps are refused rather than allocated, a corrupt size
+/// shouldn't be able to take all the memory there is
+const MAX_SAVED_TILE_CHUNK_COUNT: u32 = 1 << 20;
+const MAX_SAVED_CHUNK_SHIFT: u32 = 8;
+
+/// A tile map as it was saved. It is copied into the live one, which keeps its
+/// tiles in the world arena.
+pub struct SavedTileMap {
+    pub chunk_shift: u32,
+    pub tile_side_in_meters: f32,
+    pub tile_chunk_count_x: u32,
+    pub tile_chunk_count_y: u32,
+    pub tile_chunk_count_z: u32,
+    /// The index and tiles of every chunk that had tiles in it
+    pub chunks: Vec<(u32, Vec<u32>)>,
+}
+
+pub struct SavedPlayer {
+    pub p: TileMapPosition,
+    pub walk_frame: u32,
+}
+
+pub struct SavedCamera {
+    pub p: TileMapPosition,
+    pub zoom: f32,
+}
+
+/// What a save held. Chunks the save didn't have are None, and the game
+/// keeps what it has for those.
+#[derive(Default)]
+pub struct SavedGame {
+    /// The version it was saved with, from before any migrations
+    pub version: u32,
+    pub tile_map: Option<SavedTileMap>,
+    pub player: Option<SavedPlayer>,
+    pub camera: Option<SavedCamera>,
+}
+
+fn invalid_save(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn get_save_file_name(slot: usize) -> String {
+    format!("saves/slot_{}.sav", slot + 1)
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_f32(bytes: &mut Vec<u8>, value: f32) {
+    write_u32(bytes, value.to_bits());
+}
+
+/// Infinities and NaNs are refused, nothing saved should be either
+fn read_f32(reader: &mut &[u8]) -> io::Result<f32> {
+    let value = f32::from_bits(read_u32(reader)?);
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(invalid_save(format!("{} is not a finite number", value)))
+    }
+}
+
+/// Starts a chunk and returns where its size goes, for `end_chunk`
+fn begin_chunk(bytes: &mut Vec<u8>, tag: [u8; 4]) -> usize {
+    bytes.extend_from_slice(&tag);
+    write_u32(bytes, 0);
+    bytes.len() - 4
+}
+
+fn end_chunk(bytes: &mut [u8], size_offset: usize) {
+    let size = (bytes.len() - size_offset - 4) as u32;
+    bytes[size_offset..size_offset + 4].copy_from_slice(&size.to_le_bytes());
+}
+
+fn write_position(bytes: &mut Vec<u8>, p: &TileMapPosition) {
+    write_u32(bytes, p.abs_tile_x);
+    write_u32(bytes, p.abs_tile_y);
+    write_u32(bytes, p.abs_tile_z);
+    write_f32(bytes, p.offset_x);
+    write_f32(bytes, p.offset_y);
+}
+
+fn read_position(reader: &mut &[u8]) -> io::Result<TileMapPosition> {
+    Ok(TileMapPosition {
+        abs_tile_x: read_u32(reader)?,
+        abs_tile_y: read_u32(reader)?,
+        abs_tile_z: read_u32(reader)?,
+        offset_x: read_f32(reader)?,
+        offset_y: read_f32(reader)?,
+    })
+}
+
+/// Only the chunks with tiles in them are written, with their index
+fn write_tile_map(bytes: &mut Vec<u8>, tile_map: &TileMap) {
+    write_u32(bytes, tile_map.chunk_shift);
+    write_f32(bytes, tile_map.tile_side_in_meters);
+    write_u32(bytes, tile_map.tile_chunk_count_x);
+    write_u32(bytes, tile_map.tile_chunk_count_y);
+    write_u32(bytes, tile_map.tile_chunk_count_z);
+
+    write_u32(bytes, get_loaded_chunk_count(tile_map) as u32);
+    for chunk_index in 0..get_tile_chunk_count(tile_map) {
+        if let Some(tiles) = get_chunk_tiles(tile_map, chunk_index) {
+            write_u32(bytes, chunk_index as u32);
+            for &tile in tiles {
+                write_u32(bytes, tile);
+            }
+        }
+    }
+}
+
+fn read_tile_map(reader: &mut &[u8]) -> io::Result<SavedTileMap> {
+    let chunk_shift = read_u32(reader)?;
+    if chunk_shift > MAX_SAVED_CHUNK_SHIFT {
+        return Err(invalid_save(format!(
+            "tile chunks of 2^{} tiles a side are too big",
+            chunk_shift
+        )));
+    }
+    let tile_side_in_meters = read_f32(reader)?;
+    if tile_side_in_meters <= 0.0 {
+        return Err(invalid_save(format!(
+            "tiles can't be {} meters a side",
+            tile_side_in_meters
+        )));
+    }
+    let tile_chunk_count_x = read_u32(reader)?;
+    let tile_chunk_count_y = read_u32(reader)?;
+    let tile_chunk_count_z = read_u32(reader)?;
+    let tile_chunk_count = tile_chunk_count_x
+        .checked_mul(tile_chunk_count_y)
+        .and_then(|count| count.checked_mul(tile_chunk_count_z))
+        .filter(|&count| count <= MAX_SAVED_TILE_CHUNK_COUNT)
+        .ok_or_else(|| {
+            invalid_save(format!(
+                "{} by {} by {} tile chunks are too many",
+                tile_chunk_count_x, tile_chunk_count_y, tile_chunk_count_z
+            ))
+        })?;
+
+    let chunk_dim = 1 << chunk_shift;
+    let mut tile_map = SavedTileMap {
+        chunk_shift,
+        tile_side_in_meters,
+        tile_chunk_count_x,
+        tile_chunk_count_y,
+        tile_chunk_count_z,
+        chunks: Vec::new(),
+    };
+
+    let loaded_chunk_count = read_u32(reader)?;
+    for _ in 0..loaded_chunk_count {
+        let chunk_index = read_u32(reader)?;
+        if chunk_index >= tile_chunk_count {
+            return Err(invalid_save(format!(
+                "tile chunk {} is outside the tile map",
+                chunk_index
+            )));
+        }
+        let tiles = (0..chunk_dim * chunk_dim)
+            .map(|_| read_u32(reader))
+            .collect::<io::Result<Vec<u32>>>()?;
+        tile_map.chunks.push((chunk_index, tiles));
+    }
+
+    Ok(tile_map)
+}
+
+pub fn encode_save(game_state: &State, tile_map: &TileMap) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&SAVE_MAGIC);
+    write_u32(&mut bytes, SAVE_VERSION);
+
+    let chunk = begin_chunk(&mut bytes, TILE_MAP_TAG);
+    write_tile_map(&mut bytes, tile_map);
+    end_chunk(&mut bytes, chunk);
+
+    let chunk = begin_chunk(&mut bytes, PLAYER_TAG);
+    write_position(&mut bytes, &game_state.player_p);
+    write_u32(&mut bytes, game_state.character_walk_frame);
+    end_chunk(&mut bytes, chunk);
+
+    let chunk = begin_chunk(&mut bytes, CAMERA_TAG);
+    write_position(&mut bytes, &game_state.camera_p);
+    write_f32(&mut bytes, game_state.camera_zoom);
+    end_chunk(&mut bytes, chunk);
+
+    bytes
+}
+
+pub fn decode_save(bytes: &[u8]) -> io::Result<SavedGame> {
+    let mut reader = bytes;
+    if reader.len() < SAVE_MAGIC.len() || reader[..SAVE_MAGIC.len()] != SAVE_MAGIC {
+        return Err(invalid_save("not a save file".to_string()));
+    }
+    reader = &reader[SAVE_MAGIC.len()..];
+
+    let mut result = SavedGame {
+        version: read_u32(&mut reader)?,
+        ..SavedGame::default()
+    };
+    if result.version == 0 {
+        return Err(invalid_save("save version 0 does not exist".to_string()));
+    }
+
+    while !reader.is_empty() {
+        let tag = read_tag(&mut reader)?;
+        let size = read_u32(&mut reader)? as usize;
+        if size > reader.len() {
+            return Err(invalid_save(format!(
+                "{} chunk runs past the end of the save",
+                String::from_utf8_lossy(&tag)
+            )));
+        }
+        let (mut chunk, rest) = reader.split_at(size);
+        reader = rest;
+
+        let decoded = match tag {
+            TILE_MAP_TAG => read_tile_map(&mut chunk).map(|tile_map| {
+                result.tile_map = Some(tile_map);
+            }),
+            PLAYER_TAG => read_position(&mut chunk).and_then(|p| {
+                result.player = Some(SavedPlayer {
+                    p,
+                    walk_frame: read_u32(&mut chunk)?,
+                });
+                Ok(())
+            }),
+            CAMERA_TAG => read_position(&mut chunk).and_then(|p| {
+                result.camera = Some(SavedCamera {
+                    p,
+                    zoom: read_f32(&mut chunk)?.clamp(MIN_CAMERA_ZOOM, MAX_CAMERA_ZOOM),
+                });
+                Ok(())
+            }),
+            // NOTE: From a newer build, skip it
+            _ => Ok(()),
+        };
+        decoded
+            .map_err(|e| invalid_save(format!("{} chunk: {}", String::from_utf8_lossy(&tag), e)))?;
+    }
+
+    let first_migration = (result.version.min(SAVE_VERSION) - 1) as usize;
+    for migrate in &SAVE_MIGRATIONS[first_migration..] {
+        migrate(&mut result);
+    }
+
+    Ok(result)
+}
+
+/// Puts what the save held back into the game. A tile map of another shape
+/// than the live one is refused, before anything is changed.
+pub fn apply_saved_game(
+    game_state: &mut State,
+    tile_map: &mut TileMap,
+    saved_game: SavedGame,
+) -> io::Result<()> {
+    if let Some(saved_tile_map) = &saved_game.tile_map {
+        let is_same_shape = saved_tile_map.chunk_shift == tile_map.chunk_shift
+            && saved_tile_map.tile_chunk_count_x == tile_map.tile_chunk_count_x
+            && saved_tile_map.tile_chunk_count_y == tile_map.tile_chunk_count_y
+            && saved_tile_map.tile_chunk_count_z == tile_map.tile_chunk_count_z;
+        if !is_same_shape {
+            return Err(invalid_save(format!(
+                "the save has {} by {} by {} tile chunks of 2^{} tiles a side, \
+                 the world has {} by {} by {} of 2^{}",
+                saved_tile_map.tile_chunk_count_x,
+                saved_tile_map.tile_chunk_count_y,
+                saved_tile_map.tile_chunk_count_z,
+                saved_tile_map.chunk_shift,
+                tile_map.tile_chunk_count_x,
+                tile_map.tile_chunk_count_y,
+                tile_map.tile_chunk_count_z,
+                tile_map.chunk_shift
+            )));
+        }
+    }
+
+    if let Some(saved_tile_map) = saved_game.tile_map {
+        // NOTE: The tiles are copied into the live map, in the world arena,
+        // rather than the map being replaced. Chunks the save has no tiles
+        // for keep theirs, all set to 0 which reads the same as none.
+        tile_map.tile_side_in_meters = saved_tile_map.tile_side_in_meters;
+        for chunk_index in 0..get_tile_chunk_count(tile_map) {
+            if get_chunk_tiles(tile_map, chunk_index).is_some() {
+                unsafe {
+                    get_or_push_chunk_tiles(tile_map, &mut game_state.world_arena, chunk_index)
+                        .fill(0);
+                }
+            }
+        }
+        for (chunk_index, tiles) in saved_tile_map.chunks {
+            unsafe {
+                get_or_push_chunk_tiles(
+                    tile_map,
+                    &mut game_state.world_arena,
+                    chunk_index as usize,
+                )
+                .copy_from_slice(&tiles);
+            }
+        }
+    }
+
+    if let Some(player) = saved_game.player {
+        game_state.player_p = player.p;
+        game_state.character_walk_frame = player.walk_frame % game_state.character_image.frames;
+    }
+    if let Some(camera) = saved_game.camera {
+        game_state.camera_p = camera.p;
+        game_state.camera_zoom = camera.zoom;
+    }
+    game_state.has_move_target = false;
+
+    Ok(())
+}
+
+/// # Safety
+///
+/// The platform functions in `memory` must be valid, or None.
+pub unsafe fn save_game(
+    memory: &GameMemory,
+    game_state: &State,
+    tile_map: &TileMap,
+    slot: usize,
+) -> io::Result<()> {
+    let write_entire_file = memory
+        .platform_write_entire_file
+        .ok_or_else(|| io::Error::other("the platform can't write files"))?;
+
+    let name = get_save_file_name(slot);
+    let bytes = encode_save(game_state, tile_map);
+    if write_entire_file(name.as_ptr(), name.len(), bytes.as_ptr(), bytes.len()) {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("could not write {}", name)))
+    }
+}
+
+/// None if there is no save in the slot
+///
+/// # Safety
+///
+/// The platform functions in `memory` must be valid, or None.
+pub unsafe fn load_game(memory: &GameMemory, slot: usize) -> io::Result<Option<SavedGame>> {
+    let (read_entire_file, free_file_memory) = match (
+        memory.platform_read_entire_file,
+        memory.platform_free_file_memory,
+    ) {
+        (Some(read_entire_file), Some(free_file_memory)) => (read_entire_file, free_file_memory),
+        _ => return Err(io::Error::other("the platform can't read files")),
+    };
+
+    let name = get_save_file_name(slot);
+    let file = read_entire_file(name.as_ptr(), name.len());
+    if file.contents.is_null() {
+        return Ok(None);
+    }
+
+    let result = decode_save(slice::from_raw_parts(file.contents, file.size));
+    free_file_memory(file);
+    result.map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::zeroed;
+
+    const TEST_TILES: [u32; 16] = [1, 2, 1, 1, 1, 2, 2, 1, 1, 1, 1, 1, 2, 1, 1, 3];
+
+    /// A 2 by 2 by 1 tile map of 4 by 4 tile chunks, with tiles in chunk 3.
+    /// The boxes keep the relative pointers where they were set.
+    unsafe fn make_test_tile_map(storage: &mut Vec<u8>) -> (Box<MemoryArena>, Box<TileMap>) {
+        let mut arena: Box<MemoryArena> = Box::new(zeroed());
+        initialize_arena(&mut arena, "test", storage.len(), storage.as_mut_ptr());
+        let mut tile_map: Box<TileMap> = Box::new(zeroed());
+        tile_map.chunk_shift = 2;
+        tile_map.chunk_mask = 3;
+        tile_map.chunk_dim = 4;
+        tile_map.tile_side_in_meters = 1.4;
+        tile_map.tile_chunk_count_x = 2;
+        tile_map.tile_chunk_count_y = 2;
+        tile_map.tile_chunk_count_z = 1;
+        push_tile_chunks(&mut tile_map, &mut *arena);
+        get_or_push_chunk_tiles(&mut tile_map, &mut *arena, 3).copy_from_slice(&TEST_TILES);
+        (arena, tile_map)
+    }
+
+    fn make_test_save(version: u32, chunks: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = SAVE_MAGIC.to_vec();
+        write_u32(&mut bytes, version);
+        for (tag, data) in chunks {
+            let chunk = begin_chunk(&mut bytes, *tag);
+            bytes.extend_from_slice(data);
+            end_chunk(&mut bytes, chunk);
+        }
+        bytes
+    }
+
+    fn make_position_bytes(abs_tile_x: u32, offset_x: f32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_position(
+            &mut bytes,
+            &TileMapPosition {
+                abs_tile_x,
+                abs_tile_y: 7,
+                abs_tile_z: 0,
+                offset_x,
+                offset_y: -0.25,
+            },
+        );
+        bytes
+    }
+
+    fn make_camera_bytes(zoom: f32) -> Vec<u8> {
+        let mut bytes = make_position_bytes(17, 0.0);
+        write_f32(&mut bytes, zoom);
+        bytes
+    }
+
+    fn make_tile_map_bytes(
+        chunk_shift: u32,
+        tile_side_in_meters: f32,
+        counts: [u32; 3],
+        chunks: &[u32],
+    ) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_u32(&mut bytes, chunk_shift);
+        write_f32(&mut bytes, tile_side_in_meters);
+        for &count in &counts {
+            write_u32(&mut bytes, count);
+        }
+        write_u32(&mut bytes, chunks.len() as u32);
+        for &chunk_index in chunks {
+            write_u32(&mut bytes, chunk_index);
+            for tile in 0..(1 << (2 * chunk_shift)) {
+                write_u32(&mut bytes, tile);
+            }
+        }
+        bytes
+    }
+
+    fn get_decode_error(bytes: &[u8]) -> String {
+        match decode_save(bytes) {
+            Ok(_) => panic!("the save decoded"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn tile_map_round_trip() {
+        let mut storage = vec![0u8; 4096];
+        let (_arena, tile_map) = unsafe { make_test_tile_map(&mut storage) };
+        let mut bytes = Vec::new();
+        write_tile_map(&mut bytes, &tile_map);
+
+        let saved_tile_map = read_tile_map(&mut &bytes[..]).unwrap();
+        assert_eq!(saved_tile_map.chunk_shift, 2);
+        assert_eq!(saved_tile_map.tile_side_in_meters, 1.4);
+        assert_eq!(
+            (
+                saved_tile_map.tile_chunk_count_x,
+                saved_tile_map.tile_chunk_count_y,
+                saved_tile_map.tile_chunk_count_z
+            ),
+            (2, 2, 1)
+        );
+        assert_eq!(saved_tile_map.chunks, vec![(3, TEST_TILES.to_vec())]);
+    }
+
+    #[test]
+    fn decodes_every_chunk() {
+        let mut player = make_position_bytes(33, 0.5);
+        write_u32(&mut player, 2);
+        let bytes = make_test_save(
+            SAVE_VERSION,
+            &[
+                (TILE_MAP_TAG, make_tile_map_bytes(1, 2.0, [1, 1, 1], &[0])),
+                (PLAYER_TAG, player),
+                (CAMERA_TAG, make_camera_bytes(1.5)),
+            ],
+        );
+
+        let saved_game = decode_save(&bytes).unwrap();
+        assert_eq!(saved_game.version, SAVE_VERSION);
+        let tile_map = saved_game.tile_map.unwrap();
+        assert_eq!(tile_map.chunks, vec![(0, vec![0, 1, 2, 3])]);
+        let player = saved_game.player.unwrap();
+        assert_eq!((player.p.abs_tile_x, player.p.abs_tile_y), (33, 7));
+        assert_eq!((player.p.offset_x, player.p.offset_y), (0.5, -0.25));
+        assert_eq!(player.walk_frame, 2);
+        let camera = saved_game.camera.unwrap();
+        assert_eq!(camera.p.abs_tile_x, 17);
+        assert_eq!(camera.zoom, 1.5);
+    }
+
+    #[test]
+    fn missing_chunks_are_none() {
+        let saved_game = decode_save(&make_test_save(SAVE_VERSION, &[])).unwrap();
+        assert!(saved_game.tile_map.is_none());
+        assert!(saved_game.player.is_none());
+        assert!(saved_game.camera.is_none());
+    }
+
+    #[test]
+    fn older_versions_are_migrated() {
+        for version in 1..=SAVE_VERSION {
+            let bytes = make_test_save(version, &[(CAMERA_TAG, make_camera_bytes(1.0))]);
+            let saved_game = decode_save(&bytes).unwrap();
+            assert_eq!(saved_game.version, version);
+            assert!(saved_game.camera.is_some());
+        }
+    }
+
+    #[test]
+    fn newer_saves_skip_unknown_chunks_and_extra_fields() {
+        let mut camera = make_camera_bytes(2.0);
+        camera.extend_from_slice(&[0xff; 12]);
+        let bytes = make_test_save(
+            SAVE_VERSION + 1,
+            &[(*b"XTRA", vec![1, 2, 3]), (CAMERA_TAG, camera)],
+        );
+
+        let saved_game = decode_save(&bytes).unwrap();
+        assert_eq!(saved_game.version, SAVE_VERSION + 1);
+        assert_eq!(saved_game.camera.unwrap().zoom, 2.0);
+    }
+
+    #[test]
+    fn clamps_the_camera_zoom() {
+        let bytes = make_test_save(SAVE_VERSION, &[(CAMERA_TAG, make_camera_bytes(1000.0))]);
+        assert_eq!(
+            decode_save(&bytes).unwrap().camera.unwrap().zoom,
+            MAX_CAMERA_ZOOM
+        );
+        let bytes = make_test_save(SAVE_VERSION, &[(CAMERA_TAG, make_camera_bytes(0.0))]);
+        assert_eq!(
+            decode_save(&bytes).unwrap().camera.unwrap().zoom,
+            MIN_CAMERA_ZOOM
+        );
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(get_decode_error(b"WSK").contains("not a save file"));
+        assert!(get_decode_error(b"NOTASAVE\x01\0\0\0").contains("not a save file"));
+        assert!(get_decode_error(&make_test_save(0, &[])).contains("version 0"));
+
+        let mut bytes = SAVE_MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        assert!(decode_save(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_chunks() {
+        let mut bytes = make_test_save(SAVE_VERSION, &[(CAMERA_TAG, make_camera_bytes(1.0))]);
+        bytes.truncate(bytes.len() - 1);
+        assert!(get_decode_error(&bytes).contains("CAMR chunk runs past the end"));
+
+        // NOTE: A chunk header cut short
+        let mut bytes = make_test_save(SAVE_VERSION, &[]);
+        bytes.extend_from_slice(b"PLY");
+        assert!(decode_save(&bytes).is_err());
+
+        // NOTE: A whole chunk, without all the fields in it
+        let player = make_position_bytes(1, 0.0);
+        let bytes = make_test_save(SAVE_VERSION, &[(PLAYER_TAG, player)]);
+        assert!(get_decode_error(&bytes).contains("PLYR chunk"));
+
+        let mut tile_map = make_tile_map_bytes(1, 1.0, [1, 1, 1], &[0]);
+        tile_map.truncate(tile_map.len() - 4);
+        let bytes = make_test_save(SAVE_VERSION, &[(TILE_MAP_TAG, tile_map)]);
+        assert!(get_decode_error(&bytes).contains("TMAP chunk"));
+    }
+
+    #[test]
+    fn rejects_non_finite_numbers() {
+        for &value in &[f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let bytes = make_test_save(SAVE_VERSION, &[(CAMERA_TAG, make_camera_bytes(value))]);
+            assert!(get_decode_error(&bytes).contains("not a finite number"));
+            let bytes =
+                make_test_save(SAVE_VERSION, &[(PLAYER_TAG, make_position_bytes(1, value))]);
+            assert!(get_decode_error(&bytes).contains("not a finite number"));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_tile_maps() {
+        let bad_tile_maps = [
+            (make_tile_map_bytes(1, 0.0, [1, 1, 1], &[]), "meters a side"),
+            (
+                make_tile_map_bytes(1, -1.0, [1, 1, 1], &[]),
+                "meters a side",
+            ),
+            (
+                make_tile_map_bytes(MAX_SAVED_CHUNK_SHIFT + 1, 1.0, [1, 1, 1], &[]),
+                "too big",
+            ),
+            (
+                make_tile_map_bytes(1, 1.0, [1 << 16, 1 << 16, 2], &[]),
+                "too many",
+            ),
+            (
+                make_tile_map_bytes(1, 1.0, [2, 1, 1], &[2]),
+                "outside the tile map",
+            ),
+        ];
+        for (tile_map, message) in bad_tile_maps.iter() {
+            let bytes = make_test_save(SAVE_VERSION, &[(TILE_MAP_TAG, tile_map.clone())]);
+            let error = get_decode_error(&bytes);
+            assert!(error.contains(message), "{} should say {}", error, message);
+        }
+    }
+}
@@ -1,6 +1,11 @@
+use crate::common::*;
+use core::slice;
+
 pub struct TileMapDifference {
     pub dx: f32,
     pub dy: f32,
+    // TODO: Use this once the camera follows the player between floors
+    #[allow(dead_code)]
     pub dz: f32,
 }
 
@@ -30,7 +35,8 @@ pub struct TileChunkPosition {
 
 pub struct TileChunk {
     // TODO: Real structure for a tile
-    pub tiles: Vec<u32>,
+    /// `chunk_dim` squared tiles in the world arena, null until one is set
+    pub tiles: RelativePointer<u32>,
 }
 
 pub struct TileMap {
@@ -44,33 +50,66 @@ pub struct TileMap {
     pub tile_chunk_count_y: u32,
     pub tile_chunk_count_z: u32,
 
-    pub tile_chunks: Vec<TileChunk>,
+    /// In the world arena, see `get_tile_chunk_count`
+    pub tile_chunks: RelativePointer<TileChunk>,
 }
 
-fn get_tile_chunk(
-    tile_map: &TileMap,
-    tile_chunk_x: u32,
-    tile_chunk_y: u32,
-    tile_chunk_z: u32,
-) -> Option<&TileChunk> {
-    if let Some(index) = get_chunk_index(tile_map, tile_chunk_x, tile_chunk_y, tile_chunk_z) {
-        tile_map.tile_chunks.get(index)
-    } else {
+pub fn get_tile_chunk_count(tile_map: &TileMap) -> usize {
+    (tile_map.tile_chunk_count_x * tile_map.tile_chunk_count_y * tile_map.tile_chunk_count_z)
+        as usize
+}
+
+/// Pushes the chunks into `arena`, with no tiles in them yet. The counts and
+/// chunk shift have to be set first.
+///
+/// # Safety
+///
+/// `arena` must point to an initialized arena, and `tile_map` must stay where
+/// it is, like everything else with a `RelativePointer` in it.
+pub unsafe fn push_tile_chunks(tile_map: &mut TileMap, arena: *mut MemoryArena) {
+    let tile_chunk_count = get_tile_chunk_count(tile_map);
+    let tile_chunks = push_array::<TileChunk>(arena, tile_chunk_count, "tile chunks");
+    tile_chunks.write_bytes(0, tile_chunk_count);
+    set_relative_pointer(&mut tile_map.tile_chunks, tile_chunks);
+}
+
+fn get_tile_chunk_by_index(tile_map: &TileMap, chunk_index: usize) -> &TileChunk {
+    assert!(chunk_index < get_tile_chunk_count(tile_map));
+    unsafe { &*get_relative_pointer(&tile_map.tile_chunks).add(chunk_index) }
+}
+
+/// The tiles of the chunk at `chunk_index`, None if none have been set
+pub fn get_chunk_tiles(tile_map: &TileMap, chunk_index: usize) -> Option<&[u32]> {
+    let tiles = get_relative_pointer(&get_tile_chunk_by_index(tile_map, chunk_index).tiles);
+    if tiles.is_null() {
         None
+    } else {
+        let tile_count = (tile_map.chunk_dim * tile_map.chunk_dim) as usize;
+        Some(unsafe { slice::from_raw_parts(tiles, tile_count) })
     }
 }
 
-fn get_tile_chunk_mut(
+/// The tiles of the chunk at `chunk_index`, pushed into `arena` and set to 1
+/// if there weren't any yet
+///
+/// # Safety
+///
+/// `arena` must point to an initialized arena in the same storage block as
+/// the tile map.
+pub unsafe fn get_or_push_chunk_tiles(
     tile_map: &mut TileMap,
-    tile_chunk_x: u32,
-    tile_chunk_y: u32,
-    tile_chunk_z: u32,
-) -> Option<&mut TileChunk> {
-    if let Some(index) = get_chunk_index(tile_map, tile_chunk_x, tile_chunk_y, tile_chunk_z) {
-        tile_map.tile_chunks.get_mut(index)
-    } else {
-        None
+    arena: *mut MemoryArena,
+    chunk_index: usize,
+) -> &mut [u32] {
+    assert!(chunk_index < get_tile_chunk_count(tile_map));
+    let tile_count = (tile_map.chunk_dim * tile_map.chunk_dim) as usize;
+    let tile_chunk = get_relative_pointer(&tile_map.tile_chunks).add(chunk_index);
+    if get_relative_pointer(&(*tile_chunk).tiles).is_null() {
+        let tiles = push_array::<u32>(arena, tile_count, "tiles");
+        slice::from_raw_parts_mut(tiles, tile_count).fill(1);
+        set_relative_pointer(&mut (*tile_chunk).tiles, tiles);
     }
+    slice::from_raw_parts_mut(get_relative_pointer(&(*tile_chunk).tiles), tile_count)
 }
 
 fn get_chunk_index(
@@ -95,15 +134,15 @@ fn get_chunk_index(
 
 fn get_tile_value_rel(
     tile_map: &TileMap,
-    tile_chunk: &TileChunk,
+    chunk_index: usize,
     tile_x: u32,
     tile_y: u32,
 ) -> u32 {
     debug_assert!(tile_x < tile_map.chunk_dim);
     debug_assert!(tile_y < tile_map.chunk_dim);
 
-    if let Some(value) = tile_chunk.tiles.get((tile_y * tile_map.chunk_dim + tile_x) as usize) {
-        *value
+    if let Some(tiles) = get_chunk_tiles(tile_map, chunk_index) {
+        tiles[(tile_y * tile_map.chunk_dim + tile_x) as usize]
     } else {
         0
     }
@@ -111,7 +150,7 @@ fn get_tile_value_rel(
 
 fn set_tile_value_for_chunk(
     chunk_dim: u32,
-    tile_chunk: &mut TileChunk,
+    tiles: &mut [u32],
     tile_x: u32,
     tile_y: u32,
     tile_value: u32,
@@ -119,7 +158,7 @@ fn set_tile_value_for_chunk(
     debug_assert!(tile_x < chunk_dim);
     debug_assert!(tile_y < chunk_dim);
 
-    tile_chunk.tiles[(tile_y * chunk_dim + tile_x) as usize] = tile_value
+    tiles[(tile_y * chunk_dim + tile_x) as usize] = tile_value
 }
 
 fn get_chunk_position_for(
@@ -144,7 +183,7 @@ pub fn get_tile_value_abs(
     abs_tile_z: u32,
 ) -> u32 {
     let chunk_pos = get_chunk_position_for(tile_map, abs_tile_x, abs_tile_y, abs_tile_z);
-    if let Some(tile_chunk) = get_tile_chunk(
+    if let Some(chunk_index) = get_chunk_index(
         tile_map,
         chunk_pos.tile_chunk_x,
         chunk_pos.tile_chunk_y,
@@ -152,7 +191,7 @@ pub fn get_tile_value_abs(
     ) {
         get_tile_value_rel(
             tile_map,
-            tile_chunk,
+            chunk_index,
             chunk_pos.rel_tile_x,
             chunk_pos.rel_tile_y,
         )
@@ -170,7 +209,12 @@ pub fn is_tile_map_point_empty(tile_map: &TileMap, pos: &TileMapPosition) -> boo
     [1, 3, 4].contains(&get_tile_value(tile_map, pos))
 }
 
-pub fn set_tile_value(
+/// # Safety
+///
+/// `arena` must point to an initialized arena in the same storage block as
+/// the tile map.
+pub unsafe fn set_tile_value(
+    arena: *mut MemoryArena,
     tile_map: &mut TileMap,
     abs_tile_x: u32,
     abs_tile_y: u32,
@@ -179,30 +223,31 @@ pub fn set_tile_value(
 ) {
     let chunk_pos = get_chunk_position_for(tile_map, abs_tile_x, abs_tile_y, abs_tile_z);
     let chunk_dim = tile_map.chunk_dim;
-    let tile_chunk = get_tile_chunk_mut(
+    let chunk_index = get_chunk_index(
         tile_map,
         chunk_pos.tile_chunk_x,
         chunk_pos.tile_chunk_y,
         chunk_pos.tile_chunk_z,
     )
     .expect("could not get tile_chunk");
-
-    if tile_chunk.tiles.is_empty() {
-        let tile_count = chunk_dim * chunk_dim;
-        for _ in 0..tile_count {
-            tile_chunk.tiles.push(1);
-        }
-    }
+    let tiles = get_or_push_chunk_tiles(tile_map, arena, chunk_index);
 
     set_tile_value_for_chunk(
         chunk_dim,
-        tile_chunk,
+        tiles,
         chunk_pos.rel_tile_x,
         chunk_pos.rel_tile_y,
         tile_value,
     );
 }
 
+/// Chunks that have had a tile set, the others have no tile storage yet
+pub fn get_loaded_chunk_count(tile_map: &TileMap) -> usize {
+    (0..get_tile_chunk_count(tile_map))
+        .filter(|&chunk_index| get_chunk_tiles(tile_map, chunk_index).is_some())
+        .count()
+}
+
 //
 // TODO: Do these really belong in more of a "positioning" or "geometry" file?
 //
@@ -232,6 +277,20 @@ pub fn recanonicalize_position(tile_map: &TileMap, pos: TileMapPosition) -> Tile
     result
 }
 
+/// Moves `pos` by (`dx`, `dy`) meters and recanonicalizes the result
+pub fn offset_position(
+    tile_map: &TileMap,
+    pos: &TileMapPosition,
+    dx: f32,
+    dy: f32,
+) -> TileMapPosition {
+    let mut result = pos.clone();
+    result.offset_x += dx;
+    result.offset_y += dy;
+
+    recanonicalize_position(tile_map, result)
+}
+
 pub fn are_on_same_tile(a: &TileMapPosition, b: &TileMapPosition) -> bool {
     a.abs_tile_x == b.abs_tile_x && a.abs_tile_y == b.abs_tile_y && a.abs_tile_z == b.abs_tile_z
 }